@@ -8,7 +8,7 @@ extern crate image;
 use test::Bencher;
 use nalgebra::*;
 use image::Rgb;
-use std::boxed::Box as StdBox;
+use std::sync::Arc;
 
 use rust_raytracer::objects::{Box, Intersectable};
 use rust_raytracer::material::Phong;
@@ -24,7 +24,7 @@ fn test_intersection_box_intersects(b: &mut Bencher) -> () {
     let _box = Box::new(Vector3::new(1., 1., 1.),
                         Isometry3::new(Vector3::new(0., 0., -1.),
                                        Vector3::new(0., 0. ,0.)),
-                        StdBox::new(mat));
+                        Arc::new(mat));
     let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
     b.iter(|| {
         ray_box(&ray, &_box);
@@ -40,7 +40,7 @@ fn test_intersection_box_no_intersects(b: &mut Bencher) -> () {
     let _box = Box::new(Vector3::new(1., 1., 1.),
                         Isometry3::new(Vector3::new(0., 0., 5.),
                                        Vector3::new(0., 0. ,0.)),
-                        StdBox::new(mat));
+                        Arc::new(mat));
     let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
     b.iter(|| {
         ray_box(&ray, &_box);
@@ -8,6 +8,7 @@ extern crate image;
 use test::Bencher;
 use nalgebra::*;
 use image::Rgb;
+use std::sync::Arc;
 
 use rust_raytracer::objects::{Face, Intersectable};
 use rust_raytracer::material::Phong;
@@ -20,7 +21,7 @@ fn test_intersection_face_intersects(b: &mut Bencher) -> () {
                          Rgb { data: [0., 0., 0.] },
                          Rgb { data: [0., 0., 0.] },
                          2.);
-    let face = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -1.), Vector3::new(0., 0. ,0.)), Box::new(mat));
+    let face = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -1.), Vector3::new(0., 0. ,0.)), Arc::new(mat));
     let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
     b.iter(|| {
         ray_face(&ray, &face);
@@ -33,7 +34,7 @@ fn test_intersection_face_no_intersects(b: &mut Bencher) -> () {
                          Rgb { data: [0., 0., 0.] },
                          Rgb { data: [0., 0., 0.] },
                          2.);
-    let face = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., 1.), Vector3::new(0., 0. ,0.)), Box::new(mat));
+    let face = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., 1.), Vector3::new(0., 0. ,0.)), Arc::new(mat));
     let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
     b.iter(|| {
         ray_face(&ray, &face);
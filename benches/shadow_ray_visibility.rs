@@ -0,0 +1,85 @@
+#![feature(test)]
+
+extern crate test;
+extern crate rust_raytracer;
+extern crate nalgebra;
+extern crate image;
+
+use test::{black_box, Bencher};
+use nalgebra::*;
+use image::Rgb;
+use std::boxed::Box as StdBox;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rust_raytracer::camera::Orthographic;
+use rust_raytracer::light::Light;
+use rust_raytracer::material::{LightMaterial, Phong};
+use rust_raytracer::objects::{Face, Intersectable, Object, Surface};
+use rust_raytracer::ray::Ray;
+use rust_raytracer::scene::Scene;
+
+fn scene() -> Scene {
+    let grey = Rgb { data: [0.6, 0.6, 0.6] };
+    let mat = Arc::new(Phong::new(grey, grey, grey, 2.));
+    let wall = Face::new(4., 4., Isometry3::new(Vector3::new(0., 0., -3.), Vector3::new(0., 0., 0.)), mat.clone());
+    // The light faces the wall
+    let light = Light::new(Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -2.), Vector3::new(PI, 0., 0.)), mat),
+                           LightMaterial::new(grey, grey));
+    let origin = Isometry3::new(Vector3::new(0., 0., 0.), Vector3::new(0., 0., 0.));
+    let camera = Orthographic::new((8, 8), (1., 1.), origin);
+    Scene::new(grey, vec!(Object::from_surface(Surface::from_face(wall)), Object::from_light(light)),
+               StdBox::new(camera))
+}
+
+/// Returns a point of the wall beside the light, its normal and the center of
+/// the light, which nothing hides from the point.
+fn wall_and_light(scene: &Scene) -> (Point3<f64>, Vector3<f64>, Point3<f64>) {
+    let wall = scene.intersects(Ray::new(Point3::new(1.5, 0., 0.), -Vector3::z())).unwrap();
+    (wall.position, wall.normal, scene.lights()[0].sample_on_face_at((0.5, 0.5), 0.))
+}
+
+/// Ray from the wall to the light, traced to find whether the light is what
+/// it hits first.
+fn shadow_ray(scene: &Scene) -> Ray {
+    let (position, normal, target) = wall_and_light(scene);
+    Ray::from_surface(position, normal, target - position, 0.)
+}
+
+#[bench]
+fn test_shadow_ray_visibility_by_equality(b: &mut Bencher) -> () {
+    let scene = scene();
+    let light = scene.lights()[0];
+    let ray = shadow_ray(&scene);
+    b.iter(|| {
+        let visible = match scene.intersects(ray.clone()) {
+            Some(inter) => inter.object == &Object::from_light(light.clone()),
+            None => false
+        };
+        black_box(visible);
+    });
+}
+
+#[bench]
+fn test_shadow_ray_visibility_by_id(b: &mut Bencher) -> () {
+    let scene = scene();
+    let (light_id, _) = scene.lights_with_ids()[0];
+    let ray = shadow_ray(&scene);
+    b.iter(|| {
+        let visible = match scene.intersects(ray.clone()) {
+            Some(inter) => inter.object_id == light_id,
+            None => false
+        };
+        black_box(visible);
+    });
+}
+
+#[bench]
+fn test_shadow_ray_occluded(b: &mut Bencher) -> () {
+    let scene = scene();
+    let (position, normal, target) = wall_and_light(&scene);
+    let ray = Ray::shadow(position, normal, target, 0.);
+    b.iter(|| {
+        black_box(scene.occluded(ray.clone()));
+    });
+}
@@ -0,0 +1,110 @@
+use nalgebra::*;
+
+/// A rigid transform keyframed over time.
+/// Translations are interpolated linearly and rotations spherically between the
+/// two keyframes surrounding the requested time. Outside of the keyframed
+/// interval, the first or last keyframe is held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedTransform {
+    keyframes: Vec<(f64, Isometry3<f64>)>
+}
+
+impl AnimatedTransform {
+    pub fn new(keyframes: Vec<(f64, Isometry3<f64>)>) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "An animated transform needs at least one keyframe");
+        let mut keyframes = keyframes;
+        keyframes.sort_by(|k1, k2| k1.0.partial_cmp(&k2.0).unwrap());
+        AnimatedTransform { keyframes: keyframes }
+    }
+
+    /// Convenience constructor for a transform moving from `start` at time `t0`
+    /// to `end` at time `t1`.
+    pub fn between(t0: f64, start: Isometry3<f64>, t1: f64, end: Isometry3<f64>) -> AnimatedTransform {
+        AnimatedTransform::new(vec!((t0, start), (t1, end)))
+    }
+
+    pub fn keyframes(&self) -> &[(f64, Isometry3<f64>)] {
+        &self.keyframes
+    }
+
+    /// Returns the animation moved by `transform`, applied after each
+    /// keyframe.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> AnimatedTransform {
+        AnimatedTransform { keyframes: self.keyframes.iter().map(|&(t, k)| (t, *transform * k)).collect() }
+    }
+
+    pub fn interpolate(&self, time: f64) -> Isometry3<f64> {
+        let (t_first, first) = self.keyframes[0];
+        let (t_last, last) = self.keyframes[self.keyframes.len() - 1];
+        if time <= t_first {
+            return first;
+        }
+        if time >= t_last {
+            return last;
+        }
+        let i = self.keyframes.iter().position(|k| k.0 > time).unwrap();
+        let (t0, k0) = self.keyframes[i - 1];
+        let (t1, k1) = self.keyframes[i];
+        interpolate_isometry(&k0, &k1, (time - t0) / (t1 - t0))
+    }
+}
+
+/// Interpolates between two isometries, `s` going from 0 (`a`) to 1 (`b`).
+pub fn interpolate_isometry(a: &Isometry3<f64>, b: &Isometry3<f64>, s: f64) -> Isometry3<f64> {
+    let translation = a.translation + (b.translation - a.translation) * s;
+    // Rotating by a fraction of the relative rotation is a slerp
+    let delta = a.rotation.inverse().unwrap() * b.rotation;
+    let rotation = a.rotation * Rotation3::new(delta.rotation() * s);
+    Isometry3::from_rotation_matrix(translation, rotation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::{One, Zero};
+    use std::f64::consts::PI;
+
+    fn test_animation() -> AnimatedTransform {
+        AnimatedTransform::between(0., Isometry3::one(),
+                                   1., Isometry3::new(Vector3::new(2., 0., 0.), Vector3::y() * (PI / 2.)))
+    }
+
+    #[test]
+    fn test_interpolate_keyframes() {
+        let a = test_animation();
+        assert!(a.interpolate(0.).approx_eq(&Isometry3::one()));
+        assert!(a.interpolate(1.).approx_eq(&Isometry3::new(Vector3::new(2., 0., 0.), Vector3::y() * (PI / 2.))));
+    }
+
+    #[test]
+    fn test_interpolate_outside_range() {
+        let a = test_animation();
+        assert!(a.interpolate(-1.).approx_eq(&a.interpolate(0.)));
+        assert!(a.interpolate(5.).approx_eq(&a.interpolate(1.)));
+    }
+
+    #[test]
+    fn test_interpolate_midpoint() {
+        let a = test_animation();
+        let m = a.interpolate(0.5);
+        assert!(m.translation.approx_eq(&Vector3::new(1., 0., 0.)));
+        assert!(m.rotation.approx_eq(&Rotation3::new(Vector3::y() * (PI / 4.))));
+    }
+
+    #[test]
+    fn test_transformed() {
+        let parent = Isometry3::new(Vector3::new(0., 0., -3.), Vector3::x() * PI);
+        let a = test_animation().transformed(&parent);
+        for &t in [0., 0.3, 1.].iter() {
+            assert!(a.interpolate(t).approx_eq(&(parent * test_animation().interpolate(t))));
+        }
+    }
+
+    #[test]
+    fn test_keyframes_sorted() {
+        let a = AnimatedTransform::new(vec!((1., Isometry3::one()),
+                                            (0., Isometry3::new(Vector3::x(), Vector3::zero()))));
+        assert!(a.keyframes()[0].0 == 0.);
+        assert!(a.interpolate(0.).translation.approx_eq(&Vector3::x()));
+    }
+}
@@ -1,3 +1,7 @@
+mod animated;
 mod onb;
+mod transform;
 
+pub use self::animated::*;
 pub use self::onb::OrthoNormalBase;
+pub use self::transform::Transform;
@@ -0,0 +1,171 @@
+use nalgebra::*;
+use std::ops::Mul;
+
+/// Affine transform, which may scale objects unevenly or shear them on top
+/// of rotating and moving them.
+///
+/// The inverse of the matrix and its transpose, which transforms normals,
+/// are computed once when building the transform, so that intersecting rays
+/// never inverts matrices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    inverse_transpose: Matrix4<f64>
+}
+
+fn diagonal(x: f64, y: f64, z: f64) -> Matrix4<f64> {
+    Matrix4::new(x, 0., 0., 0.,
+                 0., y, 0., 0.,
+                 0., 0., z, 0.,
+                 0., 0., 0., 1.)
+}
+
+impl Transform {
+    fn from_parts(matrix: Matrix4<f64>, inverse: Matrix4<f64>) -> Transform {
+        Transform { matrix: matrix, inverse: inverse, inverse_transpose: inverse.transpose() }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::from_parts(diagonal(1., 1., 1.), diagonal(1., 1., 1.))
+    }
+
+    /// Builds a transform from the homogeneous matrix of an affine transform,
+    /// failing if its bottom row is not (0, 0, 0, 1) or it cannot be inverted.
+    pub fn from_matrix(matrix: Matrix4<f64>) -> Option<Transform> {
+        if matrix.m41 != 0. || matrix.m42 != 0. || matrix.m43 != 0. || matrix.m44 != 1. {
+            return None;
+        }
+        matrix.inverse().map(|inverse| Transform::from_parts(matrix, inverse))
+    }
+
+    pub fn from_isometry(isometry: Isometry3<f64>) -> Transform {
+        Transform::from_parts(isometry.to_homogeneous(), isometry.inverse().unwrap().to_homogeneous())
+    }
+
+    /// Scales along the axes by the components of `scale`, none of which may
+    /// be 0.
+    pub fn scaling(scale: Vector3<f64>) -> Transform {
+        assert!(scale.x != 0. && scale.y != 0. && scale.z != 0., "Cannot scale by 0");
+        Transform::from_parts(diagonal(scale.x, scale.y, scale.z), diagonal(1. / scale.x, 1. / scale.y, 1. / scale.z))
+    }
+
+    pub fn matrix(&self) -> &Matrix4<f64> {
+        &self.matrix
+    }
+
+    pub fn inverse_matrix(&self) -> &Matrix4<f64> {
+        &self.inverse
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform::from_parts(self.inverse, self.matrix)
+    }
+
+    pub fn transform_point(&self, p: &Point3<f64>) -> Point3<f64> {
+        let p = self.matrix * Vector4::new(p.x, p.y, p.z, 1.);
+        Point3::new(p.x, p.y, p.z)
+    }
+
+    pub fn transform_vector(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        let v = self.matrix * Vector4::new(v.x, v.y, v.z, 0.);
+        Vector3::new(v.x, v.y, v.z)
+    }
+
+    /// Transforms a normal by the inverse transpose of the transform, which
+    /// keeps it perpendicular to transformed surfaces, and normalizes it.
+    pub fn transform_normal(&self, n: &Vector3<f64>) -> Vector3<f64> {
+        let n = self.inverse_transpose * Vector4::new(n.x, n.y, n.z, 0.);
+        Vector3::new(n.x, n.y, n.z).normalize()
+    }
+
+    pub fn inverse_transform_point(&self, p: &Point3<f64>) -> Point3<f64> {
+        let p = self.inverse * Vector4::new(p.x, p.y, p.z, 1.);
+        Point3::new(p.x, p.y, p.z)
+    }
+
+    pub fn inverse_transform_vector(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        let v = self.inverse * Vector4::new(v.x, v.y, v.z, 0.);
+        Vector3::new(v.x, v.y, v.z)
+    }
+
+    /// Returns whether the transform only rotates and moves objects, without
+    /// scaling, shearing or mirroring them.
+    pub fn is_rigid(&self) -> bool {
+        let x = self.transform_vector(&Vector3::x());
+        let y = self.transform_vector(&Vector3::y());
+        norm(&x).approx_eq(&1.) && norm(&y).approx_eq(&1.) && dot(&x, &y).approx_eq(&0.) &&
+            cross(&x, &y).approx_eq(&self.transform_vector(&Vector3::z()))
+    }
+
+    pub fn approx_eq(&self, other: &Transform) -> bool {
+        self.matrix.approx_eq(&other.matrix)
+    }
+}
+
+/// `a * b` applies `b`, then `a`.
+impl Mul<Transform> for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform::from_parts(self.matrix * rhs.matrix, rhs.inverse * self.inverse)
+    }
+}
+
+impl From<Isometry3<f64>> for Transform {
+    fn from(isometry: Isometry3<f64>) -> Transform {
+        Transform::from_isometry(isometry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_traits::Zero;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_from_isometry() {
+        let isometry = Isometry3::new(Vector3::new(1., 2., 3.), Vector3::y() * (PI / 2.));
+        let t = Transform::from_isometry(isometry);
+        let p = Point3::new(1., 0., 0.);
+        assert!(t.transform_point(&p).approx_eq(&isometry.transform(&p)));
+        assert!(t.transform_vector(&Vector3::x()).approx_eq(&(isometry * Vector3::x())));
+        assert!(t.transform_normal(&Vector3::x()).approx_eq(&(isometry * Vector3::x())));
+        assert!(t.inverse_transform_point(&t.transform_point(&p)).approx_eq(&p));
+    }
+
+    #[test]
+    fn test_scaled_normals() {
+        // Squashing a slanted plane along y tilts its normal towards y
+        let t = Transform::scaling(Vector3::new(1., 0.5, 1.));
+        let n = t.transform_normal(&Vector3::new(1., 1., 0.).normalize());
+        assert!(n.approx_eq(&Vector3::new(1., 2., 0.).normalize()));
+        let tangent = t.transform_vector(&Vector3::new(1., -1., 0.));
+        assert!(n.dot(&tangent).approx_eq(&0.));
+    }
+
+    #[test]
+    fn test_composition() {
+        let rotation = Transform::from_isometry(Isometry3::new(Vector3::zero(), Vector3::z() * (PI / 2.)));
+        let t = Transform::from_isometry(Isometry3::new(Vector3::x() * 5., Vector3::zero())) * rotation *
+                Transform::scaling(Vector3::new(2., 1., 1.));
+        assert!(t.transform_point(&Point3::new(1., 0., 0.)).approx_eq(&Point3::new(5., 2., 0.)));
+        assert!((t * t.inverse()).approx_eq(&Transform::identity()));
+        let shear = Matrix4::new(1., 0.5, 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1.);
+        assert!(Transform::from_matrix(shear).unwrap().inverse_transform_point(&Point3::new(0.5, 1., 0.))
+                    .approx_eq(&Point3::new(0., 1., 0.)));
+        assert!(Transform::from_matrix(Matrix4::new(0., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1.))
+                    .is_none());
+        let projective = Matrix4::new(1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 1., 1.);
+        assert!(projective.inverse().is_some() && Transform::from_matrix(projective).is_none());
+    }
+
+    #[test]
+    fn test_is_rigid() {
+        let isometry = Isometry3::new(Vector3::new(1., 2., 3.), Vector3::new(0.3, -1., 2.));
+        assert!(Transform::from_isometry(isometry).is_rigid());
+        assert!(!Transform::scaling(Vector3::new(1., 2., 1.)).is_rigid());
+        assert!(!Transform::scaling(Vector3::new(1., 1., -1.)).is_rigid());
+    }
+}
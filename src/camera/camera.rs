@@ -1,10 +1,27 @@
 use ray::Ray;
 use nalgebra::Point3;
+use std::fmt::Debug;
 
-pub trait Camera: Sync {
+pub trait Camera: Debug + Sync {
     fn viewport(&self) -> (u32, u32);
     fn set_viewport(&mut self, viewport: (u32, u32));
 
-    fn pixel_ray(&self, coords: (f64, f64)) -> Option<Ray>;
+    /// Returns the interval of time during which the shutter is open.
+    fn shutter(&self) -> (f64, f64);
+    fn set_shutter(&mut self, shutter: (f64, f64));
+
+    /// Returns the ray going through the given pixel at the given time.
+    fn pixel_ray_at(&self, coords: (f64, f64), time: f64) -> Option<Ray>;
     fn eye_position(&self) -> Point3<f64>;
+
+    /// Returns the ray going through the given pixel when the shutter opens.
+    fn pixel_ray(&self, coords: (f64, f64)) -> Option<Ray> {
+        self.pixel_ray_at(coords, self.shutter().0)
+    }
+
+    /// Maps `u` in [0, 1] to an instant in the shutter interval.
+    fn shutter_time(&self, u: f64) -> f64 {
+        let (open, close) = self.shutter();
+        open + u * (close - open)
+    }
 }
@@ -1,4 +1,5 @@
 use nalgebra::*;
+use algebra::AnimatedTransform;
 use camera::Camera;
 use ray::Ray;
 
@@ -6,14 +7,28 @@ use ray::Ray;
 pub struct Orthographic {
     pub viewport: (u32, u32),
     pub plane_size: (f64, f64),
-    pub transform: Isometry3<f64>
+    pub transform: Isometry3<f64>,
+    pub shutter: (f64, f64),
+    /// Keyframed transform overriding `transform`, for camera motion.
+    pub motion: Option<AnimatedTransform>
 }
 
 impl Orthographic {
     pub fn new(viewport: (u32, u32), plane_size: (f64, f64),
                transform: Isometry3<f64>) -> Orthographic {
         Orthographic { viewport: viewport, plane_size: plane_size,
-                       transform: transform }
+                       transform: transform, shutter: (0., 0.), motion: None }
+    }
+
+    pub fn set_motion(&mut self, motion: Option<AnimatedTransform>) {
+        self.motion = motion;
+    }
+
+    pub fn transform_at(&self, time: f64) -> Isometry3<f64> {
+        match self.motion {
+            Some(ref motion) => motion.interpolate(time),
+            None => self.transform
+        }
     }
 }
 
@@ -21,19 +36,23 @@ impl Camera for Orthographic {
     fn viewport(&self) -> (u32, u32) { self.viewport }
     fn set_viewport(&mut self, viewport: (u32, u32)) { self.viewport = viewport; }
 
-    fn pixel_ray(&self, coords: (f64, f64)) -> Option<Ray> {
+    fn shutter(&self) -> (f64, f64) { self.shutter }
+    fn set_shutter(&mut self, shutter: (f64, f64)) { self.shutter = shutter; }
+
+    fn pixel_ray_at(&self, coords: (f64, f64), time: f64) -> Option<Ray> {
         let (pw, ph) = self.plane_size;
         let (vwu, vhu) = self.viewport;
         let (vw, vh) = (vwu as f64, vhu as f64);
         let (x, y) = coords;
         if x < vw && y < vh {
-            let right = self.transform * Vector3::x();
-            let up = self.transform * Vector3::y();
-            let forward = self.transform * Vector3::z();
+            let transform = self.transform_at(time);
+            let right = transform * Vector3::x();
+            let up = transform * Vector3::y();
+            let forward = transform * Vector3::z();
             let cam_x = ((x / (vw - 1.)) - 0.5) * pw;
             let cam_y = ((y / (vh - 1.)) - 0.5) * ph;
-            Some(Ray::new((self.transform.translation + cam_x * right + cam_y * up).to_point(),
-                           forward.normalize()))
+            Some(Ray::with_time((transform.translation + cam_x * right + cam_y * up).to_point(),
+                                forward.normalize(), time))
         }
         else { None }
     }
@@ -77,4 +96,16 @@ mod tests {
         assert!(ray.origin.approx_eq(&Point3::new(50., 50., 0.)));
         assert!(ray.direction.approx_eq(&Vector3::z()));
     }
+
+    #[test]
+    fn test_pixel_ray_moving() {
+        let start = Isometry3::new(Vector3::new(0., 0., 0.), Vector3::zero());
+        let end = Isometry3::new(Vector3::new(10., 0., 0.), Vector3::zero());
+        let mut cam = Orthographic::new((800, 600), (100., 100.), start);
+        cam.set_shutter((0., 1.));
+        cam.set_motion(Some(AnimatedTransform::between(0., start, 1., end)));
+        let ray = cam.pixel_ray_at((0., 0.), cam.shutter_time(0.5)).unwrap();
+        assert!(ray.time == 0.5);
+        assert!(ray.origin.approx_eq(&Point3::new(-45., -50., 0.)));
+    }
 }
@@ -1,3 +1,4 @@
+use algebra::AnimatedTransform;
 use camera::Camera;
 use nalgebra::*;
 use ray::Ray;
@@ -8,20 +9,35 @@ use ray::Ray;
 pub struct Perspective {
     pub viewport: (u32, u32),
     pub fov: (f64, f64),
-    pub transform: Isometry3<f64>
+    pub transform: Isometry3<f64>,
+    pub shutter: (f64, f64),
+    /// Keyframed transform overriding `transform`, for camera motion or shake.
+    pub motion: Option<AnimatedTransform>
 }
 
 impl Perspective {
     pub fn fov(viewport: (u32, u32), fov: (f64, f64), transform: Isometry3<f64>) -> Perspective {
-        Perspective { viewport: viewport, fov: fov, transform: transform }
+        Perspective { viewport: viewport, fov: fov, transform: transform,
+                      shutter: (0., 0.), motion: None }
     }
 
     pub fn aspect_ratio_fovx(viewport: (u32, u32), fovx: f64, transform: Isometry3<f64>) -> Perspective {
-        Perspective { viewport: viewport, fov: (fovx, fovx / (viewport.0 as f64 / viewport.1 as f64)), transform: transform }
+        Perspective::fov(viewport, (fovx, fovx / (viewport.0 as f64 / viewport.1 as f64)), transform)
     }
 
     pub fn aspect_ratio_fovy(viewport: (u32, u32), fovy: f64, transform: Isometry3<f64>) -> Perspective {
-        Perspective { viewport: viewport, fov: (fovy * (viewport.0 as f64 / viewport.1 as f64), fovy), transform: transform }
+        Perspective::fov(viewport, (fovy * (viewport.0 as f64 / viewport.1 as f64), fovy), transform)
+    }
+
+    pub fn set_motion(&mut self, motion: Option<AnimatedTransform>) {
+        self.motion = motion;
+    }
+
+    pub fn transform_at(&self, time: f64) -> Isometry3<f64> {
+        match self.motion {
+            Some(ref motion) => motion.interpolate(time),
+            None => self.transform
+        }
     }
 }
 
@@ -29,7 +45,10 @@ impl Camera for Perspective {
     fn viewport(&self) -> (u32, u32) { self.viewport }
     fn set_viewport(&mut self, viewport: (u32, u32)) { self.viewport = viewport; }
 
-    fn pixel_ray(&self, coords: (f64, f64)) -> Option<Ray> {
+    fn shutter(&self) -> (f64, f64) { self.shutter }
+    fn set_shutter(&mut self, shutter: (f64, f64)) { self.shutter = shutter; }
+
+    fn pixel_ray_at(&self, coords: (f64, f64), time: f64) -> Option<Ray> {
         let (xu, yu) = coords;
         let (x, y) = (xu as f64, yu as f64);
         let (wu, hu) = self.viewport;
@@ -40,9 +59,10 @@ impl Camera for Perspective {
                 let (fov_x, fov_y) = self.fov;
                 let (xf, yf) = (x / (w - 1.) - 0.5, y / (h - 1.) - 0.5);
                 let (theta_x, theta_y) = (xf * fov_x, yf * fov_y);
-                let direction = self.transform * Vector3::new(theta_x.tan(), theta_y.tan(), -1.).normalize();
-                Some(Ray::new(self.transform.transform(&Point3::new(0., 0., 0.)),
-                              direction))
+                let transform = self.transform_at(time);
+                let direction = transform * Vector3::new(theta_x.tan(), theta_y.tan(), -1.).normalize();
+                Some(Ray::with_time(transform.transform(&Point3::new(0., 0., 0.)),
+                                    direction, time))
             }
         }
     }
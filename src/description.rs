@@ -0,0 +1,431 @@
+//! Plain data descriptions of scenes and render settings, which can be saved
+//! or sent to other processes as JSON and built into the actual objects.
+
+use image::Rgb;
+use nalgebra::*;
+use num_traits::One;
+use rustc_serialize::json;
+use std::boxed::Box as StdBox;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use algebra::Transform;
+use camera::{Camera, Orthographic, Perspective};
+use film::{filter_from_name, Aov};
+use light::Light;
+use material::{self, LightMaterial, Material, MaterialLibrary, Phong};
+use objects::{self, BoxFace, Face, Object, Surface};
+use raytracer::{AdaptiveSettings, CancellationToken, CropWindow, SimpleSettings, TileOrder};
+use scene::Scene;
+use scene_graph::{Group, Node};
+
+/// Transform made of a translation, a rotation given as its axis scaled by
+/// its angle in radians, and for objects a scale along their axes applied
+/// first.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct TransformDescription {
+    pub translation: [f64; 3],
+    pub rotation: [f64; 3],
+    /// Rejected for cameras and groups, which are only moved and rotated.
+    pub scale: Option<[f64; 3]>
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum MaterialDescription {
+    Simple { color: [f64; 3] },
+    Phong { ambient: [f64; 3], diffuse: [f64; 3], specular: [f64; 3], shininess: f64 },
+    /// Material of the scene library with this name, shared by every object
+    /// referring to it.
+    Named(String)
+}
+
+/// Materials of the faces of a box replacing the one of the box.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct BoxFacesDescription {
+    pub top: Option<MaterialDescription>,
+    pub bottom: Option<MaterialDescription>,
+    pub left: Option<MaterialDescription>,
+    pub right: Option<MaterialDescription>,
+    pub front: Option<MaterialDescription>,
+    pub back: Option<MaterialDescription>
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum ObjectDescription {
+    Face { width: f64, height: f64, transform: TransformDescription, material: MaterialDescription },
+    Box { size: [f64; 3], transform: TransformDescription, material: MaterialDescription,
+          faces: Option<BoxFacesDescription> },
+    /// Rectangular area light, lit by `diffuse` and `specular`.
+    Light { width: f64, height: f64, transform: TransformDescription, material: MaterialDescription,
+            diffuse: [f64; 3], specular: [f64; 3] },
+    /// Objects placed by `transform`, on top of their own.
+    Group { transform: TransformDescription, children: Vec<ObjectDescription> }
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum CameraDescription {
+    /// Field of view along x and y in radians.
+    Perspective { resolution: [u32; 2], fov: [f64; 2], transform: TransformDescription },
+    Orthographic { resolution: [u32; 2], plane_size: [f64; 2], transform: TransformDescription }
+}
+
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct SceneDescription {
+    pub background: [f64; 3],
+    /// Materials the objects may refer to by name.
+    pub materials: BTreeMap<String, MaterialDescription>,
+    pub objects: Vec<ObjectDescription>,
+    pub camera: CameraDescription
+}
+
+/// Settings of `raytracer::Simple`, filters, AOVs and tile orders being
+/// given by name.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct RenderDescription {
+    pub n_samples: u32,
+    pub bounces: u32,
+    pub seed: u32,
+    /// Minimum and maximum samples per pixel and noise threshold of adaptive
+    /// sampling.
+    pub adaptive: Option<(u32, u32, f64)>,
+    pub filter: String,
+    pub aovs: Vec<String>,
+    pub tile_size: u32,
+    pub tile_order: String,
+    /// `x0, y0, x1, y1` of the pixels to render.
+    pub crop: Option<[u32; 4]>
+}
+
+fn rgb(c: &[f64; 3]) -> Rgb<f64> {
+    Rgb { data: *c }
+}
+
+impl TransformDescription {
+    pub fn new(translation: [f64; 3], rotation: [f64; 3]) -> TransformDescription {
+        TransformDescription { translation: translation, rotation: rotation, scale: None }
+    }
+
+    /// Builds the rigid part of the transform, without the scale.
+    pub fn build(&self) -> Isometry3<f64> {
+        let (t, r) = (self.translation, self.rotation);
+        Isometry3::new(Vector3::new(t[0], t[1], t[2]), Vector3::new(r[0], r[1], r[2]))
+    }
+
+    /// Builds the transform of a camera or group, failing if it has a scale.
+    pub fn build_rigid(&self) -> Result<Isometry3<f64>, String> {
+        match self.scale {
+            Some(s) => Err(format!("scale by [{}, {}, {}] of a camera or group", s[0], s[1], s[2])),
+            None => Ok(self.build())
+        }
+    }
+
+    /// Builds the whole transform, failing on a scale flattening objects.
+    pub fn build_transform(&self) -> Result<Transform, String> {
+        let rigid = Transform::from_isometry(self.build());
+        match self.scale {
+            Some(s) if s[0] == 0. || s[1] == 0. || s[2] == 0. =>
+                Err(format!("scale by [{}, {}, {}]", s[0], s[1], s[2])),
+            Some(s) => Ok(rigid * Transform::scaling(Vector3::new(s[0], s[1], s[2]))),
+            None => Ok(rigid)
+        }
+    }
+}
+
+impl MaterialDescription {
+    /// Builds the material, looking named materials up in `materials`. Fails
+    /// on an unknown name, returning it.
+    pub fn build(&self, materials: &MaterialLibrary) -> Result<Arc<Material>, String> {
+        match *self {
+            MaterialDescription::Simple { ref color } => Ok(Arc::new(material::Simple::new(rgb(color)))),
+            MaterialDescription::Phong { ref ambient, ref diffuse, ref specular, shininess } =>
+                Ok(Arc::new(Phong::new(rgb(ambient), rgb(diffuse), rgb(specular), shininess))),
+            MaterialDescription::Named(ref name) => materials.get(name).ok_or_else(|| name.clone())
+        }
+    }
+}
+
+impl BoxFacesDescription {
+    pub fn new() -> BoxFacesDescription {
+        BoxFacesDescription { top: None, bottom: None, left: None, right: None, front: None, back: None }
+    }
+
+    /// Builds the materials of the faces given one, failing on an unknown
+    /// material name.
+    pub fn build(&self, materials: &MaterialLibrary) -> Result<Vec<(BoxFace, Arc<Material>)>, String> {
+        let faces = [(BoxFace::Top, &self.top), (BoxFace::Bottom, &self.bottom), (BoxFace::Left, &self.left),
+                     (BoxFace::Right, &self.right), (BoxFace::Front, &self.front), (BoxFace::Back, &self.back)];
+        let mut built = vec!();
+        for &(face, material) in faces.iter() {
+            if let Some(ref material) = *material {
+                built.push((face, material.build(materials)?));
+            }
+        }
+        Ok(built)
+    }
+}
+
+impl ObjectDescription {
+    /// Builds the object, failing on an unknown material name, returning it,
+    /// on a scale by 0 or on a scaled group.
+    pub fn build(&self, materials: &MaterialLibrary) -> Result<Node, String> {
+        let object = match *self {
+            ObjectDescription::Face { width, height, ref transform, ref material } => {
+                let face = Face::new(width, height, transform.build_transform()?, material.build(materials)?);
+                Object::from_surface(Surface::from_face(face))
+            }
+            ObjectDescription::Box { ref size, ref transform, ref material, ref faces } => {
+                let size = Vector3::new(size[0], size[1], size[2]);
+                let faces = match *faces {
+                    Some(ref faces) => faces.build(materials)?,
+                    None => vec!()
+                };
+                let b = objects::Box::with_face_materials(size, transform.build_transform()?,
+                                                          material.build(materials)?, &faces);
+                Object::from_surface(Surface::from_box(b))
+            }
+            ObjectDescription::Light { width, height, ref transform, ref material, ref diffuse, ref specular } => {
+                let face = Face::new(width, height, transform.build_transform()?, material.build(materials)?);
+                Object::from_light(Light::new(face, LightMaterial::new(rgb(diffuse), rgb(specular))))
+            }
+            ObjectDescription::Group { ref transform, ref children } => {
+                let children = children.iter().map(|c| c.build(materials)).collect::<Result<_, _>>()?;
+                return Ok(Node::from_group(Group::with_children(transform.build_rigid()?, children)));
+            }
+        };
+        Ok(Node::from_object(object))
+    }
+}
+
+impl CameraDescription {
+    pub fn resolution(&self) -> (u32, u32) {
+        match *self {
+            CameraDescription::Perspective { resolution, .. } |
+            CameraDescription::Orthographic { resolution, .. } => (resolution[0], resolution[1])
+        }
+    }
+
+    /// Builds the camera, failing if it is scaled.
+    pub fn build(&self) -> Result<StdBox<Camera>, String> {
+        Ok(match *self {
+            CameraDescription::Perspective { resolution, fov, ref transform } =>
+                StdBox::new(Perspective::fov((resolution[0], resolution[1]), (fov[0], fov[1]),
+                                             transform.build_rigid()?)),
+            CameraDescription::Orthographic { resolution, plane_size, ref transform } =>
+                StdBox::new(Orthographic::new((resolution[0], resolution[1]), (plane_size[0], plane_size[1]),
+                                              transform.build_rigid()?))
+        })
+    }
+}
+
+impl SceneDescription {
+    /// Builds the scene, its objects sharing the materials of the library.
+    /// Fails on an unknown material name, returning it, on an object scaled
+    /// by 0 or on a scaled group or camera; library materials cannot refer to
+    /// each other.
+    pub fn build(&self) -> Result<Scene, String> {
+        let mut materials = MaterialLibrary::new();
+        for (name, material) in self.materials.iter() {
+            let material = material.build(&MaterialLibrary::new())?;
+            materials.add(name, material);
+        }
+        let children = self.objects.iter().map(|o| o.build(&materials)).collect::<Result<_, _>>()?;
+        let root = Group::with_children(Isometry3::one(), children);
+        Ok(Scene::with_materials(rgb(&self.background), materials, root.flatten(), self.camera.build()?))
+    }
+
+    pub fn to_json(&self) -> String {
+        json::encode(self).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> Result<SceneDescription, json::DecoderError> {
+        json::decode(s)
+    }
+}
+
+impl RenderDescription {
+    /// Describes rendering `n_samples` per pixel with the defaults of `rt`.
+    pub fn new(n_samples: u32, bounces: u32) -> RenderDescription {
+        RenderDescription { n_samples: n_samples, bounces: bounces, seed: 0, adaptive: None,
+                            filter: "box".to_string(), aovs: vec!(), tile_size: 16,
+                            tile_order: "spiral".to_string(), crop: None }
+    }
+
+    /// Builds the settings, without observer. Fails on an unknown filter, AOV
+    /// or tile order, returning its name, or on adaptive settings or a crop
+    /// window out of order, returning what is wrong with them.
+    pub fn build(&self) -> Result<SimpleSettings, String> {
+        let filter = filter_from_name(&self.filter).ok_or(self.filter.clone())?;
+        let mut aovs = vec!();
+        for name in self.aovs.iter() {
+            aovs.push(Aov::from_name(name).ok_or(name.clone())?);
+        }
+        let tile_order = TileOrder::from_name(&self.tile_order).ok_or(self.tile_order.clone())?;
+        let adaptive = match self.adaptive {
+            Some((min, max, _)) if min == 0 || min > max =>
+                return Err(format!("adaptive sampling from {} to {} samples", min, max)),
+            Some((min, max, threshold)) => Some(AdaptiveSettings::new(min, max, threshold)),
+            None => None
+        };
+        let crop = match self.crop {
+            Some(c) if c[0] > c[2] || c[1] > c[3] =>
+                return Err(format!("crop window from ({}, {}) to ({}, {})", c[0], c[1], c[2], c[3])),
+            Some(c) => Some(CropWindow::new(c[0], c[1], c[2], c[3])),
+            None => None
+        };
+        Ok(SimpleSettings { n_samples: self.n_samples, bounces: self.bounces, seed: self.seed,
+                            adaptive: adaptive, filter: filter, aovs: aovs, tile_size: self.tile_size,
+                            tile_order: tile_order, crop: crop, observer: None,
+                            cancellation: CancellationToken::new() })
+    }
+}
+
+/// A wall lit by a small area light in front of a perspective camera, shared
+/// by the tests that render a scene.
+#[cfg(test)]
+pub fn test_scene(resolution: [u32; 2]) -> SceneDescription {
+    use std::f64::consts::PI;
+
+    let grey = MaterialDescription::Phong { ambient: [0.1; 3], diffuse: [0.6; 3], specular: [0.6; 3],
+                                            shininess: 2. };
+    SceneDescription {
+        background: [0.3; 3],
+        materials: BTreeMap::new(),
+        objects: vec!(ObjectDescription::Face { width: 4., height: 4.,
+                                                transform: TransformDescription::new([0., 0., -3.], [0.; 3]),
+                                                material: grey.clone() },
+                      ObjectDescription::Light { width: 1., height: 1.,
+                                                 transform: TransformDescription::new([0., 1.5, -2.],
+                                                                                      [PI / 2., 0., 0.]),
+                                                 material: grey, diffuse: [0.6; 3], specular: [0.6; 3] }),
+        camera: CameraDescription::Perspective { resolution: resolution, fov: [PI / 2., PI / 2.],
+                                                 transform: TransformDescription::new([0.; 3], [0.; 3]) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use material::same_handle;
+    use nalgebra::*;
+    use objects::Drawable;
+    use std::f64::consts::PI;
+
+    fn box_scene() -> SceneDescription {
+        let mut scene = test_scene([8, 8]);
+        scene.objects.insert(1, ObjectDescription::Box { size: [1., 1., 1.],
+                                                         transform: TransformDescription::new([0., -1., -2.5],
+                                                                                              [0., 0.3, 0.]),
+                                                         material: MaterialDescription::Simple { color: [1., 0., 0.] },
+                                                         faces: None });
+        scene
+    }
+
+    #[test]
+    fn test_scene_json_round_trip() {
+        let scene = box_scene();
+        let decoded = SceneDescription::from_json(&scene.to_json()).unwrap();
+        assert!(decoded == scene);
+        assert!(decoded.build().unwrap().hash() == scene.build().unwrap().hash());
+        assert!(scene.build().unwrap().objects().len() == 3);
+    }
+
+    #[test]
+    fn test_named_materials() {
+        let mut scene = box_scene();
+        scene.materials.insert("red".to_string(), MaterialDescription::Simple { color: [1., 0., 0.] });
+        for object in scene.objects.iter_mut() {
+            match *object {
+                ObjectDescription::Face { ref mut material, .. } | ObjectDescription::Box { ref mut material, .. } =>
+                    *material = MaterialDescription::Named("red".to_string()),
+                _ => {}
+            }
+        }
+        let decoded = SceneDescription::from_json(&scene.to_json()).unwrap();
+        assert!(decoded == scene);
+        let built = decoded.build().unwrap();
+        let red = built.materials().get("red").unwrap();
+        assert!(same_handle(built.objects()[0].material(), &red));
+        assert!(same_handle(built.objects()[1].material(), &red));
+        let transform = TransformDescription::new([0.; 3], [0.; 3]);
+        scene.objects.push(ObjectDescription::Box { size: [1.; 3], transform: transform,
+                                                    material: MaterialDescription::Named("blue".to_string()),
+                                                    faces: None });
+        assert!(scene.build().err() == Some("blue".to_string()));
+        scene.materials.insert("pink".to_string(), MaterialDescription::Named("red".to_string()));
+        assert!(scene.build().err() == Some("red".to_string()));
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut transform = TransformDescription::new([0., 0., -3.], [0.; 3]);
+        let json = r#"{"translation":[0.0,0.0,-3.0],"rotation":[0.0,0.0,0.0]}"#;
+        assert!(json::decode::<TransformDescription>(json).unwrap() == transform);
+        transform.scale = Some([2., 1., 1.]);
+        let p = transform.build_transform().unwrap().transform_point(&Point3::new(1., 1., 0.));
+        assert!(p.approx_eq(&Point3::new(2., 1., -3.)));
+        assert!(transform.build().translation.approx_eq(&Vector3::new(0., 0., -3.)));
+        transform.scale = Some([1., 0., 1.]);
+        assert!(transform.build_transform().is_err());
+        let mut scene = box_scene();
+        if let ObjectDescription::Box { transform: ref mut t, .. } = scene.objects[1] {
+            t.scale = Some([1., 0., 1.]);
+        }
+        assert!(scene.build().is_err());
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut scene = box_scene();
+        let objects = scene.objects.clone();
+        let transform = TransformDescription::new([1., 2., 3.], [0., 0.5, 0.]);
+        scene.objects = vec!(ObjectDescription::Group { transform: transform.clone(), children: objects.clone() });
+        let built = scene.build().unwrap();
+        assert!(built.objects().len() == 3);
+        for (object, description) in built.objects().iter().zip(objects.iter()) {
+            match description.build(&MaterialLibrary::new()).unwrap() {
+                Node::Object(o) => assert!(**object == o.transformed(&transform.build())),
+                _ => panic!("not an object")
+            }
+        }
+        // Groups and cameras cannot be scaled
+        let mut scaled = transform.clone();
+        scaled.scale = Some([2., 2., 2.]);
+        scene.objects = vec!(ObjectDescription::Group { transform: scaled.clone(), children: objects });
+        assert!(scene.build().is_err());
+        let mut scene = box_scene();
+        if let CameraDescription::Perspective { ref mut transform, .. } = scene.camera {
+            *transform = scaled;
+        }
+        assert!(scene.build().is_err());
+    }
+
+    #[test]
+    fn test_render_description() {
+        let mut render = RenderDescription::new(4, 1);
+        assert!(render.build().is_ok());
+        render.aovs = vec!("depth".to_string(), "fog".to_string());
+        assert!(render.build().err() == Some("fog".to_string()));
+    }
+
+    #[test]
+    fn test_render_description_adaptive_range() {
+        let mut render = RenderDescription::new(4, 1);
+        render.adaptive = Some((4, 16, 0.1));
+        assert!(render.build().is_ok());
+        render.adaptive = Some((0, 4, 0.1));
+        assert!(render.build().is_err());
+        render.adaptive = Some((8, 4, 0.1));
+        assert!(render.build().is_err());
+    }
+
+    #[test]
+    fn test_render_description_crop() {
+        let mut render = RenderDescription::new(4, 1);
+        render.crop = Some([1, 1, 5, 5]);
+        assert!(render.build().is_ok());
+        render.crop = Some([5, 0, 1, 1]);
+        assert!(render.build().is_err());
+        render.crop = Some([0, 5, 1, 1]);
+        assert!(render.build().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use distributed::protocol::*;
+use film::Film;
+use io::bytes::invalid_data;
+use raytracer::{Jittered, Simple, Tile};
+
+/// Time between checks for new workers or tiles to hand out.
+const POLL_INTERVAL_MS: u64 = 10;
+
+/// Time a worker may stall reading a tile or sending its film before the
+/// tile goes back to the other workers.
+const WORKER_TIMEOUT_SECS: u64 = 300;
+
+/// Tiles left to hand out and films received, shared by the threads serving
+/// the workers.
+struct Schedule {
+    tiles: Vec<Tile>,
+    pending: VecDeque<usize>,
+    films: Vec<Option<Film>>,
+    /// Tiles whose film has not been received yet, handed out or not.
+    left: usize
+}
+
+/// Hands out the tiles of a job to the workers connecting to it and merges
+/// the films they send back.
+pub struct Coordinator {
+    listener: TcpListener,
+    job: Job
+}
+
+impl Schedule {
+    fn next_tile(&mut self) -> Option<(usize, Tile)> {
+        self.pending.pop_front().map(|i| (i, self.tiles[i]))
+    }
+}
+
+/// Serves a worker until the render is done or the worker fails or times
+/// out, the tile it was rendering then going back to the other workers. The
+/// films sent back must cover the same pixels as the tiles of `template`.
+fn serve_worker(mut stream: TcpStream, job: Job, template: Film, schedule: Arc<Mutex<Schedule>>) {
+    let timeout = Some(Duration::from_secs(WORKER_TIMEOUT_SECS));
+    if stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout)).is_err() ||
+       write_message(&mut stream, &Message::Job(job)).is_err() {
+        return;
+    }
+    loop {
+        let next = {
+            let mut schedule = schedule.lock().unwrap();
+            if schedule.left == 0 {
+                let _ = write_message(&mut stream, &Message::Done);
+                return;
+            }
+            schedule.next_tile()
+        };
+        let (i, tile) = match next {
+            Some(next) => next,
+            // Tiles may still come back from workers failing
+            None => {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                continue;
+            }
+        };
+        let film = write_message(&mut stream, &Message::Tile(i as u32, tile)).and_then(|_| {
+            let tile_film = template.tile(tile.x0, tile.y0, tile.x1, tile.y1);
+            match read_message(&mut stream, Some(&tile_film))? {
+                Message::TileFilm(id, film) => match id as usize == i {
+                    true => Ok(film),
+                    false => Err(invalid_data("film of another tile"))
+                },
+                _ => Err(invalid_data("expected the film of the tile"))
+            }
+        });
+        let mut schedule = schedule.lock().unwrap();
+        match film {
+            Ok(film) => {
+                schedule.films[i] = Some(film);
+                schedule.left -= 1;
+            }
+            Err(_) => {
+                schedule.pending.push_back(i);
+                return;
+            }
+        }
+    }
+}
+
+impl Coordinator {
+    pub fn bind<A: ToSocketAddrs>(address: A, job: Job) -> io::Result<Coordinator> {
+        Ok(Coordinator { listener: TcpListener::bind(address)?, job: job })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Renders the job with the workers connecting, which may come and go:
+    /// the render waits for workers as long as tiles are left. Failing to
+    /// accept a worker is logged and does not stop the render. The image is
+    /// the same as rendering the job locally.
+    pub fn render(&self) -> io::Result<Film> {
+        let settings = self.job.render.build()
+            .map_err(|_| invalid_data("job with unknown or invalid render settings"))?;
+        let scene = self.job.scene.build().map_err(|_| invalid_data("job with an unknown material or invalid scene"))?;
+        let renderer = Simple::new(scene, settings, Jittered::with_seed(self.job.render.seed));
+        let tiles = renderer.tile_schedule();
+        let n_tiles = tiles.len();
+        let schedule = Arc::new(Mutex::new(Schedule { tiles: tiles, pending: (0..n_tiles).collect(),
+                                                      films: (0..n_tiles).map(|_| None).collect(),
+                                                      left: n_tiles }));
+        let mut film = renderer.new_film();
+        let (width, height) = film.resolution();
+        let template = Film::without_pixels(width, height, film.filter().box_clone(), &film.aovs());
+
+        self.listener.set_nonblocking(true)?;
+        let mut workers = vec!();
+        while schedule.lock().unwrap().left > 0 {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    // Only this worker is dropped, the others carry on with the render
+                    if let Err(e) = stream.set_nonblocking(false).and_then(|_| stream.set_nodelay(true)) {
+                        println!("Cannot set up a worker connection: {}", e);
+                        continue;
+                    }
+                    let (job, template, schedule) = (self.job.clone(), template.clone(), schedule.clone());
+                    workers.push(thread::spawn(move || serve_worker(stream, job, template, schedule)));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                }
+                Err(e) => {
+                    println!("Cannot accept a worker: {}", e);
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                }
+            }
+        }
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        // Tiles do not share any pixel, so the image does not depend on which
+        // worker rendered what
+        let mut schedule = schedule.lock().unwrap();
+        for tile_film in schedule.films.iter_mut() {
+            film.merge(&tile_film.take().unwrap());
+        }
+        Ok(film)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use description::*;
+    use distributed::run_worker;
+    use film::same_image;
+    use raytracer::ParallelRenderer;
+
+    fn test_job() -> Job {
+        let scene = test_scene([16, 12]);
+        let mut render = RenderDescription::new(3, 1);
+        render.filter = "tent".to_string();
+        render.tile_size = 4;
+        Job { scene: scene, render: render }
+    }
+
+    fn local_render(job: &Job) -> Film {
+        let sampler = Jittered::with_seed(job.render.seed);
+        Simple::new(job.scene.build().unwrap(), job.render.build().unwrap(), sampler).render_parallel()
+    }
+
+    #[test]
+    fn test_render_with_local_workers() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", test_job()).unwrap();
+        let address = coordinator.local_addr().unwrap();
+        let workers: Vec<_> = (0..3).map(|_| thread::spawn(move || run_worker(address))).collect();
+        let film = coordinator.render().unwrap();
+        for worker in workers {
+            assert!(worker.join().unwrap().is_ok());
+        }
+        assert!(same_image(&film, &local_render(&test_job())));
+    }
+
+    #[test]
+    fn test_worker_disconnect() {
+        let coordinator = Coordinator::bind("127.0.0.1:0", test_job()).unwrap();
+        let address = coordinator.local_addr().unwrap();
+        // The first worker takes a tile and leaves without rendering it
+        let workers = thread::spawn(move || {
+            {
+                let mut stream = TcpStream::connect(address).unwrap();
+                read_message(&mut stream, None).unwrap();
+                match read_message(&mut stream, None).unwrap() {
+                    Message::Tile(..) => (),
+                    m => panic!("unexpected {:?}", m)
+                }
+            }
+            run_worker(address)
+        });
+        let film = coordinator.render().unwrap();
+        assert!(workers.join().unwrap().is_ok());
+        assert!(same_image(&film, &local_render(&test_job())));
+    }
+}
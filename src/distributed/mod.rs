@@ -0,0 +1,11 @@
+//! Rendering a frame on several processes or machines: a coordinator hands
+//! out the tiles of the frame to the workers connecting to it over TCP, and
+//! merges the films they send back.
+
+mod protocol;
+mod coordinator;
+mod worker;
+
+pub use self::protocol::*;
+pub use self::coordinator::*;
+pub use self::worker::*;
@@ -0,0 +1,139 @@
+use rustc_serialize::json;
+use std::io::{self, Read, Write};
+
+use description::{RenderDescription, SceneDescription};
+use film::Film;
+use io::bytes::{invalid_data, read_u32, write_u32};
+use io::{read_film_like, write_film};
+use raytracer::Tile;
+
+/// What a coordinator sends its workers once they connect.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Job {
+    pub scene: SceneDescription,
+    pub render: RenderDescription
+}
+
+/// Messages between a coordinator and its workers. The coordinator sends the
+/// job, then tiles one at a time, each answered by its film, and finally
+/// `Done`.
+#[derive(Debug)]
+pub enum Message {
+    Job(Job),
+    /// Tile to render and its number in the render.
+    Tile(u32, Tile),
+    /// Film of the tile with the given number.
+    TileFilm(u32, Film),
+    Done
+}
+
+const JOB: u8 = 1;
+const TILE: u8 = 2;
+const TILE_FILM: u8 = 3;
+const DONE: u8 = 4;
+
+/// Largest payload read, so that a corrupt or hostile size is rejected
+/// before allocating it.
+pub const MAX_MESSAGE_SIZE: u32 = 1 << 30;
+
+/// Writes a message as its kind, the size of its payload and the payload.
+pub fn write_message<W: Write>(w: &mut W, message: &Message) -> io::Result<()> {
+    let mut payload = vec!();
+    let kind = match *message {
+        Message::Job(ref job) => {
+            payload.extend_from_slice(json::encode(job).unwrap().as_bytes());
+            JOB
+        }
+        Message::Tile(id, ref tile) => {
+            for &v in [id, tile.x0, tile.y0, tile.x1, tile.y1, tile.column, tile.row].iter() {
+                write_u32(&mut payload, v)?;
+            }
+            TILE
+        }
+        Message::TileFilm(id, ref film) => {
+            write_u32(&mut payload, id)?;
+            write_film(&mut payload, film)?;
+            TILE_FILM
+        }
+        Message::Done => DONE
+    };
+    w.write_all(&[kind])?;
+    write_u32(w, payload.len() as u32)?;
+    w.write_all(&payload)?;
+    w.flush()
+}
+
+/// Reads a message. Tile films must cover the same pixels as `template`, the
+/// empty film of the tile handed out, which only workers, who never receive
+/// films, may leave out.
+pub fn read_message<R: Read>(r: &mut R, template: Option<&Film>) -> io::Result<Message> {
+    let mut kind = [0; 1];
+    r.read_exact(&mut kind)?;
+    let size = read_u32(r)?;
+    if size > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("message too large"));
+    }
+    let mut payload = vec![0; size as usize];
+    r.read_exact(&mut payload)?;
+    let mut payload = &payload[..];
+    match kind[0] {
+        JOB => {
+            let job = String::from_utf8(payload.to_vec()).map_err(|_| invalid_data("job is not UTF-8"))?;
+            json::decode(&job).map(Message::Job).map_err(|_| invalid_data("invalid job"))
+        }
+        TILE => {
+            let mut v = [0; 7];
+            for x in v.iter_mut() {
+                *x = read_u32(&mut payload)?;
+            }
+            Ok(Message::Tile(v[0], Tile { x0: v[1], y0: v[2], x1: v[3], y1: v[4], column: v[5], row: v[6] }))
+        }
+        TILE_FILM => {
+            let template = template.ok_or(invalid_data("unexpected tile film"))?;
+            let id = read_u32(&mut payload)?;
+            Ok(Message::TileFilm(id, read_film_like(&mut payload, template)?))
+        }
+        DONE => Ok(Message::Done),
+        _ => Err(invalid_data("unknown message"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::filter_from_name;
+
+    #[test]
+    fn test_tile_messages() {
+        let tile = Tile { x0: 16, y0: 0, x1: 32, y1: 9, column: 1, row: 0 };
+        let filter = filter_from_name("box").unwrap();
+        let template = Film::without_pixels(40, 9, filter, &[]);
+        let film = template.tile(16, 0, 32, 9);
+        let mut bytes = vec!();
+        write_message(&mut bytes, &Message::Tile(3, tile)).unwrap();
+        write_message(&mut bytes, &Message::TileFilm(3, film)).unwrap();
+        write_message(&mut bytes, &Message::Done).unwrap();
+        let mut r = &bytes[..];
+        match read_message(&mut r, None).unwrap() {
+            Message::Tile(3, t) => assert!(t == tile),
+            m => panic!("unexpected {:?}", m)
+        }
+        assert!(read_message(&mut &r[..], None).is_err());
+        assert!(read_message(&mut &r[..], Some(&template.tile(0, 0, 16, 9))).is_err());
+        match read_message(&mut r, Some(&template.tile(16, 0, 32, 9))).unwrap() {
+            Message::TileFilm(3, f) => assert!(f.origin() == (16, 0) && f.size() == (16, 9)),
+            m => panic!("unexpected {:?}", m)
+        }
+        match read_message(&mut r, None).unwrap() {
+            Message::Done => (),
+            m => panic!("unexpected {:?}", m)
+        }
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let mut bytes = vec![TILE_FILM];
+        write_u32(&mut bytes, MAX_MESSAGE_SIZE + 1).unwrap();
+        assert!(read_message(&mut &bytes[..], None).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+
+use distributed::protocol::*;
+use io::bytes::invalid_data;
+use raytracer::{Jittered, Simple};
+
+/// Connects to a coordinator and renders the tiles it hands out, with a
+/// jittered sampler, until it tells the render is done.
+pub fn run_worker<A: ToSocketAddrs>(address: A) -> io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let job = match read_message(&mut stream, None)? {
+        Message::Job(job) => job,
+        _ => return Err(invalid_data("expected a job"))
+    };
+    let settings = job.render.build()
+        .map_err(|_| invalid_data("job with unknown or invalid render settings"))?;
+    let scene = job.scene.build().map_err(|_| invalid_data("job with an unknown material or invalid scene"))?;
+    let renderer = Simple::new(scene, settings, Jittered::with_seed(job.render.seed));
+    loop {
+        match read_message(&mut stream, None)? {
+            Message::Tile(id, tile) => {
+                let film = renderer.render_tile_film(&tile);
+                write_message(&mut stream, &Message::TileFilm(id, film))?;
+            }
+            Message::Done => return Ok(()),
+            _ => return Err(invalid_data("unexpected message"))
+        }
+    }
+}
@@ -0,0 +1,204 @@
+/// Arbitrary output variable, an extra per-pixel buffer rendered along with
+/// the radiance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aov {
+    /// Distance from the camera to the first hit.
+    Depth,
+    /// World space normal at the first hit.
+    Normal,
+    /// Diffuse color of the material at the first hit.
+    Albedo,
+    /// Identifier of the object at the first hit, 0 for the background.
+    ObjectId,
+    /// Identifier of the material at the first hit, 0 for the background.
+    MaterialId,
+    /// Radiance reaching the camera straight from the first hit or the
+    /// background: emission, ambient and direct lighting.
+    Direct,
+    /// Radiance reaching the camera after a reflection at the first hit.
+    Indirect
+}
+
+pub const N_AOVS: usize = 7;
+
+pub const ALL_AOVS: [Aov; N_AOVS] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId,
+                                     Aov::MaterialId, Aov::Direct, Aov::Indirect];
+
+/// Position of the buffer of each AOV in a list of buffers, by `Aov::index`,
+/// so that samples find their buffer without searching the list.
+pub type AovSlots = [Option<usize>; N_AOVS];
+
+pub fn aov_slots(aovs: &[Aov]) -> AovSlots {
+    let mut slots = [None; N_AOVS];
+    for (i, aov) in aovs.iter().enumerate() {
+        slots[aov.index()] = Some(i);
+    }
+    slots
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().find(|a| a.name() == name).cloned()
+    }
+
+    /// Returns the position of the AOV in `ALL_AOVS`.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect"
+        }
+    }
+
+    /// Names of the channels of the AOV, following the OpenEXR layer naming.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match *self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["N.X", "N.Y", "N.Z"],
+            Aov::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Aov::ObjectId => &["objectId"],
+            Aov::MaterialId => &["materialId"],
+            Aov::Direct => &["direct.R", "direct.G", "direct.B"],
+            Aov::Indirect => &["indirect.R", "indirect.G", "indirect.B"]
+        }
+    }
+
+    pub fn components(&self) -> usize {
+        self.channels().len()
+    }
+
+    /// Identifiers cannot be averaged, so pixels keep the identifier of their
+    /// first sample.
+    pub fn is_identifier(&self) -> bool {
+        *self == Aov::ObjectId || *self == Aov::MaterialId
+    }
+}
+
+/// Adds a sample of an AOV to the sum and sample count of a pixel.
+pub fn add_aov_sample(aov: Aov, sum: &mut [f64], count: &mut u32, value: &[f64]) {
+    assert!(value.len() == sum.len());
+    let identifier_set = aov.is_identifier() && *count > 0;
+    if !identifier_set {
+        for (v, x) in sum.iter_mut().zip(value.iter()) {
+            *v += *x;
+        }
+    }
+    *count += 1;
+}
+
+/// Samples of an AOV accumulated per pixel, without reconstruction filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovBuffer {
+    aov: Aov,
+    values: Vec<f64>,
+    counts: Vec<u32>
+}
+
+impl AovBuffer {
+    pub fn new(aov: Aov, n_pixels: usize) -> AovBuffer {
+        AovBuffer { aov: aov, values: vec![0.; n_pixels * aov.components()], counts: vec![0; n_pixels] }
+    }
+
+    /// Rebuilds a buffer from its accumulated values and sample counts, e.g.
+    /// read back from a checkpoint.
+    pub fn from_parts(aov: Aov, values: Vec<f64>, counts: Vec<u32>) -> AovBuffer {
+        assert!(values.len() == counts.len() * aov.components());
+        AovBuffer { aov: aov, values: values, counts: counts }
+    }
+
+    pub fn aov(&self) -> Aov {
+        self.aov
+    }
+
+    /// Sums of the samples, `components()` values per pixel.
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    /// Returns the sums and counts of the samples, to accumulate into
+    /// parts of the buffer at once.
+    pub fn parts_mut(&mut self) -> (&mut [f64], &mut [u32]) {
+        (&mut self.values[..], &mut self.counts[..])
+    }
+
+    pub fn add(&mut self, i: usize, value: &[f64]) {
+        let n = self.aov.components();
+        add_aov_sample(self.aov, &mut self.values[i * n..(i + 1) * n], &mut self.counts[i], value);
+    }
+
+    /// Adds the samples of pixel `j` of another buffer to pixel `i`.
+    pub fn merge(&mut self, i: usize, other: &AovBuffer, j: usize) {
+        let n = self.aov.components();
+        if other.counts[j] > 0 {
+            let value = other.values[j * n..(j + 1) * n].to_vec();
+            match self.aov.is_identifier() {
+                true => if self.counts[i] == 0 {
+                    self.values[i * n..(i + 1) * n].copy_from_slice(&value);
+                },
+                false => for (v, x) in self.values[i * n..(i + 1) * n].iter_mut().zip(value.iter()) {
+                    *v += *x;
+                }
+            }
+            self.counts[i] += other.counts[j];
+        }
+    }
+
+    /// Returns the average of the samples of a pixel, 0 if there are none.
+    pub fn value(&self, i: usize) -> Vec<f64> {
+        let n = self.aov.components();
+        let values = &self.values[i * n..(i + 1) * n];
+        match (self.counts[i], self.aov.is_identifier()) {
+            (0, _) => vec![0.; n],
+            (_, true) => values.to_vec(),
+            (count, false) => values.iter().map(|v| v / count as f64).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aov_names() {
+        for aov in ALL_AOVS.iter() {
+            assert!(Aov::from_name(aov.name()) == Some(*aov));
+            assert!(ALL_AOVS[aov.index()] == *aov);
+        }
+        assert!(Aov::from_name("beauty").is_none());
+        let slots = aov_slots(&[Aov::Indirect, Aov::Depth]);
+        assert!(slots[Aov::Indirect.index()] == Some(0) && slots[Aov::Depth.index()] == Some(1));
+        assert!(slots[Aov::Normal.index()].is_none());
+    }
+
+    #[test]
+    fn test_average_and_identifiers() {
+        let mut normal = AovBuffer::new(Aov::Normal, 2);
+        normal.add(1, &[1., 0., 0.]);
+        normal.add(1, &[0., 1., 0.]);
+        assert!(normal.value(1) == vec!(0.5, 0.5, 0.));
+        assert!(normal.value(0) == vec!(0., 0., 0.));
+
+        let mut ids = AovBuffer::new(Aov::ObjectId, 2);
+        ids.add(0, &[3.]);
+        ids.add(0, &[5.]);
+        let mut other = AovBuffer::new(Aov::ObjectId, 1);
+        other.add(0, &[7.]);
+        ids.merge(0, &other, 0);
+        ids.merge(1, &other, 0);
+        assert!(ids.value(0) == vec!(3.));
+        assert!(ids.value(1) == vec!(7.));
+    }
+}
@@ -0,0 +1,144 @@
+use image::Rgb;
+use rayon::prelude::*;
+
+use film::{Aov, Film};
+
+/// Settings of the edge-avoiding à-trous wavelet denoiser. Neighbouring pixels
+/// are averaged less the further apart their colors and AOVs are, relative to
+/// the `sigma` of each. The AOVs guiding the filter are the normal, albedo and
+/// depth ones held by the film.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenoiseSettings {
+    /// Number of passes, each doubling the extent of the filter.
+    pub iterations: u32,
+    /// Color tolerance of the first pass, halved at each following pass.
+    pub sigma_color: f64,
+    pub sigma_normal: f64,
+    pub sigma_albedo: f64,
+    /// Depth tolerance, relative to the largest depth of the film.
+    pub sigma_depth: f64
+}
+
+/// B3 spline coefficients of the à-trous kernel.
+const KERNEL: [f64; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+struct Guide {
+    values: Vec<Vec<f64>>,
+    sigma: f64
+}
+
+impl DenoiseSettings {
+    pub fn new() -> DenoiseSettings {
+        DenoiseSettings { iterations: 5, sigma_color: 1., sigma_normal: 0.3, sigma_albedo: 0.1, sigma_depth: 0.05 }
+    }
+}
+
+fn distance2(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn guides(film: &Film, settings: &DenoiseSettings) -> Vec<Guide> {
+    let (width, height) = film.resolution();
+    let aovs = film.aovs();
+    let mut guides = vec!();
+    for &(aov, sigma) in [(Aov::Normal, settings.sigma_normal),
+                          (Aov::Albedo, settings.sigma_albedo),
+                          (Aov::Depth, settings.sigma_depth)].iter() {
+        if aovs.contains(&aov) {
+            let mut values: Vec<Vec<f64>> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+                                                       .map(|(x, y)| film.aov_pixel(x, y, aov).unwrap())
+                                                       .collect();
+            if aov == Aov::Depth {
+                let max = values.iter().map(|v| v[0]).fold(0., f64::max);
+                if max > 0. {
+                    for v in values.iter_mut() {
+                        v[0] /= max;
+                    }
+                }
+            }
+            guides.push(Guide { values: values, sigma: sigma });
+        }
+    }
+    guides
+}
+
+/// Returns a denoised copy of the film, the filter being guided by the
+/// normal, albedo and depth AOVs of the film if it holds them.
+pub fn denoise(film: &Film, settings: &DenoiseSettings) -> Film {
+    let (width, height) = film.resolution();
+    let guides = guides(film, settings);
+    let mut colors: Vec<Vec<f64>> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+                                               .map(|(x, y)| film.pixel(x, y).data.to_vec())
+                                               .collect();
+    let pixels: Vec<usize> = (0..colors.len()).collect();
+
+    for i in 0..settings.iterations {
+        let step = 1 << i;
+        let sigma_color = settings.sigma_color / (1 << i) as f64;
+        let next: Vec<Vec<f64>> = pixels.par_iter().map(|&p| {
+            let (px, py) = ((p % width as usize) as i64, (p / width as usize) as i64);
+            let mut sum = vec![0.; 3];
+            let mut weights = 0.;
+            for (ky, k_y) in KERNEL.iter().enumerate() {
+                for (kx, k_x) in KERNEL.iter().enumerate() {
+                    let qx = px + (kx as i64 - 2) * step;
+                    let qy = py + (ky as i64 - 2) * step;
+                    if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                        continue;
+                    }
+                    let q = (qy * width as i64 + qx) as usize;
+                    let mut w = k_x * k_y * (-distance2(&colors[p], &colors[q]) / (sigma_color * sigma_color)).exp();
+                    for g in guides.iter() {
+                        w *= (-distance2(&g.values[p], &g.values[q]) / (g.sigma * g.sigma)).exp();
+                    }
+                    for c in 0..3 {
+                        sum[c] += w * colors[q][c];
+                    }
+                    weights += w;
+                }
+            }
+            // The center pixel always has a weight, so weights is not 0
+            sum.iter().map(|s| s / weights).collect()
+        }).collect();
+        colors = next;
+    }
+
+    let mut denoised = film.clone();
+    for (p, color) in colors.iter().enumerate() {
+        let (x, y) = ((p % width as usize) as u32, (p / width as usize) as u32);
+        denoised.set_pixel(x, y, &Rgb { data: [color[0], color[1], color[2]] });
+    }
+    denoised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::filter_from_name;
+    use rand::Rng;
+    use util::seeded_rng;
+
+    #[test]
+    fn test_denoise_preserves_edges() {
+        let mut film = Film::with_aovs(16, 16, filter_from_name("box").unwrap(), &[Aov::Albedo]);
+        let mut rng = seeded_rng(&[0]);
+        let clean = |x: u32| if x < 8 { 0.2 } else { 0.8 };
+        for y in 0..16 {
+            for x in 0..16 {
+                let v = clean(x) + (rng.gen::<f64>() - 0.5) * 0.2;
+                film.add_sample((x as f64 + 0.5, y as f64 + 0.5), &Rgb { data: [v, v, v] }, 1.);
+                film.add_aov_sample(x, y, Aov::Albedo, &[clean(x), clean(x), clean(x)]);
+            }
+        }
+        let denoised = denoise(&film, &DenoiseSettings::new());
+        let error = |f: &Film| -> f64 {
+            (0..16).flat_map(|y| (0..16).map(move |x| (x, y)))
+                   .map(|(x, y)| (f.pixel(x, y)[0] - clean(x)).powi(2))
+                   .sum()
+        };
+        assert!(error(&denoised) < error(&film) / 4.);
+        assert!((denoised.pixel(7, 8)[0] - 0.2).abs() < 0.05);
+        assert!((denoised.pixel(8, 8)[0] - 0.8).abs() < 0.05);
+        assert!(denoised.alpha(3, 3) == 1.);
+    }
+}
@@ -0,0 +1,580 @@
+use image::*;
+use film::{add_aov_sample, aov_slots, Aov, AovBuffer, AovSlots, Filter, ToneMapping, to_u8};
+use raytracer::Tile;
+
+/// Per-pixel sample counts.
+pub type SampleCounts = ImageBuffer<Luma<u32>, Vec<u32>>;
+
+/// Accumulated filtered samples of a pixel. `alpha` accumulates the coverage
+/// of the samples, 1 for samples hitting the scene and 0 for the background.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilmPixel {
+    pub rgb: [f64; 3],
+    /// Colors of the samples weighted by their coverage as well, so that
+    /// misses add no color, as `alpha` is.
+    pub covered_rgb: [f64; 3],
+    pub alpha: f64,
+    pub weight: f64
+}
+
+/// Floating point image plane accumulating linear radiance samples through a
+/// reconstruction filter, along with their coverage and how many samples were
+/// taken per pixel. Conversion to a displayable image is a separate step.
+///
+/// Positions are in image coordinates: pixel `(x, y)` covers
+/// `[x, x + 1) × [y, y + 1)`, row 0 being the top of the image.
+///
+/// A film can also cover only a window of the image, e.g. a tile rendered in
+/// another process, to be merged into the full film later. Films of
+/// successive passes over the same image can be merged the same way.
+///
+/// AOVs are accumulated per pixel, samples only contributing to the pixel
+/// they are taken in.
+#[derive(Debug)]
+pub struct Film {
+    resolution: (u32, u32),
+    origin: (u32, u32),
+    size: (u32, u32),
+    pixels: Vec<FilmPixel>,
+    counts: Vec<u32>,
+    aovs: Vec<AovBuffer>,
+    aov_slots: AovSlots,
+    filter: Box<Filter>
+}
+
+/// Rows of a tile of a film, top to bottom, each borrowing its part of a row
+/// of the film.
+type Rows<'a, T> = Vec<&'a mut [T]>;
+
+/// Tile of a film borrowed to accumulate samples into, the tiles borrowed at
+/// once not sharing any pixel so that they can be rendered in parallel.
+/// Samples only contribute to the pixels of their tile, so the samples that
+/// the filter spreads across its edges must also be taken in the tile.
+pub struct FilmTile<'a> {
+    resolution: (u32, u32),
+    origin: (u32, u32),
+    size: (u32, u32),
+    filter: &'a Filter,
+    pixels: Rows<'a, FilmPixel>,
+    counts: Rows<'a, u32>,
+    aovs: Vec<(Aov, Rows<'a, f64>, Rows<'a, u32>)>,
+    aov_slots: AovSlots
+}
+
+impl Clone for Film {
+    fn clone(&self) -> Film {
+        Film { resolution: self.resolution, origin: self.origin, size: self.size,
+               pixels: self.pixels.clone(), counts: self.counts.clone(), aovs: self.aovs.clone(),
+               aov_slots: self.aov_slots, filter: self.filter.box_clone() }
+    }
+}
+
+impl FilmPixel {
+    pub fn new() -> FilmPixel {
+        FilmPixel { rgb: [0., 0., 0.], covered_rgb: [0., 0., 0.], alpha: 0., weight: 0. }
+    }
+
+    fn add(&mut self, weight: f64, color: &Rgb<f64>, alpha: f64) {
+        self.rgb[0] += weight * color[0];
+        self.rgb[1] += weight * color[1];
+        self.rgb[2] += weight * color[2];
+        self.covered_rgb[0] += weight * alpha * color[0];
+        self.covered_rgb[1] += weight * alpha * color[1];
+        self.covered_rgb[2] += weight * alpha * color[2];
+        self.alpha += weight * alpha;
+        self.weight += weight;
+    }
+
+    /// Returns the weighted average of the samples, black if there are none.
+    pub fn color(&self) -> Rgb<f64> {
+        match self.weight.abs() < 1e-12 {
+            true => Rgb { data: [0., 0., 0.] },
+            false => Rgb { data: [self.rgb[0] / self.weight,
+                                  self.rgb[1] / self.weight,
+                                  self.rgb[2] / self.weight] }
+        }
+    }
+
+    /// Returns the weighted average color of the samples hitting the scene,
+    /// black if there are none.
+    pub fn covered_color(&self) -> Rgb<f64> {
+        match self.alpha.abs() < 1e-12 {
+            true => Rgb { data: [0., 0., 0.] },
+            false => Rgb { data: [self.covered_rgb[0] / self.alpha,
+                                  self.covered_rgb[1] / self.alpha,
+                                  self.covered_rgb[2] / self.alpha] }
+        }
+    }
+
+    /// Returns the weighted average coverage of the samples.
+    pub fn alpha(&self) -> f64 {
+        match self.weight.abs() < 1e-12 {
+            true => 0.,
+            false => self.alpha / self.weight
+        }
+    }
+}
+
+/// Calls `f` with every pixel of the `size` window from `origin` within the
+/// filter radius of a sample at `position`, and the weight of the sample there.
+fn splat<F>(filter: &Filter, origin: (u32, u32), size: (u32, u32), position: (f64, f64), mut f: F)
+    where F: FnMut(u32, u32, f64)
+{
+    let (px, py) = (position.0 - 0.5, position.1 - 0.5);
+    let (rx, ry) = filter.radius();
+    let x0 = (px - rx).ceil().max(origin.0 as f64);
+    let y0 = (py - ry).ceil().max(origin.1 as f64);
+    let x1 = (px + rx).floor().min((origin.0 + size.0) as f64 - 1.);
+    let y1 = (py + ry).floor().min((origin.1 + size.1) as f64 - 1.);
+    if x1 < x0 || y1 < y0 {
+        return;
+    }
+    for y in (y0 as u32)..(y1 as u32 + 1) {
+        for x in (x0 as u32)..(x1 as u32 + 1) {
+            f(x, y, filter.evaluate((x as f64 - px, y as f64 - py)));
+        }
+    }
+}
+
+/// Splits the rows of `n` values per pixel of the `size` window from `origin`
+/// of an image into the rows of each of `tiles`.
+fn split_rows<'a, T>(data: &'a mut [T], origin: (u32, u32), size: (u32, u32), n: usize, tiles: &[Tile])
+                     -> Vec<Rows<'a, T>> {
+    let mut rows: Vec<Rows<T>> = tiles.iter().map(|_| vec!()).collect();
+    if size.0 == 0 {
+        return rows;
+    }
+    let mut by_x: Vec<usize> = (0..tiles.len()).collect();
+    by_x.sort_by_key(|&i| tiles[i].x0);
+    for (y, row) in data.chunks_mut(size.0 as usize * n).enumerate() {
+        let y = origin.1 + y as u32;
+        let (mut rest, mut x) = (row, origin.0);
+        for &i in by_x.iter().filter(|&&i| tiles[i].y0 <= y && y < tiles[i].y1) {
+            let tile = &tiles[i];
+            assert!(x <= tile.x0, "overlapping tiles");
+            let (_, tail) = {rest}.split_at_mut((tile.x0 - x) as usize * n);
+            let (segment, tail) = tail.split_at_mut(tile.width() as usize * n);
+            rows[i].push(segment);
+            rest = tail;
+            x = tile.x1;
+        }
+    }
+    rows
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Box<Filter>) -> Film {
+        Film::with_aovs(width, height, filter, &[])
+    }
+
+    /// Returns a film also accumulating the given AOVs.
+    pub fn with_aovs(width: u32, height: u32, filter: Box<Filter>, aovs: &[Aov]) -> Film {
+        Film::window(width, height, (0, 0), (width, height), filter, aovs)
+    }
+
+    /// Returns a film of the image covering none of its pixels, to make
+    /// tiles from without allocating the whole image.
+    pub fn without_pixels(width: u32, height: u32, filter: Box<Filter>, aovs: &[Aov]) -> Film {
+        Film::window(width, height, (0, 0), (0, 0), filter, aovs)
+    }
+
+    fn window(width: u32, height: u32, origin: (u32, u32), size: (u32, u32), filter: Box<Filter>,
+              aovs: &[Aov]) -> Film {
+        let n_pixels = (size.0 as usize).checked_mul(size.1 as usize).expect("Film too large");
+        Film { resolution: (width, height), origin: origin, size: size,
+               pixels: vec![FilmPixel::new(); n_pixels],
+               counts: vec![0; n_pixels],
+               aovs: aovs.iter().map(|aov| AovBuffer::new(*aov, n_pixels)).collect(),
+               aov_slots: aov_slots(aovs), filter: filter }
+    }
+
+    /// Rebuilds a film covering the `size` pixels from `origin` of a
+    /// `resolution` image from the pixels, sample counts and AOV buffers of
+    /// another one, e.g. read back from a checkpoint.
+    pub fn from_parts(resolution: (u32, u32), origin: (u32, u32), size: (u32, u32), filter: Box<Filter>,
+                      pixels: Vec<FilmPixel>, counts: Vec<u32>, aovs: Vec<AovBuffer>) -> Film {
+        let n_pixels = (size.0 as usize).checked_mul(size.1 as usize).expect("Film too large");
+        assert!(origin.0 + size.0 <= resolution.0 && origin.1 + size.1 <= resolution.1);
+        assert!(pixels.len() == n_pixels && counts.len() == n_pixels);
+        assert!(aovs.iter().all(|b| b.counts().len() == n_pixels));
+        let slots = aov_slots(&aovs.iter().map(|b| b.aov()).collect::<Vec<_>>());
+        Film { resolution: resolution, origin: origin, size: size,
+               pixels: pixels, counts: counts, aovs: aovs, aov_slots: slots, filter: filter }
+    }
+
+    /// Returns an empty film covering the pixels of `[x0, x1) × [y0, y1)`,
+    /// e.g. to render a tile on its own.
+    pub fn tile(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> Film {
+        assert!(x0 <= x1 && x1 <= self.resolution.0 && y0 <= y1 && y1 <= self.resolution.1);
+        Film::window(self.resolution.0, self.resolution.1, (x0, y0), (x1 - x0, y1 - y0),
+                     self.filter.box_clone(), &self.aovs())
+    }
+
+    /// Borrows the pixels of each of `tiles`, which must not overlap and be
+    /// within the window covered by the film, to render them in parallel.
+    pub fn tiles_mut(&mut self, tiles: &[Tile]) -> Vec<FilmTile> {
+        let (resolution, origin, size, slots) = (self.resolution, self.origin, self.size, self.aov_slots);
+        assert!(tiles.iter().all(|t| origin.0 <= t.x0 && t.x1 <= origin.0 + size.0 &&
+                                     origin.1 <= t.y0 && t.y1 <= origin.1 + size.1));
+        let filter = &*self.filter;
+        let mut pixels = split_rows(&mut self.pixels[..], origin, size, 1, tiles).into_iter();
+        let mut counts = split_rows(&mut self.counts[..], origin, size, 1, tiles).into_iter();
+        let mut aovs: Vec<_> = self.aovs.iter_mut().map(|buffer| {
+            let aov = buffer.aov();
+            let (values, counts) = buffer.parts_mut();
+            (aov, split_rows(values, origin, size, aov.components(), tiles).into_iter(),
+             split_rows(counts, origin, size, 1, tiles).into_iter())
+        }).collect();
+        tiles.iter().map(|t| {
+            FilmTile { resolution: resolution, origin: (t.x0, t.y0), size: (t.width(), t.height()), filter: filter,
+                       pixels: pixels.next().unwrap(), counts: counts.next().unwrap(),
+                       aovs: aovs.iter_mut().map(|&mut (aov, ref mut values, ref mut counts)| {
+                           (aov, values.next().unwrap(), counts.next().unwrap())
+                       }).collect(),
+                       aov_slots: slots }
+        }).collect()
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    /// Returns the first pixel of the window covered by the film.
+    pub fn origin(&self) -> (u32, u32) {
+        self.origin
+    }
+
+    /// Returns the size of the window covered by the film.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn filter(&self) -> &Box<Filter> {
+        &self.filter
+    }
+
+    pub fn aovs(&self) -> Vec<Aov> {
+        self.aovs.iter().map(|b| b.aov()).collect()
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        let (ox, oy) = self.origin;
+        let (w, h) = self.size;
+        match x >= ox && y >= oy && x < ox + w && y < oy + h {
+            true => Some((y - oy) as usize * w as usize + (x - ox) as usize),
+            false => None
+        }
+    }
+
+    /// Splats a sample into every pixel of the film within the filter radius.
+    pub fn add_sample(&mut self, position: (f64, f64), color: &Rgb<f64>, alpha: f64) {
+        let ((ox, oy), width) = (self.origin, self.size.0);
+        let pixels = &mut self.pixels;
+        splat(&*self.filter, self.origin, self.size, position, |x, y, w| {
+            pixels[(y - oy) as usize * width as usize + (x - ox) as usize].add(w, color, alpha);
+        });
+    }
+
+    /// Records that `n` more samples were taken in the given pixel.
+    pub fn add_sample_count(&mut self, x: u32, y: u32, n: u32) {
+        if let Some(i) = self.index(x, y) {
+            self.counts[i] += n;
+        }
+    }
+
+    /// Adds a sample of an AOV to a pixel. Does nothing if the film does not
+    /// hold the AOV.
+    pub fn add_aov_sample(&mut self, x: u32, y: u32, aov: Aov, value: &[f64]) {
+        if let (Some(i), Some(k)) = (self.index(x, y), self.aov_slots[aov.index()]) {
+            self.aovs[k].add(i, value);
+        }
+    }
+
+    /// Adds the samples accumulated in another film, typically a tile.
+    pub fn merge(&mut self, other: &Film) {
+        let (ox, oy) = other.origin;
+        let (w, h) = other.size;
+        let sources: Vec<Option<usize>> = self.aovs.iter().map(|b| other.aov_slots[b.aov().index()]).collect();
+        for y in oy..(oy + h) {
+            for x in ox..(ox + w) {
+                if let (Some(i), Some(j)) = (self.index(x, y), other.index(x, y)) {
+                    let src = other.pixels[j];
+                    let dst = &mut self.pixels[i];
+                    for c in 0..3 {
+                        dst.rgb[c] += src.rgb[c];
+                        dst.covered_rgb[c] += src.covered_rgb[c];
+                    }
+                    dst.alpha += src.alpha;
+                    dst.weight += src.weight;
+                    self.counts[i] += other.counts[j];
+                    for (buffer, source) in self.aovs.iter_mut().zip(sources.iter()) {
+                        if let Some(k) = *source {
+                            buffer.merge(i, &other.aovs[k], j);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the accumulated pixels of the window covered by the film, row
+    /// by row.
+    pub fn film_pixels(&self) -> &[FilmPixel] {
+        &self.pixels
+    }
+
+    /// Returns the sample counts of the window covered by the film.
+    pub fn pixel_counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    pub fn aov_buffers(&self) -> &[AovBuffer] {
+        &self.aovs
+    }
+
+    pub fn film_pixel(&self, x: u32, y: u32) -> FilmPixel {
+        self.index(x, y).map_or(FilmPixel::new(), |i| self.pixels[i])
+    }
+
+    /// Returns the reconstructed color of a pixel.
+    pub fn pixel(&self, x: u32, y: u32) -> Rgb<f64> {
+        self.film_pixel(x, y).color()
+    }
+
+    pub fn alpha(&self, x: u32, y: u32) -> f64 {
+        self.film_pixel(x, y).alpha()
+    }
+
+    /// Replaces the color of a pixel, keeping its weight and coverage. The
+    /// color of its covered part becomes the same.
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: &Rgb<f64>) {
+        if let Some(i) = self.index(x, y) {
+            let p = &mut self.pixels[i];
+            if p.weight.abs() < 1e-12 {
+                p.weight = 1.;
+            }
+            for c in 0..3 {
+                p.rgb[c] = color[c] * p.weight;
+                p.covered_rgb[c] = color[c] * p.alpha;
+            }
+        }
+    }
+
+    /// Returns the average value of an AOV in a pixel, if the film holds it.
+    pub fn aov_pixel(&self, x: u32, y: u32, aov: Aov) -> Option<Vec<f64>> {
+        let buffer = self.aov_slots[aov.index()].map(|k| &self.aovs[k]);
+        match (self.index(x, y), buffer) {
+            (Some(i), Some(b)) => Some(b.value(i)),
+            (None, Some(_)) => Some(vec![0.; aov.components()]),
+            (_, None) => None
+        }
+    }
+
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.index(x, y).map_or(0, |i| self.counts[i])
+    }
+
+    pub fn sample_counts(&self) -> SampleCounts {
+        let (width, height) = self.resolution;
+        ImageBuffer::from_fn(width, height, |x, y| Luma { data: [self.sample_count(x, y)] })
+    }
+
+    /// Converts the film to an 8 bits display image.
+    pub fn to_rgb_image(&self, tone: &ToneMapping) -> RgbImage {
+        let (width, height) = self.resolution;
+        ImageBuffer::from_fn(width, height, |x, y| tone.map_to_u8(&self.pixel(x, y)))
+    }
+
+    /// Converts the film to an 8 bits display image with its coverage as alpha.
+    /// Samples missing the scene add neither color nor coverage, so the
+    /// background does not show through partially covered pixels. Colors
+    /// are not premultiplied.
+    pub fn to_rgba_image(&self, tone: &ToneMapping) -> RgbaImage {
+        let (width, height) = self.resolution;
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let p = self.film_pixel(x, y);
+            let c = tone.map_to_u8(&p.covered_color());
+            Rgba { data: [c[0], c[1], c[2], to_u8(p.alpha())] }
+        })
+    }
+}
+
+impl<'a> FilmTile<'a> {
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    /// Returns the row and column of a pixel in the tile.
+    fn index(&self, x: u32, y: u32) -> Option<(usize, usize)> {
+        let (ox, oy) = self.origin;
+        match x >= ox && y >= oy && x < ox + self.size.0 && y < oy + self.size.1 {
+            true => Some(((y - oy) as usize, (x - ox) as usize)),
+            false => None
+        }
+    }
+
+    /// Splats a sample into every pixel of the tile within the filter radius.
+    pub fn add_sample(&mut self, position: (f64, f64), color: &Rgb<f64>, alpha: f64) {
+        let (ox, oy) = self.origin;
+        let pixels = &mut self.pixels;
+        splat(self.filter, self.origin, self.size, position, |x, y, w| {
+            pixels[(y - oy) as usize][(x - ox) as usize].add(w, color, alpha);
+        });
+    }
+
+    /// Records that `n` more samples were taken in the given pixel, if it is
+    /// in the tile.
+    pub fn add_sample_count(&mut self, x: u32, y: u32, n: u32) {
+        if let Some((row, column)) = self.index(x, y) {
+            self.counts[row][column] += n;
+        }
+    }
+
+    /// Adds a sample of an AOV to a pixel. Does nothing if the pixel is out
+    /// of the tile or the film does not hold the AOV.
+    pub fn add_aov_sample(&mut self, x: u32, y: u32, aov: Aov, value: &[f64]) {
+        if let (Some((row, column)), Some(k)) = (self.index(x, y), self.aov_slots[aov.index()]) {
+            let (_, ref mut values, ref mut counts) = self.aovs[k];
+            let n = aov.components();
+            add_aov_sample(aov, &mut values[row][column * n..(column + 1) * n], &mut counts[row][column], value);
+        }
+    }
+}
+
+/// Whether two films hold exactly the same pixels, for tests comparing renders.
+#[cfg(test)]
+pub fn same_image(a: &Film, b: &Film) -> bool {
+    let (width, height) = a.resolution();
+    b.resolution() == (width, height) &&
+        (0..height).all(|y| (0..width).all(|x| a.film_pixel(x, y) == b.film_pixel(x, y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::*;
+
+    fn white() -> Rgb<f64> {
+        Rgb { data: [1., 1., 1.] }
+    }
+
+    #[test]
+    fn test_box_filter_stays_in_pixel() {
+        let mut film = Film::new(4, 4, filter_from_name("box").unwrap());
+        film.add_sample((1.3, 2.6), &white(), 1.);
+        assert!(film.film_pixel(1, 2).weight == 1.);
+        assert!(film.film_pixel(0, 2).weight == 0.);
+        assert!(film.film_pixel(1, 1).weight == 0.);
+        assert!(film.pixel(1, 2) == white());
+    }
+
+    #[test]
+    fn test_tent_filter_splats_neighbours() {
+        let mut film = Film::new(4, 4, filter_from_name("tent").unwrap());
+        film.add_sample((1.5, 1.5), &white(), 1.);
+        film.add_sample((2.2, 1.5), &Rgb { data: [0., 0., 0.] }, 0.);
+        assert!(film.film_pixel(2, 1).weight > 0.);
+        let c = film.pixel(1, 1)[0];
+        assert!(0.5 < c && c < 1.);
+        assert!(film.alpha(1, 1) == c);
+    }
+
+    #[test]
+    fn test_rgba_image_ignores_misses() {
+        let mut film = Film::new(1, 1, filter_from_name("box").unwrap());
+        film.add_sample((0.25, 0.5), &Rgb { data: [0., 0., 1.] }, 0.);
+        film.add_sample((0.75, 0.5), &Rgb { data: [1., 0., 0.] }, 1.);
+        assert!(film.film_pixel(0, 0).covered_color() == Rgb { data: [1., 0., 0.] });
+        let image = film.to_rgba_image(&ToneMapping::default());
+        assert!(image.get_pixel(0, 0).data == [255, 0, 0, 128]);
+    }
+
+    #[test]
+    fn test_radiance_not_clamped() {
+        let mut film = Film::new(2, 2, filter_from_name("box").unwrap());
+        film.add_sample((0.5, 0.5), &Rgb { data: [4., 2., 0.5] }, 1.);
+        assert!(film.pixel(0, 0) == Rgb { data: [4., 2., 0.5] });
+        let image = film.to_rgb_image(&ToneMapping::default());
+        assert!(image.get_pixel(0, 0).data == [255, 255, 188]);
+    }
+
+    #[test]
+    fn test_aovs_merge() {
+        let mut film = Film::with_aovs(4, 4, filter_from_name("tent").unwrap(), &[Aov::Depth]);
+        let mut tile = film.tile(0, 2, 4, 4);
+        tile.add_aov_sample(1, 3, Aov::Depth, &[2.]);
+        tile.add_aov_sample(1, 3, Aov::Depth, &[4.]);
+        tile.add_aov_sample(1, 3, Aov::Normal, &[0., 0., 1.]);
+        film.merge(&tile);
+        assert!(film.aovs() == vec!(Aov::Depth));
+        assert!(film.aov_pixel(1, 3, Aov::Depth) == Some(vec!(3.)));
+        assert!(film.aov_pixel(1, 2, Aov::Depth) == Some(vec!(0.)));
+        assert!(film.aov_pixel(1, 3, Aov::Normal).is_none());
+    }
+
+    #[test]
+    fn test_tiles_mut() {
+        let mut film = Film::with_aovs(5, 4, filter_from_name("tent").unwrap(), &[Aov::Depth]);
+        let mut reference = Film::with_aovs(5, 4, filter_from_name("tent").unwrap(), &[Aov::Depth]);
+        let tiles = [Tile { x0: 0, y0: 0, x1: 3, y1: 2, column: 0, row: 0 },
+                     Tile { x0: 3, y0: 0, x1: 5, y1: 2, column: 1, row: 0 },
+                     Tile { x0: 0, y0: 2, x1: 3, y1: 4, column: 0, row: 1 },
+                     Tile { x0: 3, y0: 2, x1: 5, y1: 4, column: 1, row: 1 }];
+        let samples = [(0.5, 0.5), (2.9, 1.9), (3.1, 2.4), (4.5, 3.5)];
+        for &p in samples.iter() {
+            reference.add_sample(p, &white(), 1.);
+        }
+        reference.add_sample_count(3, 2, 2);
+        reference.add_aov_sample(3, 2, Aov::Depth, &[2.]);
+        for tile in film.tiles_mut(&tiles).iter_mut() {
+            for &p in samples.iter() {
+                tile.add_sample(p, &white(), 1.);
+            }
+            tile.add_sample_count(3, 2, 2);
+            tile.add_aov_sample(3, 2, Aov::Depth, &[2.]);
+        }
+        for y in 0..4 {
+            for x in 0..5 {
+                assert!(film.film_pixel(x, y) == reference.film_pixel(x, y));
+                assert!(film.sample_count(x, y) == reference.sample_count(x, y));
+            }
+        }
+        assert!(film.aov_pixel(3, 2, Aov::Depth) == Some(vec!(2.)));
+    }
+
+    #[test]
+    fn test_normalized_constant() {
+        for name in ["box", "tent", "gaussian", "mitchell", "lanczos"].iter() {
+            let mut film = Film::new(8, 8, filter_from_name(name).unwrap());
+            for i in 0..64 {
+                film.add_sample(((i % 8) as f64 + 0.3, (i / 8) as f64 + 0.7), &white(), 1.);
+            }
+            let c = film.pixel(4, 4);
+            assert!((c[0] - 1.).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_merge_tiles() {
+        let mut film = Film::new(4, 4, filter_from_name("tent").unwrap());
+        let mut reference = Film::new(4, 4, filter_from_name("tent").unwrap());
+        let (mut top, mut bottom) = (film.tile(0, 0, 4, 2), film.tile(0, 2, 4, 4));
+        assert!(top.origin() == (0, 0) && top.size() == (4, 2));
+        // Samples near the edge between the tiles go in both
+        for &p in [(0.5, 0.5), (1.7, 1.9), (3.2, 0.1), (2.5, 2.2)].iter() {
+            top.add_sample(p, &white(), 1.);
+            bottom.add_sample(p, &white(), 1.);
+            reference.add_sample(p, &white(), 1.);
+        }
+        top.add_sample_count(1, 1, 3);
+        film.merge(&top);
+        film.merge(&bottom);
+        assert!(film.sample_count(1, 1) == 3);
+        assert!(film.aov_pixel(1, 1, Aov::Depth).is_none());
+        for y in 0..4 {
+            for x in 0..4 {
+                assert!(film.film_pixel(x, y) == reference.film_pixel(x, y));
+            }
+        }
+    }
+}
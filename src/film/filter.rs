@@ -0,0 +1,235 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+/// Pixel reconstruction filter, weighting the contribution of a sample to the
+/// pixels around it.
+pub trait Filter: Debug + Sync + Send {
+    /// Half extent of the support of the filter along x and y, in pixels.
+    fn radius(&self) -> (f64, f64);
+
+    /// Weight of a sample at the given offset from a pixel center. Offsets
+    /// at or outside of the radius have a weight of 0.
+    fn evaluate(&self, offset: (f64, f64)) -> f64;
+
+    fn box_clone(&self) -> Box<Filter>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxFilter {
+    radius: (f64, f64)
+}
+
+/// Triangle filter, falling off linearly to 0 at its radius.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TentFilter {
+    radius: (f64, f64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaussianFilter {
+    radius: (f64, f64),
+    alpha: f64,
+    exp: (f64, f64)
+}
+
+/// Mitchell-Netravali cubic filter. The default `B = C = 1/3` trades ringing
+/// for blurring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MitchellFilter {
+    radius: (f64, f64),
+    b: f64,
+    c: f64
+}
+
+/// Windowed sinc filter, `tau` being the number of sinc lobes in the window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanczosFilter {
+    radius: (f64, f64),
+    tau: f64
+}
+
+impl BoxFilter {
+    pub fn new(radius: (f64, f64)) -> BoxFilter {
+        BoxFilter { radius: radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> (f64, f64) { self.radius }
+
+    fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        match offset.0.abs() < self.radius.0 && offset.1.abs() < self.radius.1 {
+            true => 1.,
+            false => 0.
+        }
+    }
+
+    fn box_clone(&self) -> Box<Filter> {
+        Box::new(self.clone())
+    }
+}
+
+impl TentFilter {
+    pub fn new(radius: (f64, f64)) -> TentFilter {
+        TentFilter { radius: radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> (f64, f64) { self.radius }
+
+    fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        (self.radius.0 - offset.0.abs()).max(0.) * (self.radius.1 - offset.1.abs()).max(0.)
+    }
+
+    fn box_clone(&self) -> Box<Filter> {
+        Box::new(self.clone())
+    }
+}
+
+impl GaussianFilter {
+    pub fn new(radius: (f64, f64), alpha: f64) -> GaussianFilter {
+        GaussianFilter { radius: radius, alpha: alpha,
+                         exp: ((-alpha * radius.0 * radius.0).exp(), (-alpha * radius.1 * radius.1).exp()) }
+    }
+
+    /// Gaussian shifted down to reach 0 at the edge of the support.
+    fn gaussian(&self, d: f64, exp: f64) -> f64 {
+        ((-self.alpha * d * d).exp() - exp).max(0.)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> (f64, f64) { self.radius }
+
+    fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        self.gaussian(offset.0, self.exp.0) * self.gaussian(offset.1, self.exp.1)
+    }
+
+    fn box_clone(&self) -> Box<Filter> {
+        Box::new(self.clone())
+    }
+}
+
+impl MitchellFilter {
+    pub fn new(radius: (f64, f64), b: f64, c: f64) -> MitchellFilter {
+        MitchellFilter { radius: radius, b: b, c: c }
+    }
+
+    /// Evaluates the 1D filter for `x` in [-1, 1].
+    fn mitchell(&self, x: f64) -> f64 {
+        let x = (2. * x).abs();
+        let (b, c) = (self.b, self.c);
+        let v = match x > 1. {
+            true => (-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x +
+                    (-12. * b - 48. * c) * x + (8. * b + 24. * c),
+            false => (12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x +
+                     (6. - 2. * b)
+        };
+        match x > 2. {
+            true => 0.,
+            false => v / 6.
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> (f64, f64) { self.radius }
+
+    fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        self.mitchell(offset.0 / self.radius.0) * self.mitchell(offset.1 / self.radius.1)
+    }
+
+    fn box_clone(&self) -> Box<Filter> {
+        Box::new(self.clone())
+    }
+}
+
+impl LanczosFilter {
+    pub fn new(radius: (f64, f64), tau: f64) -> LanczosFilter {
+        LanczosFilter { radius: radius, tau: tau }
+    }
+
+    /// Evaluates the 1D filter for `x` in [-1, 1].
+    fn windowed_sinc(&self, x: f64) -> f64 {
+        let x = x.abs();
+        match x > 1. {
+            true => 0.,
+            false => sinc(x * self.tau) * sinc(x)
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> (f64, f64) { self.radius }
+
+    fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        self.windowed_sinc(offset.0 / self.radius.0) * self.windowed_sinc(offset.1 / self.radius.1)
+    }
+
+    fn box_clone(&self) -> Box<Filter> {
+        Box::new(self.clone())
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    match x.abs() < 1e-5 {
+        true => 1.,
+        false => (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Builds a filter with its usual parameters from its name, one of `box`,
+/// `tent`, `gaussian`, `mitchell` or `lanczos`.
+pub fn filter_from_name(name: &str) -> Option<Box<Filter>> {
+    match name {
+        "box" => Some(Box::new(BoxFilter::new((0.5, 0.5)))),
+        "tent" => Some(Box::new(TentFilter::new((1., 1.)))),
+        "gaussian" => Some(Box::new(GaussianFilter::new((1.5, 1.5), 2.))),
+        "mitchell" => Some(Box::new(MitchellFilter::new((2., 2.), 1. / 3., 1. / 3.))),
+        "lanczos" => Some(Box::new(LanczosFilter::new((3., 3.), 3.))),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_filters() -> Vec<Box<Filter>> {
+        ["box", "tent", "gaussian", "mitchell", "lanczos"].iter()
+                                                          .map(|n| filter_from_name(n).unwrap())
+                                                          .collect()
+    }
+
+    #[test]
+    fn test_filters_peak_at_center() {
+        for f in all_filters() {
+            let center = f.evaluate((0., 0.));
+            assert!(center > 0.);
+            assert!(f.evaluate((0.3, 0.2)) <= center);
+        }
+    }
+
+    #[test]
+    fn test_filters_zero_outside_radius() {
+        for f in all_filters() {
+            let (rx, ry) = f.radius();
+            assert!(f.evaluate((rx + 0.01, 0.)) == 0.);
+            assert!(f.evaluate((0., -ry - 0.01)) == 0.);
+            assert!(f.evaluate((rx, 0.)).abs() < 1e-12 && f.evaluate((0., -ry)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_box_filter_excludes_radius() {
+        let f = filter_from_name("box").unwrap();
+        assert!(f.evaluate((0.49, -0.49)) == 1.);
+        assert!(f.evaluate((0.5, 0.)) == 0. && f.evaluate((0., -0.5)) == 0.);
+    }
+
+    #[test]
+    fn test_unknown_filter() {
+        assert!(filter_from_name("sharp").is_none());
+    }
+}
@@ -0,0 +1,11 @@
+mod aov;
+mod denoise;
+mod film;
+mod filter;
+mod tonemap;
+
+pub use self::aov::*;
+pub use self::denoise::*;
+pub use self::film::*;
+pub use self::filter::*;
+pub use self::tonemap::*;
@@ -0,0 +1,206 @@
+use image::Rgb;
+use nalgebra::clamp;
+use util::*;
+
+/// Curve compressing scene radiance into displayable [0, 1] colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapOperator {
+    /// Leaves the radiance untouched, clipping it at 1.
+    Clamp,
+    /// Reinhard's global operator `L / (1 + L)` on the luminance.
+    Reinhard,
+    /// Reinhard's operator extended to map the `white` luminance to 1.
+    ReinhardExtended { white: f64 },
+    /// Narkowicz's fit of the ACES filmic reference rendering transform.
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2, scaled so that `white`
+    /// maps to 1.
+    Hable { white: f64 }
+}
+
+/// Conversion of linear radiance to display colors. The radiance is scaled by
+/// the exposure, compressed by the operator and encoded with the sRGB
+/// transfer function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    /// Exposure compensation in stops, each stop doubling the radiance.
+    pub exposure: f64,
+    pub operator: ToneMapOperator
+}
+
+/// sRGB opto-electronic transfer function, from linear [0, 1] values to
+/// encoded ones.
+pub fn srgb_oetf(c: f64) -> f64 {
+    match c <= 0.0031308 {
+        true => 12.92 * c,
+        false => 1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_oetf`.
+pub fn srgb_eotf(c: f64) -> f64 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Scales a color so that its luminance `l` becomes `f(l)`.
+fn map_luminance<F: Fn(f64) -> f64>(c: &Rgb<f64>, f: F) -> Rgb<f64> {
+    let l = rgb_luminance(c);
+    match l <= 0. {
+        true => Rgb { data: [0., 0., 0.] },
+        false => rgb_mul(c, f(l) / l)
+    }
+}
+
+fn aces(x: f64) -> f64 {
+    let x = 0.6 * x;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+impl ToneMapOperator {
+    /// Maps linear radiance to linear display colors in [0, 1].
+    pub fn apply(&self, c: &Rgb<f64>) -> Rgb<f64> {
+        let c = c.map(|v| v.max(0.));
+        let mapped = match *self {
+            ToneMapOperator::Clamp => c,
+            ToneMapOperator::Reinhard => map_luminance(&c, |l| l / (1. + l)),
+            ToneMapOperator::ReinhardExtended { white } => {
+                map_luminance(&c, |l| l * (1. + l / (white * white)) / (1. + l))
+            }
+            ToneMapOperator::Aces => c.map(aces),
+            ToneMapOperator::Hable { white } => {
+                // Hable's exposure bias of 2
+                let scale = hable(2. * white);
+                c.map(|v| hable(2. * v) / scale)
+            }
+        };
+        rgb_clamp_0_1(&mapped)
+    }
+
+    /// Returns the operator with the given white point, for the operators
+    /// that have one.
+    pub fn with_white(&self, white: f64) -> ToneMapOperator {
+        match *self {
+            ToneMapOperator::ReinhardExtended { .. } => ToneMapOperator::ReinhardExtended { white: white },
+            ToneMapOperator::Hable { .. } => ToneMapOperator::Hable { white: white },
+            op => op
+        }
+    }
+}
+
+/// Builds an operator with its usual parameters from its name, one of `clamp`,
+/// `reinhard`, `reinhard-extended`, `aces` or `hable`.
+pub fn tone_operator_from_name(name: &str) -> Option<ToneMapOperator> {
+    match name {
+        "clamp" => Some(ToneMapOperator::Clamp),
+        "reinhard" => Some(ToneMapOperator::Reinhard),
+        "reinhard-extended" => Some(ToneMapOperator::ReinhardExtended { white: 4. }),
+        "aces" => Some(ToneMapOperator::Aces),
+        "hable" => Some(ToneMapOperator::Hable { white: 11.2 }),
+        _ => None
+    }
+}
+
+impl ToneMapping {
+    pub fn new(operator: ToneMapOperator, exposure: f64) -> ToneMapping {
+        ToneMapping { exposure: exposure, operator: operator }
+    }
+
+    /// Maps linear radiance to linear display colors in [0, 1].
+    pub fn map_linear(&self, c: &Rgb<f64>) -> Rgb<f64> {
+        self.operator.apply(&rgb_mul(c, 2f64.powf(self.exposure)))
+    }
+
+    /// Maps linear radiance to sRGB encoded display colors in [0, 1].
+    pub fn map(&self, c: &Rgb<f64>) -> Rgb<f64> {
+        self.map_linear(c).map(srgb_oetf)
+    }
+
+    pub fn map_to_u8(&self, c: &Rgb<f64>) -> Rgb<u8> {
+        let c = self.map(c);
+        Rgb { data: [to_u8(c[0]), to_u8(c[1]), to_u8(c[2])] }
+    }
+}
+
+impl Default for ToneMapping {
+    /// Clips the radiance at 1 without exposure compensation.
+    fn default() -> ToneMapping {
+        ToneMapping::new(ToneMapOperator::Clamp, 0.)
+    }
+}
+
+/// Quantizes a [0, 1] value to 8 bits, rounding to the nearest level.
+pub fn to_u8(c: f64) -> u8 {
+    (clamp(c, 0., 1.) * 255. + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(v: f64) -> Rgb<f64> {
+        Rgb { data: [v, v, v] }
+    }
+
+    fn all_operators() -> Vec<ToneMapOperator> {
+        ["clamp", "reinhard", "reinhard-extended", "aces", "hable"].iter()
+                                                                   .map(|n| tone_operator_from_name(n).unwrap())
+                                                                   .collect()
+    }
+
+    #[test]
+    fn test_srgb() {
+        assert!(srgb_oetf(0.) == 0.);
+        assert!((srgb_oetf(1.) - 1.).abs() < 1e-12);
+        assert!((srgb_oetf(0.5) - 0.735357).abs() < 1e-5);
+        // Both segments meet at the threshold
+        assert!((12.92 * 0.0031308 - (1.055 * (0.0031308f64).powf(1. / 2.4) - 0.055)).abs() < 1e-6);
+        for &c in [0.001, 0.2, 0.8].iter() {
+            assert!((srgb_eotf(srgb_oetf(c)) - c).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_operators_in_range_and_monotonic() {
+        for op in all_operators() {
+            let mut previous = -1.;
+            for i in 0..100 {
+                let v = op.apply(&grey(i as f64 * 0.5))[0];
+                assert!(0. <= v && v <= 1. && v >= previous);
+                previous = v;
+            }
+            assert!(op.apply(&grey(-1.)) == grey(0.));
+        }
+    }
+
+    #[test]
+    fn test_operator_white_points() {
+        assert!((ToneMapOperator::Reinhard.apply(&grey(1.))[0] - 0.5).abs() < 1e-12);
+        let extended = tone_operator_from_name("reinhard-extended").unwrap().with_white(8.);
+        assert!((extended.apply(&grey(8.))[0] - 1.).abs() < 1e-12);
+        let hable = tone_operator_from_name("hable").unwrap();
+        assert!((hable.apply(&grey(11.2))[0] - 1.).abs() < 1e-12);
+        assert!(hable.apply(&grey(5.6))[0] < 1.);
+        assert!(tone_operator_from_name("drago").is_none());
+    }
+
+    #[test]
+    fn test_exposure() {
+        let tone = ToneMapping::new(ToneMapOperator::Clamp, 1.);
+        assert!(tone.map_linear(&grey(0.25)) == grey(0.5));
+        assert!(ToneMapping::default().map_to_u8(&grey(2.)).data == [255, 255, 255]);
+        assert!(ToneMapping::default().map_to_u8(&grey(0.5)).data == [188, 188, 188]);
+    }
+}
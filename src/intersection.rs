@@ -1,6 +1,9 @@
 use nalgebra::*;
+use algebra::Transform;
+use material::Material;
 use objects::*;
 use ray::Ray;
+use std::sync::Arc;
 use util::filter_nones;
 
 #[derive(Clone)]
@@ -8,21 +11,31 @@ pub struct Intersection<'a> {
     pub position: Point3<f64>,
     pub distance: f64,
     pub normal: Vector3<f64>,
+    /// Face hit when the object is a box.
+    pub face: Option<BoxFace>,
     pub ray: Ray,
-    pub object: &'a Object
+    pub object: &'a Object,
+    /// Identifier of the object in the scene intersected, 0 when the object
+    /// was intersected on its own.
+    pub object_id: u32
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new(position: Point3<f64>, distance: f64, normal: Vector3<f64>,
+    pub fn new(position: Point3<f64>, distance: f64, normal: Vector3<f64>, face: Option<BoxFace>,
                ray: Ray, object: &'a Object) -> Intersection<'a> {
-        Intersection { position: position, distance: distance, normal: normal,
-                       ray: ray, object: object }
+        Intersection { position: position, distance: distance, normal: normal, face: face,
+                       ray: ray, object: object, object_id: 0 }
+    }
+
+    /// Returns the material of the object at the intersection.
+    pub fn material(&self) -> &'a Arc<Material> {
+        self.object.material_at(self.face)
     }
 }
 
-/// Holds the intersection point, the distance to the intersection from the ray's origin, and the
-/// surface normal at the intersection.
-pub type HitRecord = (Point3<f64>, f64, Vector3<f64>);
+/// Holds the intersection point, the distance to the intersection from the ray's origin, the
+/// surface normal at the intersection, and the face hit when the surface is a box.
+pub type HitRecord = (Point3<f64>, f64, Vector3<f64>, Option<BoxFace>);
 
 pub fn closest_intersection(intersections: Vec<Intersection>) -> Option<Intersection> {
     intersections.into_iter().min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
@@ -35,39 +48,81 @@ fn closest_hit(hits: Vec<HitRecord>) -> Option<HitRecord> {
 pub fn ray_face(ray: &Ray, face: &Face) -> Option<HitRecord> {
     match ray.intersects_face(face) {
         Some((p, d)) => {
-            Some((p, d, face.normal()))
+            Some((p, d, face.normal_at(ray.time), None))
         }
         None => None
     }
 }
 
 pub fn ray_box(ray: &Ray, _box: &Box) -> Option<HitRecord> {
-    let hits = vec!(ray_face(ray, &_box.top),
-                    ray_face(ray, &_box.bottom),
-                    ray_face(ray, &_box.left),
-                    ray_face(ray, &_box.right),
-                    ray_face(ray, &_box.front),
-                    ray_face(ray, &_box.back));
+    match _box.motion {
+        None => ray_box_faces(ray, _box),
+        Some(ref motion) => {
+            // Move the ray into the rest pose of the box, intersect, and move the hit
+            // back to where the box is at the time of the ray, leaving the rest pose
+            // through the cached inverse of its transform
+            let placement = Transform::from_isometry(motion.interpolate(ray.time));
+            let rest_ray = ray.inverse_transformed(&placement).transformed(&_box.transform);
+            match ray_box_faces(&rest_ray, _box) {
+                Some((p, _, n, face)) => {
+                    let p = placement.transform_point(&_box.transform.inverse_transform_point(&p));
+                    // Both transforms being rigid, normals move as vectors do
+                    let n = placement.transform_vector(&_box.transform.inverse_transform_vector(&n)).normalize();
+                    Some((p, norm(&(p - ray.origin)), n, face))
+                }
+                None => None
+            }
+        }
+    }
+}
+
+/// Moves the ray into the frame of the shared surface, intersects it there
+/// and moves the hit back.
+pub fn ray_instance(ray: &Ray, instance: &Instance) -> Option<HitRecord> {
+    let transform = &instance.transform;
+    let local_ray = ray.inverse_transformed(transform);
+    match ray_surface(&local_ray, &instance.surface) {
+        // Distances are not kept by scaling, hence measured again
+        Some((p, _, n, face)) => {
+            let p = transform.transform_point(&p);
+            Some((p, norm(&(p - ray.origin)), transform.transform_normal(&n), face))
+        }
+        None => None
+    }
+}
+
+pub fn ray_surface(ray: &Ray, surface: &Surface) -> Option<HitRecord> {
+    match *surface {
+        Surface::Face(ref f) => ray_face(ray, f),
+        Surface::Box(ref b) => ray_box(ray, b),
+        Surface::Instance(ref i) => ray_instance(ray, i)
+    }
+}
+
+fn ray_box_faces(ray: &Ray, _box: &Box) -> Option<HitRecord> {
+    let hits = BoxFace::all().iter().map(|&face| {
+        ray_face(ray, _box.face(face)).map(|(p, d, n, _)| (p, d, n, Some(face)))
+    }).collect();
     closest_hit(filter_nones(hits))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use algebra::AnimatedTransform;
     use objects::Face;
     use num_traits::{One, Zero};
-    use material::Simple;
+    use material::{same_handle, Simple};
     use image::Rgb;
-    use std::boxed::Box as StdBox;
     use std::f64::consts::PI;
 
     #[test]
     fn test_new_intersection() {
         let pos = Point3::new(0., 0., 0.);
         let d = 5.;
-        let f = Face::new(1., 1., Isometry3::one(), StdBox::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+        let f = Face::new(1., 1., Isometry3::one(), Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
         let obj = Object::from_surface(Surface::from_face(f.clone()));
-        let i = Intersection::new(pos, d, f.normal(), Ray::new(Point3::new(0., 0., 0.), Vector3::x()), &obj);
+        let i = Intersection::new(pos, d, f.normal(), None, Ray::new(Point3::new(0., 0., 0.), Vector3::x()), &obj);
         assert!(i.position == pos);
         assert!(i.distance == d);
     }
@@ -76,7 +131,7 @@ mod tests {
     fn test_ray_face_intersects() {
         let f = Face::new(3., 3., Isometry3::new(Vector3::new(0., 0., -5.),
                                                  Vector3::zero()),
-                          StdBox::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+                          Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
         let i_opt = ray_face(&Ray::new(Point3::new(0., 0., 0.), -Vector3::z()), &f);
         assert!(i_opt.is_some());
         let i = i_opt.unwrap();
@@ -88,7 +143,7 @@ mod tests {
     fn test_face_no_intersects() {
         let f = Face::new(3., 3., Isometry3::from_rotation_matrix(Vector3::new(2., 0., -5.),
                                                                   Rotation3::one()),
-                          StdBox::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+                          Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
         let i_opt = ray_face(&Ray::new(Point3::new(0., 0., 0.), -Vector3::z()), &f);
         assert!(i_opt.is_none());
     }
@@ -97,7 +152,7 @@ mod tests {
     fn test_face_no_intersects_back() {
         let f = Face::new(3., 3., Isometry3::new(Vector3::new(0., 0., -5.),
                                                  Vector3::y() * PI),
-                          StdBox::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+                          Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
         let i_opt = ray_face(&Ray::new(Point3::new(0., 0., 0.), -Vector3::z()), &f);
         assert!(i_opt.is_none());
     }
@@ -107,7 +162,7 @@ mod tests {
         let mat = Simple::new(Rgb { data: [1.0, 1.0, 1.0] });
         let b = Box::new(Vector3::one(),
                          Isometry3::new(Vector3::z() * 5., Vector3::zero()),
-                         StdBox::new(mat));
+                         Arc::new(mat));
         let ray = Ray::new(Point3::new(0., 0., 0.), Vector3::z());
         let inter_opt = ray_box(&ray, &b);
         assert!(inter_opt.is_some());
@@ -122,7 +177,7 @@ mod tests {
         let mat = Simple::new(Rgb { data: [1.0, 1.0, 1.0] });
         let b = Box::new(Vector3::one(),
                          Isometry3::new(Vector3::new(5., 5., 5.), Vector3::zero()),
-                         StdBox::new(mat));
+                         Arc::new(mat));
         let ray = Ray::new(Point3::new(5., 5., 0.), Vector3::z());
         let inter_opt = ray_box(&ray, &b);
         assert!(inter_opt.is_some());
@@ -131,4 +186,53 @@ mod tests {
             assert!(i.1.approx_eq(&4.5));
         }
     }
+
+    #[test]
+    fn test_ray_box_intersects_moving() {
+        let mat = Simple::new(Rgb { data: [1.0, 1.0, 1.0] });
+        let start = Isometry3::new(Vector3::z() * 5., Vector3::zero());
+        let end = Isometry3::new(Vector3::new(5., 0., 5.), Vector3::zero());
+        let mut b = Box::new(Vector3::one(), start, Arc::new(mat));
+        b.set_motion(Some(AnimatedTransform::between(0., start, 1., end)));
+        let ray = Ray::with_time(Point3::new(5., 0., 0.), Vector3::z(), 0.);
+        assert!(ray_box(&ray, &b).is_none());
+        let ray = Ray::with_time(Point3::new(5., 0., 0.), Vector3::z(), 1.);
+        let inter_opt = ray_box(&ray, &b);
+        assert!(inter_opt.is_some());
+        if let Some(i) = inter_opt {
+            assert!(i.0.approx_eq(&Point3::new(5., 0., 4.5)));
+            assert!(i.1.approx_eq(&4.5));
+            assert!(i.2.approx_eq(&-Vector3::z()));
+        }
+    }
+
+    #[test]
+    fn test_box_face_materials() {
+        let white: Arc<Material> = Arc::new(Simple::new(Rgb { data: [1., 1., 1.] }));
+        let red: Arc<Material> = Arc::new(Simple::new(Rgb { data: [1., 0., 0.] }));
+        let b = Box::with_face_materials(Vector3::one(), Isometry3::new(Vector3::z() * -5., Vector3::zero()),
+                                         white.clone(), &[(BoxFace::Top, red.clone())]);
+        let object = Object::from_surface(Surface::from_box(b.clone()));
+        let from_above = object.intersects(Ray::new(Point3::new(0., 10., -5.), -Vector3::y())).unwrap();
+        assert!(from_above.face == Some(BoxFace::Top) && same_handle(from_above.material(), &red));
+        let from_front = object.intersects(Ray::new(Point3::new(0., 0., 0.), -Vector3::z())).unwrap();
+        assert!(from_front.face == Some(BoxFace::Front) && same_handle(from_front.material(), &white));
+        let instance = Object::from_surface(Surface::from_instance(Instance::new(Arc::new(Surface::from_box(b)),
+                                                                                 Isometry3::one())));
+        let hit = instance.intersects(Ray::new(Point3::new(0., 10., -5.), -Vector3::y())).unwrap();
+        assert!(hit.face == Some(BoxFace::Top) && same_handle(hit.material(), &red));
+        let mut object = object;
+        let blue: Arc<Material> = Arc::new(Simple::new(Rgb { data: [0., 0., 1.] }));
+        object.replace_material(&white, &blue, &mut vec!());
+        let hit = object.intersects(Ray::new(Point3::new(0., 10., -5.), -Vector3::y())).unwrap();
+        assert!(same_handle(hit.material(), &red));
+        let hit = object.intersects(Ray::new(Point3::new(0., 0., 0.), -Vector3::z())).unwrap();
+        assert!(same_handle(hit.material(), &blue));
+        // A face given the material of the box keeps it when the box changes
+        let mut b = Box::with_face_materials(Vector3::one(), Isometry3::one(), white.clone(),
+                                             &[(BoxFace::Front, white.clone())]);
+        b.set_material(blue.clone());
+        assert!(same_handle(&b.front.material, &white) && same_handle(&b.top.material, &blue));
+        assert!(b.face_material(BoxFace::Top).is_none());
+    }
 }
@@ -0,0 +1,91 @@
+//! Little and big endian encoding of the values stored in image files.
+
+use std::io::{self, Read, Write};
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Returns the number of pixels of a `width × height` image read from a file,
+/// failing if it overflows.
+pub fn image_area(width: usize, height: usize) -> io::Result<usize> {
+    width.checked_mul(height).ok_or(invalid_data("image too large"))
+}
+
+pub fn write_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8])
+}
+
+pub fn write_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8])
+}
+
+pub fn write_i32<W: Write>(w: &mut W, v: i32) -> io::Result<()> {
+    write_u32(w, v as u32)
+}
+
+pub fn write_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    write_u32(w, v as u32)?;
+    write_u32(w, (v >> 32) as u32)
+}
+
+pub fn write_f32<W: Write>(w: &mut W, v: f32) -> io::Result<()> {
+    write_u32(w, v.to_bits())
+}
+
+pub fn write_f64<W: Write>(w: &mut W, v: f64) -> io::Result<()> {
+    write_u64(w, v.to_bits())
+}
+
+pub fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+pub fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0; 2];
+    r.read_exact(&mut b)?;
+    Ok(b[0] as u16 | (b[1] as u16) << 8)
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+}
+
+pub fn read_u32_be<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(b[3] as u32 | (b[2] as u32) << 8 | (b[1] as u32) << 16 | (b[0] as u32) << 24)
+}
+
+pub fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    read_u32(r).map(|v| v as i32)
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let low = read_u32(r)? as u64;
+    let high = read_u32(r)? as u64;
+    Ok(low | high << 32)
+}
+
+pub fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    read_u32(r).map(f32::from_bits)
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    read_u64(r).map(f64::from_bits)
+}
+
+/// Reads bytes up to the given delimiter, which is consumed but not returned.
+pub fn read_until<R: Read>(r: &mut R, delimiter: u8) -> io::Result<Vec<u8>> {
+    let mut bytes = vec!();
+    loop {
+        match read_u8(r)? {
+            b if b == delimiter => return Ok(bytes),
+            b => bytes.push(b)
+        }
+    }
+}
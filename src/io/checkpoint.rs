@@ -0,0 +1,217 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use film::{filter_from_name, Aov};
+use io::bytes::*;
+use io::{read_film, write_film};
+use raytracer::{PixelStatistics, ProgressiveState};
+
+const MAGIC: &'static [u8; 4] = b"RTCK";
+const VERSION: u32 = 5;
+
+/// Longest filter or AOV name read back, known names being much shorter.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Identifies the render a checkpoint was taken from, so that it is only
+/// resumed with the same scene, sampling, filter, AOVs and crop window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointHeader {
+    /// Hash of the scene, see `Scene::hash`.
+    pub scene_hash: u64,
+    pub seed: u32,
+    /// Number of passes the render is stratified over.
+    pub passes: u32,
+    pub bounces: u32,
+    /// Name of the reconstruction filter, see `filter_from_name`.
+    pub filter: String,
+    pub aovs: Vec<Aov>,
+    /// Minimum and maximum samples per pixel and noise threshold of adaptive
+    /// sampling, as in `RenderDescription`.
+    pub adaptive: Option<(u32, u32, f64)>,
+    /// `x0, y0, x1, y1` of the pixels rendered.
+    pub crop: Option<[u32; 4]>
+}
+
+fn write_name<W: Write>(w: &mut W, name: &str) -> io::Result<()> {
+    write_u32(w, name.len() as u32)?;
+    w.write_all(name.as_bytes())
+}
+
+fn read_name<R: Read>(r: &mut R) -> io::Result<String> {
+    let length = read_u32(r)? as usize;
+    if length > MAX_NAME_LENGTH {
+        return Err(invalid_data("name too long"));
+    }
+    let mut name = vec![0; length];
+    r.read_exact(&mut name)?;
+    String::from_utf8(name).map_err(|_| invalid_data("invalid name"))
+}
+
+/// Writes the state of a progressive render, with the full precision of its
+/// accumulated sums, weights, sample counts and per pixel statistics.
+pub fn write_checkpoint<W: Write>(w: &mut W, header: &CheckpointHeader,
+                                  state: &ProgressiveState) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    write_u32(w, VERSION)?;
+    write_u64(w, header.scene_hash)?;
+    write_u32(w, header.seed)?;
+    write_u32(w, header.passes)?;
+    write_u32(w, header.bounces)?;
+    write_name(w, &header.filter)?;
+    write_u32(w, header.aovs.len() as u32)?;
+    for aov in header.aovs.iter() {
+        write_name(w, aov.name())?;
+    }
+    write_u32(w, header.adaptive.is_some() as u32)?;
+    if let Some((min, max, threshold)) = header.adaptive {
+        write_u32(w, min)?;
+        write_u32(w, max)?;
+        write_f64(w, threshold)?;
+    }
+    write_u32(w, header.crop.is_some() as u32)?;
+    for &v in header.crop.iter().flat_map(|c| c.iter()) {
+        write_u32(w, v)?;
+    }
+    write_u32(w, state.pass)?;
+    write_film(w, &state.film)?;
+    for s in state.statistics.iter() {
+        write_u32(w, s.count())?;
+        write_f64(w, s.mean())?;
+        write_f64(w, s.m2())?;
+    }
+    Ok(())
+}
+
+/// Reads a checkpoint back, its film getting the filter named in the header.
+/// Films rendered with another filter or other AOVs than the header tells
+/// are rejected.
+pub fn read_checkpoint<R: Read>(r: &mut R) -> io::Result<(CheckpointHeader, ProgressiveState)> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a render checkpoint"));
+    }
+    if read_u32(r)? != VERSION {
+        return Err(invalid_data("unsupported checkpoint version"));
+    }
+    let (scene_hash, seed, passes, bounces) = (read_u64(r)?, read_u32(r)?, read_u32(r)?, read_u32(r)?);
+    let filter = read_name(r)?;
+    let mut aovs = vec!();
+    for _ in 0..read_u32(r)? {
+        aovs.push(Aov::from_name(&read_name(r)?).ok_or(invalid_data("unknown AOV"))?);
+    }
+    let adaptive = match read_u32(r)? {
+        0 => None,
+        _ => Some((read_u32(r)?, read_u32(r)?, read_f64(r)?))
+    };
+    let crop = match read_u32(r)? {
+        0 => None,
+        _ => Some([read_u32(r)?, read_u32(r)?, read_u32(r)?, read_u32(r)?])
+    };
+    let header = CheckpointHeader { scene_hash: scene_hash, seed: seed, passes: passes, bounces: bounces,
+                                    filter: filter, aovs: aovs, adaptive: adaptive, crop: crop };
+    let pass = read_u32(r)?;
+    let film = read_film(r, filter_from_name(&header.filter).ok_or(invalid_data("unknown filter"))?)?;
+    if film.size() != film.resolution() {
+        return Err(invalid_data("checkpoint of a part of an image"));
+    }
+    if film.aovs() != header.aovs {
+        return Err(invalid_data("film rendered with other AOVs"));
+    }
+    // The film was read, so its pixels are there to size the statistics
+    let n_pixels = film.film_pixels().len();
+    let mut statistics = Vec::with_capacity(n_pixels);
+    for _ in 0..n_pixels {
+        statistics.push(PixelStatistics::from_moments(read_u32(r)?, read_f64(r)?, read_f64(r)?));
+    }
+    Ok((header, ProgressiveState { film: film, pass: pass, statistics: statistics }))
+}
+
+/// Saves a checkpoint to a file. It is written next to the file first and
+/// then moved over it, so a render killed while saving keeps its previous
+/// checkpoint.
+pub fn save_checkpoint(path: &Path, header: &CheckpointHeader, state: &ProgressiveState) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".partial");
+    {
+        let mut w = BufWriter::new(File::create(&partial)?);
+        write_checkpoint(&mut w, header, state)?;
+        w.flush()?;
+    }
+    fs::rename(&partial, path)
+}
+
+pub fn load_checkpoint(path: &Path) -> io::Result<(CheckpointHeader, ProgressiveState)> {
+    read_checkpoint(&mut BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::{filter_from_name, Aov, Film};
+    use image::Rgb;
+
+    fn test_state() -> ProgressiveState {
+        let mut film = Film::with_aovs(3, 2, filter_from_name("tent").unwrap(), &[Aov::Depth, Aov::ObjectId]);
+        film.add_sample((1.3, 0.6), &Rgb { data: [0.1, 2.5, 1e-7] }, 1.);
+        film.add_sample_count(1, 0, 1);
+        film.add_aov_sample(1, 0, Aov::Depth, &[3.25]);
+        film.add_aov_sample(2, 1, Aov::ObjectId, &[7.]);
+        let mut state = ProgressiveState::new(film);
+        state.pass = 1;
+        state.statistics[1].add(0.7);
+        state
+    }
+
+    fn test_header(filter: &str, aovs: &[Aov]) -> CheckpointHeader {
+        CheckpointHeader { scene_hash: 0x0123456789abcdef, seed: 3, passes: 16, bounces: 2,
+                           filter: filter.to_string(), aovs: aovs.to_vec(), adaptive: None, crop: None }
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let header = test_header("tent", &[Aov::Depth, Aov::ObjectId]);
+        let state = test_state();
+        let mut bytes = vec!();
+        write_checkpoint(&mut bytes, &header, &state).unwrap();
+        let (read_header, read) = read_checkpoint(&mut &bytes[..]).unwrap();
+        assert!(read_header == header);
+        assert!(read.pass == 1);
+        assert!(read.statistics == state.statistics);
+        assert!(read.film.film_pixels() == state.film.film_pixels());
+        assert!(read.film.pixel_counts() == state.film.pixel_counts());
+        assert!(read.film.aov_buffers() == state.film.aov_buffers());
+    }
+
+    #[test]
+    fn test_checkpoint_settings_mismatch() {
+        for header in [test_header("box", &[Aov::Depth, Aov::ObjectId]), test_header("tent", &[Aov::Depth]),
+                       test_header("sinc", &[Aov::Depth, Aov::ObjectId])].iter() {
+            let mut bytes = vec!();
+            write_checkpoint(&mut bytes, header, &test_state()).unwrap();
+            assert!(read_checkpoint(&mut &bytes[..]).is_err());
+        }
+        assert!(read_checkpoint(&mut &b"RTCX"[..]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_crop_and_adaptive() {
+        let mut header = test_header("tent", &[Aov::Depth, Aov::ObjectId]);
+        header.adaptive = Some((2, 64, 0.01));
+        header.crop = Some([0, 0, 2, 1]);
+        let mut bytes = vec!();
+        write_checkpoint(&mut bytes, &header, &test_state()).unwrap();
+        let (read_header, _) = read_checkpoint(&mut &bytes[..]).unwrap();
+        assert!(read_header == header);
+        // Resuming with other settings is refused by comparing the headers
+        let mut other = header.clone();
+        other.crop = Some([1, 0, 3, 2]);
+        assert!(read_header != other);
+        other.crop = header.crop;
+        other.adaptive = Some((2, 64, 0.02));
+        assert!(read_header != other);
+        other.adaptive = None;
+        assert!(read_header != other);
+    }
+}
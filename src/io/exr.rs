@@ -0,0 +1,353 @@
+use std::io::{self, Read, Write};
+use std::u32;
+
+use io::FloatImage;
+use io::bytes::*;
+
+/// Storage type of the channels of an OpenEXR file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float
+}
+
+const EXR_MAGIC: u32 = 20000630;
+/// Version 2, single part scanline file.
+const EXR_VERSION: u32 = 2;
+const EXR_TILED_FLAG: u32 = 0x200;
+/// Largest header attribute read, far above the size of the attributes of
+/// files with a few channels.
+const MAX_ATTRIBUTE_SIZE: i32 = 1 << 20;
+
+impl PixelType {
+    fn code(&self) -> i32 {
+        match *self {
+            PixelType::Half => 1,
+            PixelType::Float => 2
+        }
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            PixelType::Half => 2,
+            PixelType::Float => 4
+        }
+    }
+}
+
+/// Converts to the nearest half precision float, rounding ties to even.
+pub fn f32_to_half(f: f32) -> u16 {
+    let x = f.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exponent = ((x >> 23) & 0xff) as i32;
+    let mantissa = x & 0x7fffff;
+    if exponent == 0xff {
+        // Infinity or NaN, keeping NaNs quiet
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let e = exponent - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let (h, rest, halfway) = match e <= 0 {
+        true => {
+            // Subnormal half, made of the mantissa with its implicit bit
+            if e < -10 {
+                return sign;
+            }
+            let m = mantissa | 0x800000;
+            let shift = (14 - e) as u32;
+            (m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1))
+        }
+        false => (((e as u32) << 10) | (mantissa >> 13), mantissa & 0x1fff, 0x1000)
+    };
+    // Rounding may carry into the exponent, which is still correct
+    match rest > halfway || (rest == halfway && h & 1 == 1) {
+        true => sign | (h + 1) as u16,
+        false => sign | h as u16
+    }
+}
+
+pub fn half_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exponent = ((h >> 10) & 0x1f) as u32;
+    let mantissa = (h & 0x3ff) as u32;
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal half, normalized as a float
+            let mut e = 127 - 14;
+            let mut m = mantissa;
+            while m & 0x400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            sign | (e << 23) | ((m & 0x3ff) << 13)
+        }
+        (0x1f, _) => sign | 0x7f800000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)
+    };
+    f32::from_bits(bits)
+}
+
+fn write_attribute<W: Write>(w: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(kind.as_bytes())?;
+    w.write_all(&[0])?;
+    write_i32(w, value.len() as i32)?;
+    w.write_all(value)
+}
+
+fn box2i(x_min: i32, y_min: i32, x_max: i32, y_max: i32) -> io::Result<Vec<u8>> {
+    let mut value = vec!();
+    for &v in [x_min, y_min, x_max, y_max].iter() {
+        write_i32(&mut value, v)?;
+    }
+    Ok(value)
+}
+
+/// Writes an uncompressed scanline OpenEXR file holding every channel of the
+/// image, stored as `pixel_type`.
+pub fn write_exr<W: Write>(w: &mut W, image: &FloatImage, pixel_type: PixelType) -> io::Result<()> {
+    if image.channels.is_empty() {
+        return Err(invalid_data("cannot write an image without channels"));
+    }
+    // Channels are stored in alphabetical order
+    let mut channels: Vec<_> = image.channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    let (width, height) = (image.width as usize, image.height as usize);
+
+    let mut header = vec!();
+    write_u32(&mut header, EXR_MAGIC)?;
+    write_u32(&mut header, EXR_VERSION)?;
+    let mut chlist = vec!();
+    for c in channels.iter() {
+        chlist.write_all(c.name.as_bytes())?;
+        chlist.write_all(&[0])?;
+        write_i32(&mut chlist, pixel_type.code())?;
+        // pLinear and reserved bytes, then x and y sampling
+        chlist.write_all(&[0, 0, 0, 0])?;
+        write_i32(&mut chlist, 1)?;
+        write_i32(&mut chlist, 1)?;
+    }
+    chlist.push(0);
+    let (x0, y0) = (image.origin.0 as i32, image.origin.1 as i32);
+    let data_window = box2i(x0, y0, x0 + image.width as i32 - 1, y0 + image.height as i32 - 1)?;
+    let display_window = box2i(0, 0, image.display_size.0 as i32 - 1, image.display_size.1 as i32 - 1)?;
+    write_attribute(&mut header, "channels", "chlist", &chlist)?;
+    write_attribute(&mut header, "compression", "compression", &[0])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &data_window)?;
+    write_attribute(&mut header, "displayWindow", "box2i", &display_window)?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_attribute(&mut header, "pixelAspectRatio", "float", &[0, 0, 0x80, 0x3f])?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(&mut header, "screenWindowWidth", "float", &[0, 0, 0x80, 0x3f])?;
+    header.push(0);
+    w.write_all(&header)?;
+
+    // One scanline per chunk, each chunk starting with its row and size
+    let line_size = width * channels.len() * pixel_type.size();
+    let first_chunk = header.len() + height * 8;
+    for y in 0..height {
+        write_u64(w, (first_chunk + y * (line_size + 8)) as u64)?;
+    }
+    for y in 0..height {
+        write_i32(w, y0 + y as i32)?;
+        write_i32(w, line_size as i32)?;
+        for c in channels.iter() {
+            for &v in c.data[y * width..(y + 1) * width].iter() {
+                match pixel_type {
+                    PixelType::Half => write_u16(w, f32_to_half(v))?,
+                    PixelType::Float => write_f32(w, v)?
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads an uncompressed scanline OpenEXR file with half or float channels.
+pub fn read_exr<R: Read>(r: &mut R) -> io::Result<FloatImage> {
+    if read_u32(r)? != EXR_MAGIC {
+        return Err(invalid_data("not an OpenEXR file"));
+    }
+    if read_u32(r)? & EXR_TILED_FLAG != 0 {
+        return Err(invalid_data("tiled OpenEXR files are not supported"));
+    }
+    let mut channels: Vec<(String, PixelType)> = vec!();
+    let mut window = None;
+    let mut display_window = None;
+    loop {
+        let name = String::from_utf8_lossy(&read_until(r, 0)?).into_owned();
+        if name.is_empty() {
+            break;
+        }
+        read_until(r, 0)?;
+        let size = read_i32(r)?;
+        if size < 0 || size > MAX_ATTRIBUTE_SIZE {
+            return Err(invalid_data("invalid OpenEXR attribute size"));
+        }
+        let mut value = vec![0; size as usize];
+        r.read_exact(&mut value)?;
+        let mut value = &value[..];
+        match &name[..] {
+            "channels" => loop {
+                let channel = String::from_utf8_lossy(&read_until(&mut value, 0)?).into_owned();
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = match read_i32(&mut value)? {
+                    1 => PixelType::Half,
+                    2 => PixelType::Float,
+                    _ => return Err(invalid_data("unsupported OpenEXR pixel type"))
+                };
+                let mut rest = [0; 12];
+                value.read_exact(&mut rest)?;
+                channels.push((channel, pixel_type));
+            },
+            "compression" => if value.first() != Some(&0) {
+                return Err(invalid_data("compressed OpenEXR files are not supported"));
+            },
+            "dataWindow" => {
+                let x_min = read_i32(&mut value)?;
+                let y_min = read_i32(&mut value)?;
+                let x_max = read_i32(&mut value)?;
+                let y_max = read_i32(&mut value)?;
+                window = Some((x_min, y_min, x_max, y_max));
+            }
+            "displayWindow" => {
+                let (x_min, y_min) = (read_i32(&mut value)? as i64, read_i32(&mut value)? as i64);
+                let (x_max, y_max) = (read_i32(&mut value)? as i64, read_i32(&mut value)? as i64);
+                display_window = Some((x_max - x_min + 1, y_max - y_min + 1));
+            }
+            _ => ()
+        }
+    }
+    let (x_min, y_min, x_max, y_max) = window.ok_or(invalid_data("missing OpenEXR data window"))?;
+    if x_min < 0 || y_min < 0 {
+        return Err(invalid_data("OpenEXR data windows left or above of the image are not supported"));
+    }
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid_data("inverted OpenEXR data window"));
+    }
+    // Computed in 64 bits as the window may span all of the 32 bits range
+    let width = (x_max as i64 - x_min as i64 + 1) as usize;
+    let height = (y_max as i64 - y_min as i64 + 1) as usize;
+    let area = image_area(width, height)?;
+    for _ in 0..height {
+        read_u64(r)?;
+    }
+    let mut planes = vec![vec![0.; area]; channels.len()];
+    for _ in 0..height {
+        let y = read_i32(r)? as i64 - y_min as i64;
+        read_i32(r)?;
+        if y < 0 || y as usize >= height {
+            return Err(invalid_data("OpenEXR scanline out of the data window"));
+        }
+        let y = y as usize;
+        for (plane, &(_, pixel_type)) in planes.iter_mut().zip(channels.iter()) {
+            for v in plane[y * width..(y + 1) * width].iter_mut() {
+                *v = match pixel_type {
+                    PixelType::Half => half_to_f32(read_u16(r)?),
+                    PixelType::Float => read_f32(r)?
+                };
+            }
+        }
+    }
+    let mut image = FloatImage::new(width as u32, height as u32);
+    image.origin = (x_min as u32, y_min as u32);
+    if let Some((display_width, display_height)) = display_window {
+        let clamp = |v: i64| v.max(0).min(u32::MAX as i64) as u32;
+        image.display_size = (clamp(display_width), clamp(display_height));
+    }
+    for ((name, _), data) in channels.into_iter().zip(planes.into_iter()) {
+        image.add_channel(&name, data);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::bytes::{read_i32, read_u64};
+
+    fn test_image() -> FloatImage {
+        let mut image = FloatImage::new(3, 2);
+        image.add_channel("R", vec!(0., 0.5, 1., 2., 100., 65504.));
+        image.add_channel("G", vec!(-1., 0.25, 1e-5, 3., 7., 0.1));
+        image.add_channel("B", vec!(1., 1., 1., 1., 1., 1.));
+        image.add_channel("Z", vec!(1., 2., 3., 4., 5., 6.));
+        image
+    }
+
+    #[test]
+    fn test_half_conversion() {
+        for &f in [0., -0., 1., -2.5, 0.1, 65504., 6.1035156e-5, 5.9604645e-8].iter() {
+            assert!((half_to_f32(f32_to_half(f)) - f).abs() <= f.abs() * 1e-3);
+        }
+        assert!(f32_to_half(1.) == 0x3c00);
+        assert!(f32_to_half(65520.) == 0x7c00);
+        assert!(f32_to_half(1e-9) == 0);
+        assert!(half_to_f32(0x0001) == 5.9604645e-8);
+        assert!(half_to_f32(f32_to_half(::std::f32::NAN)).is_nan());
+        // 1 + 2^-11 is halfway between 1 and the next half, rounded to even
+        assert!(f32_to_half(1. + 1. / 2048.) == 0x3c00);
+    }
+
+    #[test]
+    fn test_exr_float_roundtrip() {
+        let image = test_image();
+        let mut bytes = vec!();
+        write_exr(&mut bytes, &image, PixelType::Float).unwrap();
+        let read = read_exr(&mut &bytes[..]).unwrap();
+        assert!(read.width == 3 && read.height == 2);
+        for c in image.channels.iter() {
+            assert!(read.channel(&c.name).unwrap() == &c.data[..]);
+        }
+    }
+
+    #[test]
+    fn test_exr_half_roundtrip() {
+        let image = test_image();
+        let mut bytes = vec!();
+        write_exr(&mut bytes, &image, PixelType::Half).unwrap();
+        let read = read_exr(&mut &bytes[..]).unwrap();
+        let names: Vec<_> = read.channels.iter().map(|c| &c.name[..]).collect();
+        assert!(names == ["B", "G", "R", "Z"]);
+        for c in image.channels.iter() {
+            for (a, b) in read.channel(&c.name).unwrap().iter().zip(c.data.iter()) {
+                assert!((a - b).abs() <= b.abs() * 1e-3 + 1e-7);
+            }
+        }
+    }
+
+    #[test]
+    fn test_exr_offsets() {
+        let mut bytes = vec!();
+        write_exr(&mut bytes, &test_image(), PixelType::Half).unwrap();
+        // The last chunk ends the file, its offset pointing at its row number
+        let line_size = 3 * 4 * 2;
+        let last_chunk = bytes.len() - line_size - 8;
+        let offsets_end = last_chunk - (line_size + 8);
+        let offset = read_u64(&mut &bytes[offsets_end - 8..]).unwrap();
+        assert!(offset as usize == last_chunk);
+        assert!(read_i32(&mut &bytes[last_chunk..]).unwrap() == 1);
+    }
+
+    #[test]
+    fn test_exr_data_window() {
+        let mut image = test_image();
+        image.origin = (5, 7);
+        image.display_size = (20, 10);
+        let mut bytes = vec!();
+        write_exr(&mut bytes, &image, PixelType::Float).unwrap();
+        let read = read_exr(&mut &bytes[..]).unwrap();
+        assert!((read.width, read.height, read.origin, read.display_size) == (3, 2, (5, 7), (20, 10)));
+        assert!(read.channel("Z").unwrap() == image.channel("Z").unwrap());
+        // Inverted data window, x_max being left of x_min
+        let window = bytes.windows(11).position(|w| w == b"dataWindow\0").unwrap() + 11 + 6 + 4;
+        bytes[window + 8..window + 12].copy_from_slice(&[4, 0, 0, 0]);
+        assert!(read_exr(&mut &bytes[..]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{self, Read, Write};
+
+use film::{Aov, AovBuffer, Film, FilmPixel, Filter};
+use io::bytes::*;
+
+/// Writes the accumulated sums, weights, sample counts and AOVs of a film,
+/// or of the window of the image it covers, with full precision.
+pub fn write_film<W: Write>(w: &mut W, film: &Film) -> io::Result<()> {
+    let (width, height) = film.resolution();
+    let (ox, oy) = film.origin();
+    let (sx, sy) = film.size();
+    let (rx, ry) = film.filter().radius();
+    for &v in [width, height, ox, oy, sx, sy].iter() {
+        write_u32(w, v)?;
+    }
+    write_f64(w, rx)?;
+    write_f64(w, ry)?;
+    write_u32(w, film.aov_buffers().len() as u32)?;
+    for buffer in film.aov_buffers() {
+        let name = buffer.aov().name();
+        write_u32(w, name.len() as u32)?;
+        w.write_all(name.as_bytes())?;
+    }
+    for p in film.film_pixels() {
+        for c in p.rgb.iter().chain(p.covered_rgb.iter()) {
+            write_f64(w, *c)?;
+        }
+        write_f64(w, p.alpha)?;
+        write_f64(w, p.weight)?;
+    }
+    for count in film.pixel_counts() {
+        write_u32(w, *count)?;
+    }
+    for buffer in film.aov_buffers() {
+        for v in buffer.values() {
+            write_f64(w, *v)?;
+        }
+        for count in buffer.counts() {
+            write_u32(w, *count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Longest AOV name read back, names of known AOVs being much shorter.
+const MAX_AOV_NAME_LENGTH: usize = 64;
+
+/// Pixels allocated ahead of reading them, so that a corrupt size fails
+/// when the data runs out rather than on allocating.
+const PREALLOCATED_PIXELS: usize = 1 << 16;
+
+/// Window of the image a film covers, its filter radius and its AOVs.
+struct FilmHeader {
+    resolution: (u32, u32),
+    origin: (u32, u32),
+    size: (u32, u32),
+    radius: (f64, f64),
+    aovs: Vec<Aov>
+}
+
+fn read_header<R: Read>(r: &mut R) -> io::Result<FilmHeader> {
+    let mut v = [0; 6];
+    for x in v.iter_mut() {
+        *x = read_u32(r)?;
+    }
+    let (resolution, origin, size) = ((v[0], v[1]), (v[2], v[3]), (v[4], v[5]));
+    if origin.0 as u64 + size.0 as u64 > resolution.0 as u64 || origin.1 as u64 + size.1 as u64 > resolution.1 as u64 {
+        return Err(invalid_data("film window out of the image"));
+    }
+    let radius = (read_f64(r)?, read_f64(r)?);
+    let mut aovs = vec!();
+    for _ in 0..read_u32(r)? {
+        let length = read_u32(r)? as usize;
+        if length > MAX_AOV_NAME_LENGTH {
+            return Err(invalid_data("unknown AOV"));
+        }
+        let mut name = vec![0; length];
+        r.read_exact(&mut name)?;
+        let name = String::from_utf8(name).map_err(|_| invalid_data("invalid AOV name"))?;
+        aovs.push(Aov::from_name(&name).ok_or(invalid_data("unknown AOV"))?);
+    }
+    Ok(FilmHeader { resolution: resolution, origin: origin, size: size, radius: radius, aovs: aovs })
+}
+
+fn read_pixels<R: Read>(r: &mut R, header: FilmHeader, filter: Box<Filter>) -> io::Result<Film> {
+    let n_pixels = (header.size.0 as usize).checked_mul(header.size.1 as usize)
+                                           .ok_or(invalid_data("film too large"))?;
+    let preallocated = n_pixels.min(PREALLOCATED_PIXELS);
+    let mut pixels = Vec::with_capacity(preallocated);
+    for _ in 0..n_pixels {
+        let rgb = [read_f64(r)?, read_f64(r)?, read_f64(r)?];
+        let covered_rgb = [read_f64(r)?, read_f64(r)?, read_f64(r)?];
+        pixels.push(FilmPixel { rgb: rgb, covered_rgb: covered_rgb, alpha: read_f64(r)?, weight: read_f64(r)? });
+    }
+    let mut counts = Vec::with_capacity(preallocated);
+    for _ in 0..n_pixels {
+        counts.push(read_u32(r)?);
+    }
+    let mut buffers = vec!();
+    for aov in header.aovs {
+        let n_values = n_pixels.checked_mul(aov.components()).ok_or(invalid_data("film too large"))?;
+        let mut values = Vec::with_capacity(n_values.min(PREALLOCATED_PIXELS));
+        for _ in 0..n_values {
+            values.push(read_f64(r)?);
+        }
+        let mut aov_counts = Vec::with_capacity(preallocated);
+        for _ in 0..n_pixels {
+            aov_counts.push(read_u32(r)?);
+        }
+        buffers.push(AovBuffer::from_parts(aov, values, aov_counts));
+    }
+    Ok(Film::from_parts(header.resolution, header.origin, header.size, filter, pixels, counts, buffers))
+}
+
+/// Reads a film back. The filter is not stored, only its radius which must
+/// match the one of `filter`.
+pub fn read_film<R: Read>(r: &mut R, filter: Box<Filter>) -> io::Result<Film> {
+    let header = read_header(r)?;
+    if header.radius != filter.radius() {
+        return Err(invalid_data("film rendered with another reconstruction filter"));
+    }
+    read_pixels(r, header, filter)
+}
+
+/// Reads back a film expected to cover the same window of the same image as
+/// `template`, with the same filter and AOVs, e.g. the film of a tile handed
+/// out. Anything else is rejected before reading the pixels.
+pub fn read_film_like<R: Read>(r: &mut R, template: &Film) -> io::Result<Film> {
+    let header = read_header(r)?;
+    if header.resolution != template.resolution() || header.origin != template.origin() ||
+       header.size != template.size() {
+        return Err(invalid_data("film of another window of the image"));
+    }
+    if header.radius != template.filter().radius() || header.aovs != template.aovs() {
+        return Err(invalid_data("film rendered with other settings"));
+    }
+    read_pixels(r, header, template.filter().box_clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::filter_from_name;
+    use image::Rgb;
+
+    #[test]
+    fn test_film_window_round_trip() {
+        let full = Film::with_aovs(16, 8, filter_from_name("tent").unwrap(), &[Aov::Normal]);
+        let mut tile = full.tile(4, 2, 8, 6);
+        tile.add_sample((5.5, 3.25), &Rgb { data: [1.5, 0.25, 3.] }, 1.);
+        tile.add_sample_count(5, 3, 1);
+        tile.add_aov_sample(5, 3, Aov::Normal, &[0., 0., 1.]);
+        let mut bytes = vec!();
+        write_film(&mut bytes, &tile).unwrap();
+        let read = read_film(&mut &bytes[..], filter_from_name("tent").unwrap()).unwrap();
+        assert!(read.origin() == tile.origin() && read.size() == tile.size());
+        assert!(read.film_pixels() == tile.film_pixels());
+        assert!(read.aov_buffers() == tile.aov_buffers());
+        assert!(read.sample_count(5, 3) == 1);
+        assert!(read_film(&mut &bytes[..], filter_from_name("box").unwrap()).is_err());
+        assert!(read_film_like(&mut &bytes[..], &full.tile(4, 2, 8, 6)).is_ok());
+        assert!(read_film_like(&mut &bytes[..], &full.tile(8, 2, 12, 6)).is_err());
+    }
+
+    #[test]
+    fn test_film_size_overflow() {
+        let mut bytes = vec!();
+        for &v in [u32::max_value(), u32::max_value(), 0, 0, u32::max_value(), u32::max_value()].iter() {
+            write_u32(&mut bytes, v).unwrap();
+        }
+        write_f64(&mut bytes, 0.5).unwrap();
+        write_f64(&mut bytes, 0.5).unwrap();
+        write_u32(&mut bytes, 0).unwrap();
+        assert!(read_film(&mut &bytes[..], filter_from_name("box").unwrap()).is_err());
+    }
+}
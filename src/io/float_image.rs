@@ -0,0 +1,204 @@
+use film::{Aov, Film};
+use raytracer::CropWindow;
+
+/// Named plane of a floating point image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub data: Vec<f32>
+}
+
+/// Floating point image made of named channels, e.g. `R`, `G`, `B` and `A`.
+/// Each channel holds `width * height` values in scanline order, row 0 being
+/// the top of the image.
+///
+/// The image may be a part of a larger one, e.g. a crop of a render, whose
+/// top left corner is at `origin` in an image of `display_size`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatImage {
+    pub width: u32,
+    pub height: u32,
+    pub origin: (u32, u32),
+    pub display_size: (u32, u32),
+    pub channels: Vec<Channel>
+}
+
+impl FloatImage {
+    pub fn new(width: u32, height: u32) -> FloatImage {
+        FloatImage { width: width, height: height, origin: (0, 0), display_size: (width, height),
+                     channels: vec!() }
+    }
+
+    /// Returns the part of the image within a window, which keeps its
+    /// position in the full image.
+    pub fn crop(&self, window: &CropWindow) -> FloatImage {
+        let window = window.clamp(self.width, self.height);
+        let mut image = FloatImage::new(window.width(), window.height());
+        image.origin = (self.origin.0 + window.x0, self.origin.1 + window.y0);
+        image.display_size = self.display_size;
+        for c in self.channels.iter() {
+            let data = (window.y0..window.y1).flat_map(|y| {
+                let row = (y * self.width) as usize;
+                c.data[row + window.x0 as usize..row + window.x1 as usize].iter().cloned()
+            }).collect();
+            image.add_channel(&c.name, data);
+        }
+        image
+    }
+
+    /// Copies a part of an image back at its position, creating the channels
+    /// missing from this image. Pixels falling outside of it are dropped.
+    pub fn paste(&mut self, part: &FloatImage) {
+        for c in part.channels.iter() {
+            if self.channel(&c.name).is_none() {
+                let zeros = vec![0.; (self.width * self.height) as usize];
+                self.add_channel(&c.name, zeros);
+            }
+            let (width, height) = (self.width, self.height);
+            let (ox, oy) = (part.origin.0 as i64 - self.origin.0 as i64, part.origin.1 as i64 - self.origin.1 as i64);
+            let target = self.channels.iter_mut().find(|t| t.name == c.name).unwrap();
+            for y in 0..part.height {
+                for x in 0..part.width {
+                    let (tx, ty) = (x as i64 + ox, y as i64 + oy);
+                    if tx >= 0 && ty >= 0 && tx < width as i64 && ty < height as i64 {
+                        target.data[(ty * width as i64 + tx) as usize] = c.data[(y * part.width + x) as usize];
+                    }
+                }
+            }
+        }
+    }
+
+    /// Linear radiance of the film in `R`, `G` and `B`, its coverage in `A`,
+    /// and its AOVs in their own layers.
+    pub fn from_film(film: &Film) -> FloatImage {
+        let (width, height) = film.resolution();
+        let mut image = FloatImage::new(width, height);
+        let mut planes = vec![Vec::with_capacity((width * height) as usize); 4];
+        for y in 0..height {
+            for x in 0..width {
+                let color = film.pixel(x, y);
+                for c in 0..3 {
+                    planes[c].push(color[c] as f32);
+                }
+                planes[3].push(film.alpha(x, y) as f32);
+            }
+        }
+        for (name, data) in ["R", "G", "B", "A"].iter().zip(planes.into_iter()) {
+            image.add_channel(name, data);
+        }
+        for aov in film.aovs() {
+            image.add_aov(film, aov, aov.channels());
+        }
+        image
+    }
+
+    /// AOV of the film on its own, in `R`, `G` and `B` or in `Y` for AOVs
+    /// with a single component.
+    pub fn from_aov(film: &Film, aov: Aov) -> Option<FloatImage> {
+        let (width, height) = film.resolution();
+        let mut image = FloatImage::new(width, height);
+        let names: &[&str] = match aov.components() {
+            1 => &["Y"],
+            _ => &["R", "G", "B"]
+        };
+        match film.aovs().contains(&aov) {
+            true => {
+                image.add_aov(film, aov, names);
+                Some(image)
+            }
+            false => None
+        }
+    }
+
+    fn add_aov(&mut self, film: &Film, aov: Aov, names: &[&str]) {
+        let mut planes = vec![Vec::with_capacity((self.width * self.height) as usize); names.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for (plane, v) in planes.iter_mut().zip(film.aov_pixel(x, y, aov).unwrap()) {
+                    plane.push(v as f32);
+                }
+            }
+        }
+        for (name, data) in names.iter().zip(planes.into_iter()) {
+            self.add_channel(name, data);
+        }
+    }
+
+    /// Adds a channel, replacing any channel with the same name.
+    pub fn add_channel(&mut self, name: &str, data: Vec<f32>) {
+        assert!(data.len() == (self.width * self.height) as usize);
+        self.channels.retain(|c| c.name != name);
+        self.channels.push(Channel { name: name.to_string(), data: data });
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&[f32]> {
+        self.channels.iter().find(|c| c.name == name).map(|c| &c.data[..])
+    }
+
+    /// Returns the `R`, `G` and `B` planes, or the first channel three times
+    /// for a greyscale image.
+    pub fn rgb_planes(&self) -> Option<[&[f32]; 3]> {
+        match (self.channel("R"), self.channel("G"), self.channel("B")) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => self.channels.first().map(|c| [&c.data[..], &c.data[..], &c.data[..]])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use film::filter_from_name;
+    use image::Rgb;
+
+    #[test]
+    fn test_from_film() {
+        let mut film = Film::new(2, 1, filter_from_name("box").unwrap());
+        film.add_sample((1.5, 0.5), &Rgb { data: [3., 0.5, 0.] }, 1.);
+        let image = FloatImage::from_film(&film);
+        assert!(image.channel("R").unwrap() == &[0., 3.]);
+        assert!(image.channel("A").unwrap() == &[0., 1.]);
+        assert!(image.rgb_planes().unwrap()[1] == &[0., 0.5]);
+        assert!(image.channels.len() == 4);
+        assert!(FloatImage::from_aov(&film, Aov::Depth).is_none());
+    }
+
+    #[test]
+    fn test_aov_layers() {
+        let mut film = Film::with_aovs(2, 1, filter_from_name("box").unwrap(), &[Aov::Normal, Aov::Depth]);
+        film.add_aov_sample(0, 0, Aov::Depth, &[5.]);
+        film.add_aov_sample(1, 0, Aov::Normal, &[0., 1., 0.]);
+        let image = FloatImage::from_film(&film);
+        assert!(image.channel("Z").unwrap() == &[5., 0.]);
+        assert!(image.channel("N.Y").unwrap() == &[0., 1.]);
+        let normal = FloatImage::from_aov(&film, Aov::Normal).unwrap();
+        assert!(normal.channel("G").unwrap() == &[0., 1.]);
+        assert!(FloatImage::from_aov(&film, Aov::Depth).unwrap().channel("Y").unwrap() == &[5., 0.]);
+    }
+
+    #[test]
+    fn test_crop_and_paste() {
+        let mut image = FloatImage::new(4, 3);
+        image.add_channel("R", (0..12).map(|v| v as f32).collect());
+        let crop = image.crop(&CropWindow::new(1, 1, 3, 5));
+        assert!((crop.width, crop.height, crop.origin, crop.display_size) == (2, 2, (1, 1), (4, 3)));
+        assert!(crop.channel("R").unwrap() == &[5., 6., 9., 10.]);
+
+        let mut full = FloatImage::new(4, 3);
+        full.paste(&crop);
+        let mut expected = vec![0.; 12];
+        for &i in [5, 6, 9, 10].iter() {
+            expected[i] = i as f32;
+        }
+        assert!(full.channel("R").unwrap() == &expected[..]);
+    }
+
+    #[test]
+    fn test_greyscale_planes() {
+        let mut image = FloatImage::new(1, 1);
+        image.add_channel("Z", vec!(2.));
+        image.add_channel("Z", vec!(4.));
+        assert!(image.channels.len() == 1);
+        assert!(image.rgb_planes().unwrap()[2] == &[4.]);
+    }
+}
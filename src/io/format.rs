@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use io::*;
+
+/// Floating point image file format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HdrFormat {
+    Exr(PixelType),
+    Pfm,
+    Radiance
+}
+
+impl HdrFormat {
+    /// Picks the format from the extension of a path, `exr`, `pfm` or `hdr`.
+    /// OpenEXR files are written with `exr_pixel_type` channels.
+    pub fn from_path(path: &Path, exr_pixel_type: PixelType) -> Option<HdrFormat> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        match extension.as_ref().map(|e| &e[..]) {
+            Some("exr") => Some(HdrFormat::Exr(exr_pixel_type)),
+            Some("pfm") => Some(HdrFormat::Pfm),
+            Some("hdr") => Some(HdrFormat::Radiance),
+            _ => None
+        }
+    }
+
+    pub fn save(&self, image: &FloatImage, path: &Path) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        match *self {
+            HdrFormat::Exr(pixel_type) => write_exr(&mut w, image, pixel_type),
+            HdrFormat::Pfm => write_pfm(&mut w, image),
+            HdrFormat::Radiance => write_radiance(&mut w, image)
+        }
+    }
+
+    pub fn load(&self, path: &Path) -> io::Result<FloatImage> {
+        let mut r = BufReader::new(File::open(path)?);
+        match *self {
+            HdrFormat::Exr(_) => read_exr(&mut r),
+            HdrFormat::Pfm => read_pfm(&mut r),
+            HdrFormat::Radiance => read_radiance(&mut r)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_path() {
+        let format = |p: &str| HdrFormat::from_path(Path::new(p), PixelType::Half);
+        assert!(format("render.EXR") == Some(HdrFormat::Exr(PixelType::Half)));
+        assert!(format("out/render.pfm") == Some(HdrFormat::Pfm));
+        assert!(format("render.hdr") == Some(HdrFormat::Radiance));
+        assert!(format("render.png") == None);
+        assert!(format("render") == None);
+    }
+}
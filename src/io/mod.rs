@@ -0,0 +1,16 @@
+pub mod bytes;
+mod float_image;
+mod exr;
+mod pfm;
+mod radiance;
+mod format;
+mod film_data;
+mod checkpoint;
+
+pub use self::float_image::*;
+pub use self::exr::*;
+pub use self::pfm::*;
+pub use self::radiance::*;
+pub use self::format::*;
+pub use self::film_data::*;
+pub use self::checkpoint::*;
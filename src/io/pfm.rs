@@ -0,0 +1,101 @@
+use std::io::{self, Read, Write};
+
+use io::FloatImage;
+use io::bytes::*;
+
+/// Writes a Portable Float Map, in color from the `R`, `G` and `B` channels
+/// of the image or in greyscale from its only channel.
+pub fn write_pfm<W: Write>(w: &mut W, image: &FloatImage) -> io::Result<()> {
+    let greyscale = image.channels.len() == 1;
+    let planes = image.rgb_planes().ok_or(invalid_data("cannot write an image without channels"))?;
+    let planes = match greyscale {
+        true => &planes[..1],
+        false => &planes[..]
+    };
+    // A negative scale means little endian data
+    write!(w, "{}\n{} {}\n-1.0\n", if greyscale { "Pf" } else { "PF" }, image.width, image.height)?;
+    // Rows are stored from the bottom of the image up
+    let width = image.width as usize;
+    for y in (0..image.height as usize).rev() {
+        for x in 0..width {
+            for plane in planes.iter() {
+                write_f32(w, plane[y * width + x])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_token<R: Read>(r: &mut R) -> io::Result<String> {
+    let mut token = String::new();
+    loop {
+        match read_u8(r)? as char {
+            c if c.is_whitespace() && token.is_empty() => (),
+            c if c.is_whitespace() => return Ok(token),
+            c => token.push(c)
+        }
+    }
+}
+
+/// Reads a Portable Float Map into `R`, `G` and `B` channels, or a `Y` channel
+/// for a greyscale map.
+pub fn read_pfm<R: Read>(r: &mut R) -> io::Result<FloatImage> {
+    let names: &[&str] = match &read_token(r)?[..] {
+        "PF" => &["R", "G", "B"],
+        "Pf" => &["Y"],
+        _ => return Err(invalid_data("not a Portable Float Map"))
+    };
+    let width = read_token(r)?.parse::<u32>().map_err(|_| invalid_data("invalid PFM width"))?;
+    let height = read_token(r)?.parse::<u32>().map_err(|_| invalid_data("invalid PFM height"))?;
+    let scale = read_token(r)?.parse::<f32>().map_err(|_| invalid_data("invalid PFM scale"))?;
+    let area = image_area(width as usize, height as usize)?;
+    let mut planes = vec![vec![0.; area]; names.len()];
+    for y in (0..height as usize).rev() {
+        for x in 0..width as usize {
+            for plane in planes.iter_mut() {
+                plane[y * width as usize + x] = match scale < 0. {
+                    true => read_f32(r)?,
+                    false => f32::from_bits(read_u32_be(r)?)
+                };
+            }
+        }
+    }
+    let mut image = FloatImage::new(width, height);
+    for (name, data) in names.iter().zip(planes.into_iter()) {
+        image.add_channel(name, data);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pfm_roundtrip() {
+        let mut image = FloatImage::new(2, 2);
+        image.add_channel("B", vec!(0., 1., 2., 3.));
+        image.add_channel("G", vec!(4., 5., 6., 7.));
+        image.add_channel("R", vec!(1e10, -1., 0.5, 1e-20));
+        let mut bytes = vec!();
+        write_pfm(&mut bytes, &image).unwrap();
+        assert!(bytes.starts_with(b"PF\n2 2\n-1.0\n"));
+        let read = read_pfm(&mut &bytes[..]).unwrap();
+        for c in image.channels.iter() {
+            assert!(read.channel(&c.name).unwrap() == &c.data[..]);
+        }
+    }
+
+    #[test]
+    fn test_pfm_greyscale_big_endian() {
+        let mut bytes = b"Pf\n2 1\n1.0\n".to_vec();
+        bytes.extend_from_slice(&[0x3f, 0x80, 0, 0, 0x40, 0, 0, 0]);
+        let read = read_pfm(&mut &bytes[..]).unwrap();
+        assert!(read.channel("Y").unwrap() == &[1., 2.]);
+        let mut written = vec!();
+        write_pfm(&mut written, &read).unwrap();
+        assert!(written.starts_with(b"Pf\n"));
+        assert!(read_pfm(&mut &written[..]).unwrap() == read);
+        assert!(read_pfm(&mut &b"PF\n4294967295 4294967295\n-1.0\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::io::{self, Read, Write};
+
+use io::FloatImage;
+use io::bytes::*;
+
+/// Encodes a color as a shared exponent RGBE pixel.
+pub fn rgb_to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let v = rgb[0].max(rgb[1]).max(rgb[2]) as f64;
+    if v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    let mut m = v / 2f64.powi(e);
+    if m >= 1. {
+        m /= 2.;
+        e += 1;
+    } else if m < 0.5 {
+        m *= 2.;
+        e -= 1;
+    }
+    if e > 127 {
+        return [255, 255, 255, 255];
+    }
+    let scale = m * 256. / v;
+    let channel = |c: f32| (c.max(0.) as f64 * scale).min(255.) as u8;
+    [channel(rgb[0]), channel(rgb[1]), channel(rgb[2]), (e + 128) as u8]
+}
+
+/// Decodes an RGBE pixel to the center of the range of colors it stands for.
+pub fn rgbe_to_rgb(rgbe: [u8; 4]) -> [f32; 3] {
+    match rgbe[3] {
+        0 => [0., 0., 0.],
+        e => {
+            let f = 2f64.powi(e as i32 - (128 + 8));
+            let channel = |c: u8| ((c as f64 + 0.5) * f) as f32;
+            [channel(rgbe[0]), channel(rgbe[1]), channel(rgbe[2])]
+        }
+    }
+}
+
+/// Number of bytes equal to `bytes[0]` at the start of `bytes`, up to 127.
+fn run_length(bytes: &[u8]) -> usize {
+    bytes.iter().take(127).take_while(|&&b| b == bytes[0]).count()
+}
+
+/// Run length encodes one component of a scanline.
+fn write_rle_component(out: &mut Vec<u8>, bytes: &[u8]) {
+    let mut i = 0;
+    while i < bytes.len() {
+        let run = run_length(&bytes[i..]);
+        if run >= 3 {
+            out.push(128 + run as u8);
+            out.push(bytes[i]);
+            i += run;
+        } else {
+            // Copy literally up to the next run worth encoding
+            let start = i;
+            while i < bytes.len() && i - start < 128 && run_length(&bytes[i..]) < 3 {
+                i += 1;
+            }
+            out.push((i - start) as u8);
+            out.extend_from_slice(&bytes[start..i]);
+        }
+    }
+}
+
+/// Writes a Radiance RGBE picture from the `R`, `G` and `B` channels of the
+/// image, or from its only channel in greyscale.
+pub fn write_radiance<W: Write>(w: &mut W, image: &FloatImage) -> io::Result<()> {
+    let planes = image.rgb_planes().ok_or(invalid_data("cannot write an image without channels"))?;
+    write!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height, image.width)?;
+    let width = image.width as usize;
+    // Scanlines outside of these widths cannot be run length encoded
+    let rle = 8 <= width && width < 0x8000;
+    for y in 0..image.height as usize {
+        let pixels: Vec<[u8; 4]> = (y * width..(y + 1) * width).map(|i| {
+            rgb_to_rgbe([planes[0][i], planes[1][i], planes[2][i]])
+        }).collect();
+        match rle {
+            true => {
+                let mut line = vec!(2, 2, (width >> 8) as u8, width as u8);
+                for c in 0..4 {
+                    let component: Vec<u8> = pixels.iter().map(|p| p[c]).collect();
+                    write_rle_component(&mut line, &component);
+                }
+                w.write_all(&line)?;
+            }
+            false => for p in pixels.iter() {
+                w.write_all(p)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a Radiance RGBE picture with flat or run length encoded scanlines
+/// into `R`, `G` and `B` channels. Only the standard `-Y height +X width`
+/// orientation is supported.
+pub fn read_radiance<R: Read>(r: &mut R) -> io::Result<FloatImage> {
+    if !read_until(r, b'\n')?.starts_with(b"#?") {
+        return Err(invalid_data("not a Radiance picture"));
+    }
+    loop {
+        let line = read_until(r, b'\n')?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(b"FORMAT=") && &line[..] != b"FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("unsupported Radiance pixel format"));
+        }
+    }
+    let resolution = String::from_utf8_lossy(&read_until(r, b'\n')?).into_owned();
+    let tokens: Vec<&str> = resolution.split_whitespace().collect();
+    let (height, width) = match (tokens.get(0), tokens.get(1), tokens.get(2), tokens.get(3)) {
+        (Some(&"-Y"), Some(h), Some(&"+X"), Some(w)) => match (h.parse::<u32>(), w.parse::<u32>()) {
+            (Ok(h), Ok(w)) => (h as usize, w as usize),
+            _ => return Err(invalid_data("invalid Radiance resolution"))
+        },
+        _ => return Err(invalid_data("unsupported Radiance orientation"))
+    };
+
+    if width == 0 && height > 0 {
+        return Err(invalid_data("empty Radiance scanlines"));
+    }
+    let mut planes = vec![vec![0.; image_area(width, height)?]; 3];
+    let mut line = vec![[0u8; 4]; width];
+    for y in 0..height {
+        let mut first = [0; 4];
+        r.read_exact(&mut first)?;
+        let encoded_width = (first[2] as usize) << 8 | first[3] as usize;
+        match first[0] == 2 && first[1] == 2 && encoded_width == width && 8 <= width && width < 0x8000 {
+            true => for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = read_u8(r)? as usize;
+                    let (n, run) = match count > 128 {
+                        true => (count - 128, Some(read_u8(r)?)),
+                        false => (count, None)
+                    };
+                    if n == 0 || x + n > width {
+                        return Err(invalid_data("invalid Radiance scanline"));
+                    }
+                    for p in line[x..x + n].iter_mut() {
+                        p[c] = match run {
+                            Some(b) => b,
+                            None => read_u8(r)?
+                        };
+                    }
+                    x += n;
+                }
+            },
+            false => {
+                line[0] = first;
+                for p in line[1..].iter_mut() {
+                    r.read_exact(p)?;
+                }
+            }
+        }
+        for (x, p) in line.iter().enumerate() {
+            let rgb = rgbe_to_rgb(*p);
+            for c in 0..3 {
+                planes[c][y * width + x] = rgb[c];
+            }
+        }
+    }
+    let mut image = FloatImage::new(width as u32, height as u32);
+    for (name, data) in ["R", "G", "B"].iter().zip(planes.into_iter()) {
+        image.add_channel(name, data);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(image: &FloatImage, read: &FloatImage) {
+        let max = image.channels.iter().flat_map(|c| c.data.iter()).cloned().fold(0., f32::max);
+        for c in image.channels.iter() {
+            for (a, b) in read.channel(&c.name).unwrap().iter().zip(c.data.iter()) {
+                // Components share the exponent of the largest one
+                assert!((a - b).abs() <= max / 128.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgbe() {
+        assert!(rgb_to_rgbe([0., 0., 0.]) == [0, 0, 0, 0]);
+        assert!(rgb_to_rgbe([1., 0.5, 0.]) == [128, 64, 0, 129]);
+        let rgb = rgbe_to_rgb(rgb_to_rgbe([1000., 3., 0.1]));
+        assert!((rgb[0] - 1000.).abs() < 1000. / 256.);
+    }
+
+    #[test]
+    fn test_radiance_rle_roundtrip() {
+        let mut image = FloatImage::new(20, 2);
+        let r: Vec<f32> = (0..40).map(|i| if i < 25 { 1. } else { i as f32 * 0.3 }).collect();
+        image.add_channel("R", r);
+        image.add_channel("G", vec![0.25; 40]);
+        image.add_channel("B", (0..40).map(|i| (i % 3) as f32 * 10.).collect());
+        let mut bytes = vec!();
+        write_radiance(&mut bytes, &image).unwrap();
+        let read = read_radiance(&mut &bytes[..]).unwrap();
+        assert!(read.width == 20 && read.height == 2);
+        assert_close(&image, &read);
+    }
+
+    #[test]
+    fn test_radiance_flat_roundtrip() {
+        let mut image = FloatImage::new(3, 1);
+        image.add_channel("Y", vec!(0.5, 2., 40.));
+        let mut bytes = vec!();
+        write_radiance(&mut bytes, &image).unwrap();
+        let read = read_radiance(&mut &bytes[..]).unwrap();
+        for c in ["R", "G", "B"].iter() {
+            let data = read.channel(c).unwrap();
+            assert!(data[2] == 40.125);
+            assert!((data[0] - 0.5).abs() < 0.5 / 128.);
+        }
+        assert!(read_radiance(&mut &b"#?RADIANCE\n\n-Y 2 +X 0\n\0\0\0\0"[..]).is_err());
+    }
+}
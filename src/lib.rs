@@ -6,14 +6,21 @@ extern crate nalgebra;
 extern crate num_traits;
 extern crate rand;
 extern crate rayon;
+extern crate rustc_serialize;
 
 pub mod algebra;
 pub mod camera;
+pub mod description;
+pub mod distributed;
+pub mod film;
 pub mod intersection;
+pub mod io;
 pub mod light;
 pub mod material;
 pub mod objects;
 pub mod ray;
 pub mod raytracer;
 pub mod scene;
+pub mod scene_graph;
+pub mod service;
 pub mod util;
@@ -3,239 +3,419 @@
 
 extern crate docopt;
 extern crate image;
-extern crate nalgebra;
-extern crate num_traits;
 extern crate rand;
 extern crate rustc_serialize;
 extern crate rust_raytracer;
 
 use image::*;
-use num_traits::*;
-use nalgebra::*;
 
 use std::boxed::Box as StdBox;
+use std::collections::BTreeMap;
 use std::f64::consts::PI;
-use std::path::Path;
-
-use rust_raytracer::camera::Perspective;
-use rust_raytracer::light::Light;
-use rust_raytracer::material::{Phong, LightMaterial};
-use rust_raytracer::objects::*;
-use rust_raytracer::ray::Ray;
-use rust_raytracer::scene::Scene;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rust_raytracer::description::*;
+use rust_raytracer::distributed::{run_worker, Coordinator, Job};
+use rust_raytracer::film::{denoise, tone_operator_from_name, Aov, DenoiseSettings, Film, ToneMapping};
+use rust_raytracer::io::{load_checkpoint, save_checkpoint, CheckpointHeader, FloatImage, HdrFormat, PixelType};
+use rust_raytracer::raytracer::*;
+use rust_raytracer::service::{JobLimits, RenderServer};
 use rust_raytracer::util::*;
 
 docopt!(Args, "
-Usage: raytrace <output> <width> <height> <N> <B>",
-arg_width: u32, arg_height: u32, arg_N: u32, arg_B: u32);
+Usage: raytrace <output> <width> <height> <N> <B> [options]
+       raytrace stitch <output> <crops>... [--exr-float]
+       raytrace worker <address>
+       raytrace serve <address> [--concurrency=<n>] [--max-pixels=<n>] [--max-job-samples=<n>]
+
+N is the number of samples per pixel and B the number of reflection bounces.
+Outputs ending in .exr, .pfm or .hdr hold the linear radiance of the render,
+other formats are tone mapped 8 bits sRGB images.
+
+Renders of a crop only hold the crop. Those saved as OpenEXR remember where
+they belong, and the stitch command puts them back together into <output>.
+
+Frames can be rendered by worker processes, started with the worker command
+on any machine and connecting to the address given to --listen.
+
+The serve command renders the scenes submitted to its HTTP API on <address>.
+
+Options:
+    --seed=<seed>         Seed of the random number generators [default: 0].
+    --adaptive=<error>    Sample adaptively, from N samples per pixel until the
+                          relative error of the pixel falls under <error>.
+    --max-samples=<max>   Maximum samples per pixel with --adaptive [default: 256].
+    --heatmap=<file>      Save a heat map of the samples taken per pixel.
+    --filter=<filter>     Pixel reconstruction filter, one of box, tent,
+                          gaussian, mitchell or lanczos [default: box].
+    --exr-float           Store OpenEXR channels as 32 bits floats instead of
+                          half floats.
+    --exposure=<ev>       Exposure compensation in stops [default: 0].
+    --tonemap=<op>        Tone mapping operator, one of clamp, reinhard,
+                          reinhard-extended, aces or hable [default: clamp].
+    --white=<luminance>   White point of reinhard-extended and hable.
+    --aovs=<list>         Comma separated extra buffers to render among depth,
+                          normal, albedo, object-id, material-id, direct and
+                          indirect. They are layers of .exr outputs, and files
+                          named after the output otherwise.
+    --denoise             Denoise the render, guided by its normal, albedo and
+                          depth, which are rendered for that purpose. Not
+                          available with --crop or --part.
+    --tile-size=<size>    Side of the tiles rendered in parallel [default: 16].
+    --tile-order=<order>  Order of the tiles, one of scanline, spiral or
+                          hilbert [default: spiral].
+    --progressive         Render N passes of one sample per pixel, or up to
+                          --max-samples with --adaptive, saving the output as
+                          the render goes.
+    --snapshot-interval=<seconds>
+                          Minimum time between saves of progressive renders
+                          [default: 10].
+    --time-budget=<seconds>
+                          Stop progressive renders after this time.
+    --noise-threshold=<error>
+                          Stop progressive renders once the relative error of
+                          every pixel falls under <error>.
+    --crop=<x0,y0,x1,y1>  Only render the pixels from (x0, y0) included to
+                          (x1, y1) excluded, y going down.
+    --part=<i/n>          Only render the i-th of n horizontal bands of the
+                          image, from 0.
+    --listen=<address>    Hand the tiles of the frame out to the workers
+                          connecting to <address>, e.g. 0.0.0.0:7878.
+    --workers=<n>         Start n workers on this machine, listening on a
+                          local port unless --listen is given [default: 0].
+    --checkpoint=<file>   Save the state of progressive renders to <file> with
+                          each snapshot.
+    --resume              Resume the progressive render saved in the checkpoint.",
+arg_width: u32, arg_height: u32, arg_N: u32, arg_B: u32, flag_seed: u32,
+flag_adaptive: Option<f64>, flag_max_samples: u32, flag_heatmap: Option<String>,
+flag_filter: String, flag_exr_float: bool, flag_exposure: f64, flag_tonemap: String,
+flag_white: Option<f64>, flag_aovs: Option<String>, flag_denoise: bool, flag_tile_size: u32,
+flag_tile_order: String, flag_progressive: bool, flag_snapshot_interval: f64,
+flag_time_budget: Option<f64>, flag_noise_threshold: Option<f64>, flag_checkpoint: Option<String>,
+flag_resume: bool, flag_crop: Option<String>, flag_part: Option<String>, flag_listen: Option<String>,
+flag_workers: u32);
+
+docopt!(StitchArgs, "
+Usage: raytrace stitch <output> <crops>... [--exr-float]
+", flag_exr_float: bool);
 
-fn print_progress(progress: f64) {
-    println!("\x1B[1A\x1B[2K{}%", progress * 100.);
+docopt!(WorkerArgs, "
+Usage: raytrace worker <address>
+");
+
+docopt!(ServeArgs, "
+Usage: raytrace serve <address> [--concurrency=<n>] [--max-pixels=<n>] [--max-job-samples=<n>]
+
+Options:
+    --concurrency=<n>  Number of jobs rendered at the same time [default: 1].
+    --max-pixels=<n>   Largest image accepted [default: 16777216].
+    --max-job-samples=<n>
+                       Most samples accepted for a job, over all its pixels
+                       [default: 4294967296].
+", flag_concurrency: u32, flag_max_pixels: u64, flag_max_job_samples: u64);
+
+/// Where and how renders are saved.
+struct Output {
+    path: PathBuf,
+    tone: ToneMapping,
+    exr_pixel_type: PixelType,
+    aovs: Vec<Aov>,
+    denoise: bool,
+    crop: Option<CropWindow>
 }
 
-fn reflection_ray(scene: &Scene, ray: &Ray, bounces: u32) -> Rgb<f64> {
-    let mut pixel = Rgb { data: [0., 0., 0.] };
-    let intersect_opt = scene.intersects(ray);
-    if let Some(intersect) = intersect_opt {
-        match intersect.object {
-            &Object::Light(ref l) => {
-                // Return the light diffuse color and stop bouncing
-                pixel = l.light_material().diffuse_intensity;
-            },
-            &Object::Surface(ref s) => {
-                // Cast light ray and compute Phong shading
-                let surface_normal = intersect.normal;
-                for light in scene.lights() {
-                    let p = light.random_on_face();
-                    let light_ray = Ray::between(intersect.position, p);
-                    match scene.intersects(&light_ray) {
-                        None => (),
-                        Some(light_inter) => {
-                            if light_inter.object == &Object::from_light(light.clone()) {
-                                let ray_diffuse_color = light.shade_diffuse(surface_normal, &intersect.object, &light_ray, &light_inter);
-                                let ray_specular_color = light.shade_specular(scene.camera().eye_position(), surface_normal, &intersect.object, &light_ray, &light_inter);
-                                let mut color = rgb_add(&ray_diffuse_color, &intersect.object.material().ambient_color());
-                                color = rgb_add(&color, &ray_specular_color);
-
-                                // Cast a reflection ray for glossy reflection
-                                if bounces != 0 {
-                                    let l = light_ray.direction.normalize();
-                                    let dln = l.dot(&surface_normal);
-                                    let r = 2. * dln * surface_normal - l;
-                                    let refl_ray = Ray::new(intersect.position, random_in_cone(r, (30.).to_radians()));
-                                    let reflection_color = reflection_ray(scene, &refl_ray, bounces - 1);
-                                    color = rgb_add(&color, &reflection_color);
-                                    pixel = color;
-                                }
-                            }
-                        }
-                    }
-                }
+impl Output {
+    fn save(&self, film: &Film) {
+        let denoised;
+        let film = match self.denoise {
+            true => {
+                denoised = denoise(film, &DenoiseSettings::new());
+                &denoised
+            }
+            false => film
+        };
+        let crop = |image: FloatImage| match self.crop {
+            Some(window) => image.crop(&window),
+            None => image
+        };
+        let format = HdrFormat::from_path(&self.path, self.exr_pixel_type);
+        let saved = match (format, self.crop) {
+            (Some(format), _) => format.save(&crop(FloatImage::from_film(film)), &self.path),
+            (None, Some(w)) => imageops::crop(&mut film.to_rgb_image(&self.tone), w.x0, w.y0, w.width(), w.height())
+                                   .to_image().save(&self.path),
+            (None, None) => film.to_rgb_image(&self.tone).save(&self.path)
+        };
+        saved.expect("Cannot save output image");
+        // OpenEXR outputs hold the AOVs as layers, other formats get a file per AOV
+        let aov_format = match format {
+            Some(HdrFormat::Exr(_)) => None,
+            Some(format) => Some((format, self.path.extension().unwrap().to_string_lossy().into_owned())),
+            None => Some((HdrFormat::Exr(self.exr_pixel_type), "exr".to_string()))
+        };
+        if let Some((format, extension)) = aov_format {
+            let stem = self.path.file_stem().unwrap().to_string_lossy().into_owned();
+            for aov in self.aovs.iter() {
+                let path = self.path.with_file_name(format!("{}.{}.{}", stem, aov.name(), extension));
+                let image = crop(FloatImage::from_aov(film, *aov).unwrap());
+                format.save(&image, &path).expect("Cannot save AOV");
             }
         }
     }
-    else {
-        pixel = scene.background();
-    }
-    pixel
 }
 
-fn ray_energy(scene: &Scene, ray: &Ray, bounces: u32) -> Rgb<f64> {
-    // Find closest intersection
-    let intersect_opt = scene.intersects(&ray);
-    let mut pixel = Rgb { data: [0., 0., 0.] };
-
-    if let Some(intersect) = intersect_opt {
-        match intersect.object {
-            &Object::Light(ref l) => {
-                // Paint the light with its diffuse color
-                pixel = l.light_material().diffuse_intensity;
-            },
-            &Object::Surface(ref s) => {
-                // Cast light ray and compute Phong shading
-                let surface_normal = intersect.normal;
-                for light in scene.lights() {
-                    let p = light.random_on_face();
-                    let light_ray = Ray::between(intersect.position, p);
-                    match scene.intersects(&light_ray) {
-                        None => (),
-                        Some(light_inter) => {
-                            if light_inter.object == &Object::from_light(light.clone()) {
-                                let ray_diffuse_color = light.shade_diffuse(surface_normal, &intersect.object, &light_ray, &light_inter);
-                                let ray_specular_color = light.shade_specular(scene.camera().eye_position(), surface_normal, &intersect.object, &light_ray, &light_inter);
-                                let mut color = rgb_add(&ray_diffuse_color, &intersect.object.material().ambient_color());
-                                color = rgb_add(&color, &ray_specular_color);
-
-                                // Cast a reflection ray for glossy reflection
-                                if bounces != 0 {
-                                    let l = light_ray.direction.normalize();
-                                    let dln = l.dot(&surface_normal);
-                                    let r = 2. * dln * surface_normal - l;
-                                    let refl_ray = Ray::new(intersect.position, random_in_cone(r, (30.).to_radians()));
-                                    let reflection_color = rgb_div(&reflection_ray(scene, &refl_ray, bounces), bounces as f64);
-                                    color = rgb_add(&color, &reflection_color);
-                                    color = rgb_div(&color, 2.);
-                                }
-
-                                pixel = color;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+fn print_progress(event: &RenderEvent) {
+    let progress = event.progress();
+    let eta = progress.eta.map_or(String::new(), |eta| format!(", {}s left", eta.as_secs()));
+    println!("\x1B[1A\x1B[2K{:.1}%, {} rays{}", progress.fraction * 100., progress.rays, eta);
+}
+
+/// The Cornell box like scene rendered by `rt`.
+fn scene_description(width: u32, height: u32) -> SceneDescription {
+    let phong = |diffuse: [f64; 3], specular: [f64; 3]| {
+        MaterialDescription::Phong { ambient: [0.1, 0.1, 0.1], diffuse: diffuse, specular: specular, shininess: 2. }
+    };
+    let mut materials = BTreeMap::new();
+    materials.insert("blue".to_string(), phong([0.1, 0.2, 1.], [0.4, 0.4, 0.4]));
+    materials.insert("red".to_string(), phong([1., 0.2, 0.1], [0.6, 0.6, 0.6]));
+    materials.insert("grey".to_string(), phong([0.6, 0.6, 0.6], [0.6, 0.6, 0.6]));
+    let material_blue = MaterialDescription::Named("blue".to_string());
+    let material_red = MaterialDescription::Named("red".to_string());
+    let material_grey = MaterialDescription::Named("grey".to_string());
+    let wall = |translation, rotation| {
+        ObjectDescription::Face { width: 50., height: 50., material: material_grey.clone(),
+                                  transform: TransformDescription::new(translation, rotation) }
+    };
+    let wall_left = wall([-2., 0., -2.], [0., PI / 2., 0.]);
+    let wall_right = wall([2., 0., -2.], [0., -(PI / 2.), 0.]);
+    let wall_back = wall([0., 0., -5.], [0., 0., 0.]);
+    let ceiling = wall([0., 3., 0.], [PI / 2., 0., 0.]);
+    let ground = wall([0., 0., -2.5], [-(PI / 2.), 0., 0.]);
+    let box1 = ObjectDescription::Box { size: [1., 1., 1.], material: material_blue, faces: None,
+                                        transform: TransformDescription::new([1., 0.5, -4.], [0., 0., 0.]) };
+    let box2 = ObjectDescription::Box { size: [1., 2., 1.], material: material_red, faces: None,
+                                        transform: TransformDescription::new([-1., 1., -4.], [0., PI / 4., 0.]) };
+    let light = ObjectDescription::Light { width: 0.5, height: 0.5, material: material_grey.clone(),
+                                           transform: TransformDescription::new([0., 2.99, -3.], [PI / 2., 0., 0.]),
+                                           diffuse: [0.6, 0.6, 0.6], specular: [0.25, 0.25, 0.25] };
+
+    let camera = CameraDescription::Perspective { resolution: [width, height],
+                                                  fov: [(90f64).to_radians(), (70f64).to_radians()],
+                                                  transform: TransformDescription::new([0., 1.8, 0.], [0., 0., 0.]) };
+    SceneDescription { background: [0.3, 0.3, 0.3], materials: materials,
+                       objects: vec!(wall_left, wall_right, wall_back, ceiling, ground, box1, box2, light),
+                       camera: camera }
+}
+
+/// Renders a frame with the workers connecting to `address`, starting
+/// `local_workers` of them on this machine.
+fn render_distributed(job: Job, address: &str, local_workers: u32) -> Film {
+    let coordinator = Coordinator::bind(address, job).unwrap_or_else(|e| {
+        println!("Cannot listen on {}: {}", address, e);
+        std::process::exit(1)
+    });
+    let address = coordinator.local_addr().expect("Cannot get the coordinator address");
+    println!("Waiting for workers on {}", address);
+    let exe = std::env::current_exe().expect("Cannot find the rt executable");
+    let mut workers: Vec<_> = (0..local_workers).map(|_| {
+        Command::new(&exe).arg("worker").arg(address.to_string()).spawn().expect("Cannot start a worker")
+    }).collect();
+    let film = coordinator.render().expect("Distributed render failed");
+    for worker in workers.iter_mut() {
+        let _ = worker.wait();
     }
-    else {
-        pixel = scene.background();
+    film
+}
+
+/// Renders the tiles handed out by a coordinator.
+fn worker() {
+    let args: WorkerArgs = WorkerArgs::docopt().decode().unwrap_or_else(|e| e.exit());
+    if let Err(e) = run_worker(&args.arg_address[..]) {
+        println!("Worker stopped: {}", e);
+        std::process::exit(1)
     }
-    pixel
 }
 
-fn correct_gamma(p: &Rgb<f64>) -> Rgb<f64> {
-    const A: f64 = 0.055;
-    p.map(|c| {
-        match c <= 0.0031308 {
-            true => 12.92 * c,
-            false => (1. + A) * c.powf(1. / 2.4) - A
-        }
-    })
+/// Renders the jobs submitted over HTTP.
+fn serve() {
+    let args: ServeArgs = ServeArgs::docopt().decode().unwrap_or_else(|e| e.exit());
+    let limits = JobLimits::new(args.flag_max_pixels, args.flag_max_job_samples);
+    let server = RenderServer::bind(&args.arg_address[..], args.flag_concurrency, limits).unwrap_or_else(|e| {
+        println!("Cannot listen on {}: {}", args.arg_address, e);
+        std::process::exit(1)
+    });
+    println!("Serving renders on {}", server.local_addr().expect("Cannot get the server address"));
+    if let Err(e) = server.run() {
+        println!("Server stopped: {}", e);
+        std::process::exit(1)
+    }
 }
 
-fn render(scene: &Scene, n: u32, bounces: u32) -> RgbImage {
-    let (width, height) = scene.camera().viewport();
-    let mut img = RgbImage::new(width, height);
-    let n_pixels = (width * height) as f64;
-    let mut i = 0;
-
-    for (x, y_inverted, pixel) in img.enumerate_pixels_mut() {
-        print_progress(i as f64 / n_pixels);
-
-        let step = 1. / (n + 1) as f64;
-        let y = height - 1 - y_inverted;
-        let mut yf = (y as f64) + step / 2.;
-        let mut energy = Rgb { data: [0., 0., 0.] };
-        while yf < (y + 1) as f64 {
-            let mut xf = (x as f64) + step / 2.;
-            while xf < (x + 1) as f64 {
-                let ray = scene.camera().pixel_ray((xf, yf)).unwrap();
-                let ray_energy = ray_energy(scene, &ray, bounces);
-                energy = rgb_add(&energy, &ray_energy);
-                xf += step;
-            }
-            yf += step;
+/// Puts crops saved as OpenEXR back into the full image.
+fn stitch() {
+    let args: StitchArgs = StitchArgs::docopt().decode().unwrap_or_else(|e| e.exit());
+    let load = |path: &String| {
+        HdrFormat::Exr(PixelType::Half).load(Path::new(path)).unwrap_or_else(|e| {
+            println!("Cannot read {}: {}", path, e);
+            std::process::exit(1)
+        })
+    };
+    let crops: Vec<FloatImage> = args.arg_crops.iter().map(load).collect();
+    let (width, height) = crops[0].display_size;
+    if crops.iter().any(|c| c.display_size != (width, height)) {
+        println!("The crops come from images of different sizes");
+        std::process::exit(1)
+    }
+    let pixel_type = if args.flag_exr_float { PixelType::Float } else { PixelType::Half };
+    let mut image = FloatImage::new(width, height);
+    for crop in crops.iter() {
+        image.paste(crop);
+    }
+    let output = Path::new(&args.arg_output);
+    match HdrFormat::from_path(output, pixel_type) {
+        Some(format) => format.save(&image, output).expect("Cannot save output image"),
+        None => {
+            println!("Stitched images can only be saved as .exr, .pfm or .hdr");
+            std::process::exit(1)
         }
-        energy = rgb_clamp_0_1(&rgb_div(&energy, (n * n) as f64));
-        *pixel = rgb_to_u8(&rgb_01_to_255(&correct_gamma(&energy)));
-        //*pixel = rgb_to_u8(&rgb_01_to_255(&energy));
-        i += 1;
     }
-    img
 }
 
 fn main() {
+    match std::env::args().nth(1) {
+        Some(ref command) if command == "stitch" => return stitch(),
+        Some(ref command) if command == "worker" => return worker(),
+        Some(ref command) if command == "serve" => return serve(),
+        _ => ()
+    }
     let args: Args = Args::docopt().decode().unwrap_or_else(|e| e.exit());
     let width = args.arg_width;
     let height = args.arg_height;
     let output = args.arg_output;
     let output_path = Path::new(&output);
+    let operator = tone_operator_from_name(&args.flag_tonemap).unwrap_or_else(|| {
+        println!("Unknown tone mapping operator: {}", args.flag_tonemap);
+        std::process::exit(1)
+    });
+    let operator = args.flag_white.map_or(operator, |white| operator.with_white(white));
+    let tone = ToneMapping::new(operator, args.flag_exposure);
+    let aovs: Vec<Aov> = args.flag_aovs.as_ref().map_or(vec!(), |list| {
+        list.split(',').map(|name| Aov::from_name(name.trim()).unwrap_or_else(|| {
+            println!("Unknown AOV: {}", name);
+            std::process::exit(1)
+        })).collect()
+    });
 
-    let ambient = Rgb { data: [0.1, 0.1, 0.1] };
-    let material_blue = Phong::new(ambient,
-                                   Rgb { data: [0.1, 0.2, 1.] },
-                                   Rgb { data: [0.4, 0.4, 0.4] },
-                                   2.);
-    let material_red  = Phong::new(ambient,
-                                   Rgb { data: [1., 0.2, 0.1] },
-                                   Rgb { data: [0.6, 0.6, 0.6] },
-                                   2.);
-    let material_grey = Phong::new(ambient,
-                                   Rgb { data: [0.6, 0.6, 0.6] },
-                                   Rgb { data: [0.6, 0.6, 0.6] },
-                                   2.);
-    let material_light  = LightMaterial::new(Rgb { data: [0.6, 0.6, 0.6] },
-                                             Rgb { data: [0.25, 0.25, 0.25] });
-    let wall_left = Face::new(50., 50.,
-                              Isometry3::new(Vector3::new(-2., 0., -2.), Vector3::y() * (PI / 2.)),
-                              StdBox::new(material_grey.clone()));
-    let wall_right = Face::new(50., 50.,
-                               Isometry3::new(Vector3::new(2., 0., -2.), Vector3::y() * -(PI / 2.)),
-                               StdBox::new(material_grey.clone()));
-    let wall_back = Face::new(50., 50.,
-                              Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zero()),
-                              StdBox::new(material_grey.clone()));
-    let ceiling = Face::new(50., 50.,
-                            Isometry3::new(Vector3::new(0., 3., 0.), Vector3::x() * PI / 2.),
-                            StdBox::new(material_grey.clone()));
-    let ground = Face::new(50., 50.,
-                           Isometry3::new(Vector3::new(0., 0., -2.5), Vector3::x() * -(PI / 2.)),
-                           StdBox::new(material_grey.clone()));
-    let box1 = Box::new(Vector3::new(1., 1., 1.),
-                        Isometry3::new(Vector3::new(1., 0.5, -4.), Vector3::zero()),
-                        StdBox::new(material_blue));
-    let box2 = Box::new(Vector3::new(1., 2., 1.),
-                        Isometry3::new(Vector3::new(-1., 1., -4.), Vector3::y() * PI / 4.),
-                        StdBox::new(material_red));
-    let light = Light::new(Face::new(0.5, 0.5,
-                                     Isometry3::new(Vector3::new(0., 2.99, -3.), Vector3::x() * (PI / 2.)),
-                                     StdBox::new(material_grey.clone())),
-                           material_light.clone());
-
-    let cam_transform = Isometry3::new(Vector3::new(0., 1.8, 0.), Vector3::zero());
-    let cam = Perspective::new((width, height),
-                               ((90.).to_radians(), (70.).to_radians()),
-                               cam_transform);
-    let scene = Scene::new(Rgb { data: [0.3, 0.3, 0.3] },
-                           vec!(Object::from_surface(Surface::from_face(wall_left)),
-                                Object::from_surface(Surface::from_face(wall_right)),
-                                Object::from_surface(Surface::from_face(wall_back)),
-                                Object::from_surface(Surface::from_face(ceiling)),
-                                Object::from_surface(Surface::from_face(ground)),
-                                Object::from_surface(Surface::from_box(box1)),
-                                Object::from_surface(Surface::from_box(box2)),
-                                Object::from_light(light)),
-                           StdBox::new(cam));
+    let scene = scene_description(width, height);
+
+    if args.flag_adaptive.is_some() && (args.arg_N == 0 || args.arg_N > args.flag_max_samples) {
+        println!("Adaptive sampling needs 0 < N <= --max-samples");
+        std::process::exit(1)
+    }
+    let max_samples = args.flag_adaptive.map_or(args.arg_N, |_| args.flag_max_samples);
+    let mut render_aovs = aovs.clone();
+    if args.flag_denoise {
+        for aov in [Aov::Normal, Aov::Albedo, Aov::Depth].iter() {
+            if !render_aovs.contains(aov) {
+                render_aovs.push(*aov);
+            }
+        }
+    }
+    let part = args.flag_part.as_ref().map(|part| {
+        let numbers: Vec<u32> = part.split('/').filter_map(|n| n.parse().ok()).collect();
+        match numbers.len() == 2 && numbers[0] < numbers[1] {
+            true => CropWindow::band(width, height, numbers[0], numbers[1]),
+            false => {
+                println!("Invalid part: {}", part);
+                std::process::exit(1)
+            }
+        }
+    });
+    let crop = args.flag_crop.as_ref().map(|crop| CropWindow::parse(crop).unwrap_or_else(|| {
+        println!("Invalid crop window: {}", crop);
+        std::process::exit(1)
+    }).clamp(width, height)).or(part);
+    if args.flag_denoise && crop.is_some() {
+        // The denoiser would see the pixels around the crop as black
+        println!("Crops cannot be denoised, denoise the whole image instead");
+        std::process::exit(1)
+    }
+    let render = RenderDescription {
+        n_samples: args.arg_N, bounces: args.arg_B, seed: args.flag_seed,
+        adaptive: args.flag_adaptive.map(|error| (args.arg_N, args.flag_max_samples, error)),
+        filter: args.flag_filter.clone(), aovs: render_aovs.iter().map(|a| a.name().to_string()).collect(),
+        tile_size: args.flag_tile_size, tile_order: args.flag_tile_order.clone(),
+        crop: crop.map(|c| [c.x0, c.y0, c.x1, c.y1])
+    };
+    let mut settings = render.build().unwrap_or_else(|e| {
+        println!("Invalid render settings: {}", e);
+        std::process::exit(1)
+    });
+    settings.observer = Some(StdBox::new(print_progress));
+    let built_scene = scene.build().unwrap_or_else(|e| {
+        println!("Unknown material or invalid scene: {}", e);
+        std::process::exit(1)
+    });
+    let header = CheckpointHeader { scene_hash: built_scene.hash(), seed: args.flag_seed, passes: max_samples,
+                                    bounces: args.arg_B, filter: args.flag_filter.clone(),
+                                    aovs: settings.aovs.clone(), adaptive: render.adaptive, crop: render.crop };
+    let renderer = Simple::new(built_scene, settings, Jittered::with_seed(args.flag_seed));
+    let distributed = args.flag_listen.is_some() || args.flag_workers > 0;
+    if distributed && args.flag_progressive {
+        println!("Progressive renders cannot be distributed");
+        std::process::exit(1)
+    }
+
+    let output = Output { path: output_path.to_path_buf(), tone: tone,
+                          exr_pixel_type: if args.flag_exr_float { PixelType::Float } else { PixelType::Half },
+                          aovs: aovs, denoise: args.flag_denoise, crop: crop };
 
     println!("");
-    let render = render(&scene, args.arg_N, args.arg_B);
-    render.save(output_path).expect("Cannot save output image");
+    let film = match args.flag_progressive {
+        false if distributed => {
+            let address = args.flag_listen.clone().unwrap_or("127.0.0.1:0".to_string());
+            let film = render_distributed(Job { scene: scene, render: render }, &address, args.flag_workers);
+            output.save(&film);
+            film
+        }
+        true => {
+            let mut progressive = ProgressiveSettings::new(max_samples);
+            progressive.time_budget = args.flag_time_budget.map(duration_from_secs_f64);
+            progressive.noise_threshold = args.flag_noise_threshold;
+            progressive.snapshot_interval = Some(duration_from_secs_f64(args.flag_snapshot_interval));
+            let checkpoint = args.flag_checkpoint.as_ref().map(Path::new);
+            let snapshot = |state: &ProgressiveState| {
+                output.save(&state.film);
+                if let Some(path) = checkpoint {
+                    save_checkpoint(path, &header, state).expect("Cannot save checkpoint");
+                }
+            };
+            match (args.flag_resume, checkpoint) {
+                (true, Some(path)) => {
+                    let (saved, state) = load_checkpoint(path).unwrap_or_else(|e| {
+                        println!("Cannot resume from {}: {}", path.display(), e);
+                        std::process::exit(1)
+                    });
+                    if saved != header {
+                        println!("The checkpoint was saved from another scene or with other settings");
+                        std::process::exit(1)
+                    }
+                    renderer.resume_progressive(&progressive, state, snapshot)
+                }
+                (true, None) => {
+                    println!("--resume needs a --checkpoint");
+                    std::process::exit(1)
+                }
+                (false, _) => renderer.render_progressive(&progressive, snapshot)
+            }
+        }
+        false => {
+            let film = renderer.render_parallel();
+            output.save(&film);
+            film
+        }
+    };
+    if let Some(heatmap) = args.flag_heatmap {
+        sample_heatmap(&film.sample_counts(), max_samples).save(Path::new(&heatmap)).expect("Cannot save heat map");
+    }
 }
@@ -0,0 +1,74 @@
+use material::Material;
+use std::sync::Arc;
+
+/// Materials of a scene by name. Objects hold the handle returned when adding
+/// a material, which they share instead of owning copies of the material.
+#[derive(Debug, Clone)]
+pub struct MaterialLibrary {
+    materials: Vec<(String, Arc<Material>)>
+}
+
+/// Tells whether two handles refer to the same material.
+pub fn same_handle(a: &Arc<Material>, b: &Arc<Material>) -> bool {
+    &**a as *const Material as *const u8 == &**b as *const Material as *const u8
+}
+
+impl MaterialLibrary {
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary { materials: vec!() }
+    }
+
+    /// Adds a material, replacing any material with the same name, and
+    /// returns its handle.
+    pub fn add(&mut self, name: &str, material: Arc<Material>) -> Arc<Material> {
+        match self.materials.iter().position(|&(ref n, _)| n == name) {
+            Some(i) => self.materials[i].1 = material.clone(),
+            None => self.materials.push((name.to_string(), material.clone()))
+        }
+        material
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Material>> {
+        self.materials.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref m)| m.clone())
+    }
+
+    /// Returns the name of the material a handle refers to, if it is part of
+    /// the library.
+    pub fn name(&self, material: &Arc<Material>) -> Option<&str> {
+        self.materials.iter().find(|&&(_, ref m)| same_handle(m, material)).map(|&(ref n, _)| &n[..])
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.materials.iter().map(|&(ref n, _)| &n[..]).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use material::Simple;
+
+    #[test]
+    fn test_library() {
+        let mut library = MaterialLibrary::new();
+        let red = library.add("red", Arc::new(Simple::new(Rgb { data: [1., 0., 0.] })));
+        library.add("blue", Arc::new(Simple::new(Rgb { data: [0., 0., 1.] })));
+        assert!(same_handle(&library.get("red").unwrap(), &red));
+        assert!(library.name(&red) == Some("red"));
+        assert!(library.get("green").is_none());
+        let copy: Arc<Material> = Arc::new(Simple::new(Rgb { data: [1., 0., 0.] }));
+        assert!(library.name(&copy).is_none());
+        library.add("red", copy.clone());
+        assert!(library.names() == vec!("red", "blue"));
+        assert!(library.name(&red).is_none() && library.name(&copy) == Some("red"));
+    }
+}
@@ -1,7 +1,9 @@
 use image::Rgb;
 use std::fmt::Debug;
 
-pub trait Material: Debug + Sync {
+/// Materials are `Send` so that surfaces can be shared between threads by
+/// instances.
+pub trait Material: Debug + Sync + Send {
     fn diffuse_color(&self) -> Rgb<f64>;
     fn ambient_color(&self) -> Rgb<f64>;
     fn specular_color(&self) -> Rgb<f64>;
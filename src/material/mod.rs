@@ -2,8 +2,10 @@ mod material;
 mod simple;
 mod phong;
 mod light;
+mod library;
 
 pub use self::material::Material;
 pub use self::simple::Simple;
 pub use self::phong::Phong;
 pub use self::light::LightMaterial;
+pub use self::library::*;
@@ -1,10 +1,29 @@
 use objects::Face;
+use algebra::{AnimatedTransform, Transform};
 use material::Material;
 use nalgebra::*;
-use std::boxed::Box as StdBox;
+use std::sync::Arc;
 use num_traits::{One, Zero};
 use std::f64::consts::PI;
 
+/// Side of a box, named as seen from the front of the box before it is
+/// transformed, the top facing y and the front facing z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxFace {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    Front,
+    Back
+}
+
+impl BoxFace {
+    pub fn all() -> [BoxFace; 6] {
+        [BoxFace::Top, BoxFace::Bottom, BoxFace::Left, BoxFace::Right, BoxFace::Front, BoxFace::Back]
+    }
+}
+
 #[derive(Debug)]
 pub struct Box {
     pub top: Face,
@@ -13,40 +32,131 @@ pub struct Box {
     pub right: Face,
     pub front: Face,
     pub back: Face,
-    pub transform: Isometry3<f64>,
+    pub transform: Transform,
+    /// Keyframed transform of the whole box, overriding `transform`. It has
+    /// no scale, so moving boxes must have a rigid `transform`, which
+    /// `set_motion` checks. The faces keep their rest pose and rays are moved
+    /// into it at intersection time instead.
+    pub motion: Option<AnimatedTransform>,
     pub size: Vector3<f64>,
-    pub material: StdBox<Material>
+    /// Material of the faces not given their own, which share it.
+    pub material: Arc<Material>,
+    /// Materials the faces were given of their own, by face in the order of
+    /// `BoxFace::all`.
+    pub face_materials: Vec<Option<Arc<Material>>>
 }
 
 impl Box {
-    pub fn new(size: Vector3<f64>, transform: Isometry3<f64>, material: StdBox<Material>) -> Box {
-        let top = Face::new(size.x, size.z, Isometry3::one(), material.box_clone());
-        let bottom = Face::new(size.x, size.z, Isometry3::one(), material.box_clone());
-        let left = Face::new(size.z, size.y, Isometry3::one(), material.box_clone());
-        let right = Face::new(size.z, size.y, Isometry3::one(), material.box_clone());
-        let front = Face::new(size.x, size.y, Isometry3::one(), material.box_clone());
-        let back = Face::new(size.x, size.y, Isometry3::one(), material.box_clone());
+    pub fn new<T: Into<Transform>>(size: Vector3<f64>, transform: T, material: Arc<Material>) -> Box {
+        let top = Face::new(size.x, size.z, Isometry3::one(), material.clone());
+        let bottom = Face::new(size.x, size.z, Isometry3::one(), material.clone());
+        let left = Face::new(size.z, size.y, Isometry3::one(), material.clone());
+        let right = Face::new(size.z, size.y, Isometry3::one(), material.clone());
+        let front = Face::new(size.x, size.y, Isometry3::one(), material.clone());
+        let back = Face::new(size.x, size.y, Isometry3::one(), material.clone());
         let mut b = Box { top: top, bottom: bottom,
                           left: left, right: right, front: front, back: back,
-                          transform: Isometry3::one(),
-                          size: size, material: material };
-        b.set_transform(transform);
+                          transform: Transform::identity(), motion: None,
+                          size: size, material: material, face_materials: vec![None; 6] };
+        b.set_transform(transform.into());
         b
     }
 
-    pub fn set_transform(&mut self, transform: Isometry3<f64>) {
+    /// Builds a box whose faces use `material`, except the ones given their
+    /// own material in `faces`.
+    pub fn with_face_materials<T: Into<Transform>>(size: Vector3<f64>, transform: T, material: Arc<Material>,
+                                                   faces: &[(BoxFace, Arc<Material>)]) -> Box {
+        let mut b = Box::new(size, transform, material);
+        for &(face, ref material) in faces.iter() {
+            b.set_face_material(face, material.clone());
+        }
+        b
+    }
+
+    pub fn face(&self, face: BoxFace) -> &Face {
+        match face {
+            BoxFace::Top => &self.top,
+            BoxFace::Bottom => &self.bottom,
+            BoxFace::Left => &self.left,
+            BoxFace::Right => &self.right,
+            BoxFace::Front => &self.front,
+            BoxFace::Back => &self.back
+        }
+    }
+
+    pub fn face_mut(&mut self, face: BoxFace) -> &mut Face {
+        match face {
+            BoxFace::Top => &mut self.top,
+            BoxFace::Bottom => &mut self.bottom,
+            BoxFace::Left => &mut self.left,
+            BoxFace::Right => &mut self.right,
+            BoxFace::Front => &mut self.front,
+            BoxFace::Back => &mut self.back
+        }
+    }
+
+    /// Gives a face its own material, which `set_material` then leaves.
+    pub fn set_face_material(&mut self, face: BoxFace, material: Arc<Material>) {
+        self.face_materials[face as usize] = Some(material.clone());
+        self.face_mut(face).material = material;
+    }
+
+    /// Returns the material a face was given of its own, if any.
+    pub fn face_material(&self, face: BoxFace) -> Option<&Arc<Material>> {
+        self.face_materials[face as usize].as_ref()
+    }
+
+    /// Places the box, each face being placed by `transform` on top of its
+    /// position on the box.
+    pub fn set_transform(&mut self, transform: Transform) {
         let top_transform = Isometry3::new(self.size.y / 2. * Vector3::y(), Vector3::x() * -PI / 2.);
-        self.top.transform = top_transform.append_transformation(&transform);
+        self.top.transform = transform * Transform::from_isometry(top_transform);
         let bottom_transform = Isometry3::new(-self.size.y / 2. * Vector3::y(), Vector3::x() * PI / 2.);
-        self.bottom.transform = bottom_transform.append_transformation(&transform);
+        self.bottom.transform = transform * Transform::from_isometry(bottom_transform);
         let left_transform = Isometry3::new(-self.size.x / 2. * Vector3::x(), Vector3::y() * -PI / 2.);
-        self.left.transform = left_transform.append_transformation(&transform);
+        self.left.transform = transform * Transform::from_isometry(left_transform);
         let right_transform = Isometry3::new(self.size.x / 2. * Vector3::x(), Vector3::y() * PI / 2.);
-        self.right.transform = right_transform.append_transformation(&transform);
+        self.right.transform = transform * Transform::from_isometry(right_transform);
         let front_transform = Isometry3::new(self.size.z / 2. * Vector3::z(), Vector3::zero());
-        self.front.transform = front_transform.append_transformation(&transform);
+        self.front.transform = transform * Transform::from_isometry(front_transform);
         let back_transform = Isometry3::new(-self.size.z / 2. * Vector3::z(), Vector3::y() * PI);
-        self.back.transform = back_transform.append_transformation(&transform);
+        self.back.transform = transform * Transform::from_isometry(back_transform);
+        self.transform = transform;
+    }
+
+    /// Returns the box moved by `transform`, applied after its own transform
+    /// and motion.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> Box {
+        let mut b = self.clone();
+        b.set_transform(Transform::from_isometry(*transform) * self.transform);
+        b.motion = self.motion.as_ref().map(|m| m.transformed(transform));
+        b
+    }
+
+    /// Sets the material of the box and of the faces which are not given
+    /// their own.
+    pub fn set_material(&mut self, material: Arc<Material>) {
+        for face in BoxFace::all().iter() {
+            if self.face_material(*face).is_none() {
+                self.face_mut(*face).material = material.clone();
+            }
+        }
+        self.material = material;
+    }
+
+    /// Moves the box, panicking if its transform is not rigid as it would be
+    /// lost while moving.
+    pub fn set_motion(&mut self, motion: Option<AnimatedTransform>) {
+        assert!(motion.is_none() || self.transform.is_rigid(), "Cannot move a scaled or sheared box");
+        self.motion = motion;
+    }
+
+    /// Returns the transform of the box at the given time.
+    pub fn transform_at(&self, time: f64) -> Transform {
+        match self.motion {
+            Some(ref motion) => Transform::from_isometry(motion.interpolate(time)),
+            None => self.transform
+        }
     }
 }
 
@@ -58,7 +168,8 @@ impl PartialEq for Box {
         self.right == other.right &&
         self.front == other.front &&
         self.back == other.back &&
-        self.transform == other.transform &&
+        self.transform.approx_eq(&other.transform) &&
+        self.motion == other.motion &&
         self.size == other.size
     }
 }
@@ -68,7 +179,7 @@ impl Clone for Box {
         Box { top: self.top.clone(), bottom: self.bottom.clone(),
               left: self.left.clone(), right: self.right.clone(),
               front: self.front.clone(), back: self.back.clone(),
-              transform: self.transform, size: self.size,
-              material: self.material.box_clone() }
+              transform: self.transform, motion: self.motion.clone(), size: self.size,
+              material: self.material.clone(), face_materials: self.face_materials.clone() }
     }
 }
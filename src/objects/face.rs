@@ -1,6 +1,8 @@
 use nalgebra::*;
+use algebra::{AnimatedTransform, Transform};
 use material::Material;
-use std::boxed::Box as StdBox;
+use rand::Rng;
+use std::sync::Arc;
 
 #[derive(Debug)]
 /// Represent a rectangular face.
@@ -10,36 +12,81 @@ use std::boxed::Box as StdBox;
 pub struct Face {
     pub width: f64,
     pub height: f64,
-    pub transform: Isometry3<f64>,
-    pub material: StdBox<Material>
+    pub transform: Transform,
+    /// Keyframed transform overriding `transform` for moving faces. It has
+    /// no scale, so moving faces must have a rigid `transform`: set it with
+    /// `set_motion`, which checks that.
+    pub motion: Option<AnimatedTransform>,
+    pub material: Arc<Material>
 }
 
 impl Face {
-    pub fn new(width: f64, height: f64, transform: Isometry3<f64>, material: StdBox<Material>) -> Face {
-        Face { width: width, height: height, transform: transform, material: material }
+    pub fn new<T: Into<Transform>>(width: f64, height: f64, transform: T, material: Arc<Material>) -> Face {
+        Face { width: width, height: height, transform: transform.into(), motion: None, material: material }
+    }
+
+    /// Returns the face moved by `transform`, applied after its own transform
+    /// and motion.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> Face {
+        let mut face = self.clone();
+        face.transform = Transform::from_isometry(*transform) * self.transform;
+        face.motion = self.motion.as_ref().map(|m| m.transformed(transform));
+        face
+    }
+
+    /// Moves the face, panicking if its transform is not rigid as it would be
+    /// lost while moving.
+    pub fn set_motion(&mut self, motion: Option<AnimatedTransform>) {
+        assert!(motion.is_none() || self.transform.is_rigid(), "Cannot move a scaled or sheared face");
+        self.motion = motion;
+    }
+
+    /// Returns the transform of the face at the given time.
+    pub fn transform_at(&self, time: f64) -> Transform {
+        match self.motion {
+            Some(ref motion) => Transform::from_isometry(motion.interpolate(time)),
+            None => self.transform
+        }
     }
 
     pub fn normal(&self) -> Vector3<f64> {
-        self.transform * Vector3::<f64>::z()
+        self.transform.transform_normal(&Vector3::z())
     }
 
-    pub fn random_on_face(&self) -> Point3<f64> {
+    pub fn normal_at(&self, time: f64) -> Vector3<f64> {
+        self.transform_at(time).transform_normal(&Vector3::z())
+    }
+
+    pub fn random_on_face<R: Rng>(&self, rng: &mut R) -> Point3<f64> {
+        self.transform.transform_point(&self.random_local_point(rng))
+    }
+
+    pub fn random_on_face_at<R: Rng>(&self, time: f64, rng: &mut R) -> Point3<f64> {
+        self.transform_at(time).transform_point(&self.random_local_point(rng))
+    }
+
+    /// Maps `(u, v)` in [0, 1]² to a point on the face at the given time.
+    pub fn sample_on_face_at(&self, uv: (f64, f64), time: f64) -> Point3<f64> {
+        let (u, v) = uv;
+        let local = Point3::new((u - 0.5) * self.width, (v - 0.5) * self.height, 0.);
+        self.transform_at(time).transform_point(&local)
+    }
+
+    fn random_local_point<R: Rng>(&self, rng: &mut R) -> Point3<f64> {
         use rand::distributions::*;
-        use rand::*;
-        let mut rng = thread_rng();
         let w = self.width / 2.;
         let h = self.height / 2.;
-        let x = Range::new(-w, w).ind_sample(&mut rng);
-        let y = Range::new(-h, h).ind_sample(&mut rng);
+        let x = Range::new(-w, w).ind_sample(rng);
+        let y = Range::new(-h, h).ind_sample(rng);
 
-        self.transform.transform(&Point3::new(x, y, 0.))
+        Point3::new(x, y, 0.)
     }
 }
 
 impl Clone for Face {
     fn clone(&self) -> Face {
         Face { width: self.width, height: self.height, transform: self.transform,
-               material: self.material.box_clone() }
+               motion: self.motion.clone(), material: self.material.clone() }
     }
 }
 
@@ -47,7 +94,8 @@ impl PartialEq for Face {
     fn eq(&self, other: &Face) -> bool {
         self.width.approx_eq(&other.width) &&
         self.height.approx_eq(&other.height) &&
-        self.transform.approx_eq(&other.transform)
+        self.transform.approx_eq(&other.transform) &&
+        self.motion == other.motion
     }
 }
 
@@ -60,9 +108,10 @@ mod tests {
     use image::Rgb;
     use ray::Ray;
     use intersection::ray_face;
+    use util::seeded_rng;
 
     fn test_face() -> Face {
-        Face::new(3., 1., Isometry3::one(), StdBox::new(Simple::new(Rgb { data: [1., 0., 0.] })))
+        Face::new(3., 1., Isometry3::one(), Arc::new(Simple::new(Rgb { data: [1., 0., 0.] })))
     }
 
     #[test]
@@ -71,10 +120,10 @@ mod tests {
         let h = 1.;
         let m = Isometry3::one();
         let mat = Simple::new(Rgb { data: [0., 0., 0.] });
-        let f = Face::new(w, h, m, StdBox::new(mat));
+        let f = Face::new(w, h, m, Arc::new(mat));
         assert!(f.width == w);
         assert!(f.height == h);
-        assert!(f.transform == m);
+        assert!(f.transform == Transform::from_isometry(m));
     }
 
     #[test]
@@ -87,7 +136,7 @@ mod tests {
     #[test]
     fn test_normal_rotated_face() {
         let mut f = test_face();
-        f.transform.rotation = Rotation3::new(Vector3::y() * (PI / 2.));
+        f.transform = Transform::from_isometry(Isometry3::new(Vector3::zero(), Vector3::y() * (PI / 2.)));
         let n = f.normal();
         assert!(n.approx_eq(&Vector3::x()));
     }
@@ -95,16 +144,15 @@ mod tests {
     #[test]
     fn test_random_on_face() {
         let f = test_face();
-        let p = f.random_on_face();
+        let p = f.random_on_face(&mut seeded_rng(&[0]));
         assert!(-1.5 <= p.x && p.x <= 1.5 && -0.5 <= p.y && p.y <= 0.5 && p.z.approx_eq(&0.));
     }
 
     #[test]
     fn test_random_on_face_transformed() {
         let mut f = test_face();
-        f.transform.translation = Vector3::z() * -5.;
-        f.transform.rotation = Rotation3::new(Vector3::new(0., 0., 0.7));
-        let p = f.random_on_face();
+        f.transform = Transform::from_isometry(Isometry3::new(Vector3::z() * -5., Vector3::new(0., 0., 0.7)));
+        let p = f.random_on_face(&mut seeded_rng(&[1]));
         let ray = Ray::between(Point3::new(0., 0., 0.), p);
         let inter_opt = ray_face(&ray, &f);
         assert!(inter_opt.is_some());
@@ -112,4 +160,35 @@ mod tests {
             assert!(inter.0.approx_eq(&p));
         }
     }
+
+    #[test]
+    fn test_sample_on_face() {
+        let f = test_face();
+        assert!(f.sample_on_face_at((0., 0.), 0.).approx_eq(&Point3::new(-1.5, -0.5, 0.)));
+        assert!(f.sample_on_face_at((0.5, 0.5), 0.).approx_eq(&Point3::new(0., 0., 0.)));
+        assert!(f.sample_on_face_at((1., 1.), 0.).approx_eq(&Point3::new(1.5, 0.5, 0.)));
+    }
+
+    #[test]
+    fn test_transform_at() {
+        let mut f = test_face();
+        assert!(f.transform_at(0.5).approx_eq(&f.transform));
+        let end = Isometry3::new(Vector3::z() * -2., Vector3::y() * (PI / 2.));
+        f.set_motion(Some(AnimatedTransform::between(0., Isometry3::one(), 1., end)));
+        assert!(f.transform_at(1.).approx_eq(&Transform::from_isometry(end)));
+        assert!(f.normal_at(1.).approx_eq(&Vector3::x()));
+    }
+
+    #[test]
+    fn test_scaled_face() {
+        let mut f = test_face();
+        let slant = Isometry3::new(Vector3::z() * -5., Vector3::y() * (PI / 4.));
+        f.transform = Transform::from_isometry(slant) * Transform::scaling(Vector3::new(2., 1., 1.));
+        assert!(f.sample_on_face_at((1., 0.5), 0.).approx_eq(&slant.transform(&Point3::new(3., 0., 0.))));
+        assert!(f.normal().approx_eq(&(slant * Vector3::z())));
+        let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
+        assert!(ray_face(&ray, &f).is_some());
+        let p = f.random_on_face(&mut seeded_rng(&[2]));
+        assert!(ray_face(&Ray::between(Point3::new(0., 0., 0.), p), &f).unwrap().0.approx_eq(&p));
+    }
 }
@@ -0,0 +1,120 @@
+use algebra::Transform;
+use material::{same_handle, Material};
+use objects::{BoxFace, Surface};
+use std::sync::Arc;
+
+/// Copy of a surface shared between many instances, placed by `transform`
+/// applied after the transform of the surface. Rays are moved into the frame
+/// of the shared surface to intersect it, so instances hold no geometry.
+#[derive(Debug)]
+pub struct Instance {
+    pub surface: Arc<Surface>,
+    pub transform: Transform,
+    /// Material replacing the one of the shared surface.
+    pub material: Option<Arc<Material>>
+}
+
+impl Instance {
+    pub fn new<T: Into<Transform>>(surface: Arc<Surface>, transform: T) -> Instance {
+        Instance { surface: surface, transform: transform.into(), material: None }
+    }
+
+    pub fn with_material<T: Into<Transform>>(surface: Arc<Surface>, transform: T, material: Arc<Material>)
+                                             -> Instance {
+        Instance { surface: surface, transform: transform.into(), material: Some(material) }
+    }
+
+    /// Returns the material of the instance, which is the one of the shared
+    /// surface unless overridden.
+    pub fn material(&self) -> &Arc<Material> {
+        match self.material {
+            Some(ref material) => material,
+            None => self.surface.material()
+        }
+    }
+
+    /// Returns the material of the instance where it was hit, the override
+    /// applying to every face.
+    pub fn material_at(&self, face: Option<BoxFace>) -> &Arc<Material> {
+        match self.material {
+            Some(ref material) => material,
+            None => self.surface.material_at(face)
+        }
+    }
+}
+
+impl Clone for Instance {
+    fn clone(&self) -> Instance {
+        Instance { surface: self.surface.clone(), transform: self.transform,
+                   material: self.material.clone() }
+    }
+}
+
+impl PartialEq for Instance {
+    fn eq(&self, other: &Instance) -> bool {
+        self.surface == other.surface &&
+        self.transform.approx_eq(&other.transform) &&
+        match (&self.material, &other.material) {
+            (&Some(ref a), &Some(ref b)) => same_handle(a, b),
+            (&None, &None) => true,
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use intersection::{ray_box, ray_surface};
+    use material::Simple;
+    use nalgebra::*;
+    use num_traits::{One, Zero};
+    use objects::Box;
+    use ray::Ray;
+    use std::f64::consts::PI;
+
+    fn red() -> Arc<Material> {
+        Arc::new(Simple::new(Rgb { data: [1., 0., 0.] }))
+    }
+
+    #[test]
+    fn test_instance_intersects_like_a_copy() {
+        let b = Box::new(Vector3::new(1., 2., 1.), Isometry3::new(Vector3::y(), Vector3::zero()), red());
+        let shared = Arc::new(Surface::from_box(b.clone()));
+        let transform = Isometry3::new(Vector3::new(2., 0., -5.), Vector3::y() * (PI / 3.));
+        let instance = Surface::from_instance(Instance::new(shared.clone(), transform));
+        let stretched = Instance::new(shared.clone(), Transform::scaling(Vector3::new(1., 1., 3.)));
+        let hit = ray_surface(&Ray::new(Point3::new(0., 1., 10.), -Vector3::z()), &Surface::from_instance(stretched));
+        assert!(hit.unwrap().0.approx_eq(&Point3::new(0., 1., 1.5)) && hit.unwrap().1.approx_eq(&8.5));
+        let copy = b.transformed(&transform);
+        for &(x, y) in [(2., 1.), (2.4, 0.5), (1.6, 2.1), (4., 1.)].iter() {
+            let ray = Ray::new(Point3::new(x, y, 0.), -Vector3::z());
+            match (ray_surface(&ray, &instance), ray_box(&ray, &copy)) {
+                (Some(a), Some(b)) => {
+                    assert!(a.0.approx_eq(&b.0) && a.1.approx_eq(&b.1) && a.2.approx_eq(&b.2));
+                }
+                (a, b) => assert!(a.is_none() && b.is_none())
+            }
+        }
+        assert!(instance.transformed(&Isometry3::one()) == instance);
+    }
+
+    #[test]
+    fn test_material_override() {
+        let shared = Arc::new(Surface::from_box(Box::new(Vector3::one(), Isometry3::one(), red())));
+        let blue = Rgb { data: [0., 0., 1.] };
+        let instances: Vec<Instance> = (0..1000).map(|i| {
+            let transform = Isometry3::new(Vector3::x() * i as f64, Vector3::zero());
+            match i % 2 {
+                0 => Instance::new(shared.clone(), transform),
+                _ => Instance::with_material(shared.clone(), transform, Arc::new(Simple::new(blue)))
+            }
+        }).collect();
+        assert!(Arc::strong_count(&shared) == 1001);
+        assert!(instances[0].material().ambient_color() == Rgb { data: [1., 0., 0.] });
+        assert!(instances[1].material().ambient_color() == blue);
+        assert!(instances[1] == instances[1].clone());
+        assert!(instances[1] != Instance::new(shared.clone(), instances[1].transform));
+    }
+}
@@ -1,9 +1,11 @@
 mod face;
 mod _box;
+mod instance;
 mod object;
 mod traits;
 
 pub use self::face::*;
 pub use self::_box::*;
+pub use self::instance::*;
 pub use self::object::*;
 pub use self::traits::*;
@@ -1,14 +1,33 @@
 use light::Light;
 use objects::*;
 use ray::Ray;
-use intersection::{ray_face, ray_box, Intersection};
-use material::Material;
+use intersection::{ray_face, ray_surface, Intersection};
+use material::{same_handle, Material};
+use nalgebra::Isometry3;
+use algebra::Transform;
 use std::boxed::Box as StdBox;
+use std::sync::Arc;
+
+fn replace_handle(material: &mut Arc<Material>, old: &Arc<Material>, new: &Arc<Material>) {
+    if same_handle(material, old) {
+        *material = new.clone();
+    }
+}
+
+/// Surfaces shared by instances which were given a new material, each with
+/// the surface it replaces, so that instances sharing a surface keep sharing
+/// its replacement.
+pub type ReplacedSurfaces = Vec<(Arc<Surface>, Arc<Surface>)>;
+
+fn same_surface(a: &Arc<Surface>, b: &Arc<Surface>) -> bool {
+    &**a as *const Surface == &**b as *const Surface
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Surface {
     Box(Box),
-    Face(Face)
+    Face(Face),
+    Instance(Instance)
 }
 
 impl Surface {
@@ -20,6 +39,109 @@ impl Surface {
         Surface::Face(face)
     }
 
+    pub fn from_instance(instance: Instance) -> Surface {
+        Surface::Instance(instance)
+    }
+
+    pub fn material(&self) -> &Arc<Material> {
+        match *self {
+            Surface::Box(ref b) => &b.material,
+            Surface::Face(ref f) => &f.material,
+            Surface::Instance(ref i) => i.material()
+        }
+    }
+
+    /// Returns the materials the surface can be hit with, starting with the
+    /// one returned by `material`.
+    pub fn materials(&self) -> Vec<&Arc<Material>> {
+        match *self {
+            Surface::Box(ref b) => {
+                let mut materials = vec!(&b.material);
+                materials.extend(BoxFace::all().iter().map(|face| &b.face(*face).material));
+                materials
+            }
+            Surface::Face(ref f) => vec!(&f.material),
+            Surface::Instance(ref i) => match i.material {
+                Some(ref material) => vec!(material),
+                None => i.surface.materials()
+            }
+        }
+    }
+
+    /// Returns the material of the surface where it was hit, which for boxes
+    /// depends on the face hit.
+    pub fn material_at(&self, face: Option<BoxFace>) -> &Arc<Material> {
+        match (self, face) {
+            (&Surface::Box(ref b), Some(face)) => &b.face(face).material,
+            (&Surface::Instance(ref i), _) => i.material_at(face),
+            _ => self.material()
+        }
+    }
+
+    /// Returns the surface moved by `transform`, applied after its own.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> Surface {
+        match *self {
+            Surface::Box(ref b) => Surface::Box(b.transformed(transform)),
+            Surface::Face(ref f) => Surface::Face(f.transformed(transform)),
+            Surface::Instance(ref i) => {
+                let mut instance = i.clone();
+                instance.transform = Transform::from_isometry(*transform) * i.transform;
+                Surface::Instance(instance)
+            }
+        }
+    }
+
+    /// Returns whether the surface uses `material` anywhere, including in
+    /// the surface shared by an instance.
+    pub fn uses_material(&self, material: &Arc<Material>) -> bool {
+        match *self {
+            Surface::Box(ref b) => {
+                same_handle(&b.material, material) ||
+                BoxFace::all().iter().any(|face| same_handle(&b.face(*face).material, material))
+            }
+            Surface::Face(ref f) => same_handle(&f.material, material),
+            Surface::Instance(ref i) => {
+                i.material.as_ref().map_or(false, |m| same_handle(m, material)) || i.surface.uses_material(material)
+            }
+        }
+    }
+
+    /// Makes the surface use `new` wherever it used `old`. A surface shared
+    /// by instances is replaced by a copy using `new`, recorded in `replaced`
+    /// so that the other instances sharing it get the same copy.
+    pub fn replace_material(&mut self, old: &Arc<Material>, new: &Arc<Material>,
+                            replaced: &mut ReplacedSurfaces) {
+        match *self {
+            Surface::Box(ref mut b) => {
+                if same_handle(&b.material, old) {
+                    b.set_material(new.clone());
+                }
+                for face in BoxFace::all().iter() {
+                    if b.face_material(*face).map_or(false, |m| same_handle(m, old)) {
+                        b.set_face_material(*face, new.clone());
+                    }
+                }
+            }
+            Surface::Face(ref mut f) => replace_handle(&mut f.material, old, new),
+            Surface::Instance(ref mut i) => {
+                if let Some(ref mut material) = i.material {
+                    replace_handle(material, old, new);
+                }
+                let known = replaced.iter().position(|&(ref shared, _)| same_surface(shared, &i.surface));
+                match known {
+                    Some(k) => i.surface = replaced[k].1.clone(),
+                    None => if i.surface.uses_material(old) {
+                        let mut surface = (*i.surface).clone();
+                        surface.replace_material(old, new, replaced);
+                        let surface = Arc::new(surface);
+                        replaced.push((i.surface.clone(), surface.clone()));
+                        i.surface = surface;
+                    }
+                }
+            }
+        }
+    }
+
     pub fn is_box(&self) -> bool {
         match self {
             &Surface::Box(_) => true,
@@ -33,6 +155,13 @@ impl Surface {
             _ => false
         }
     }
+
+    pub fn is_instance(&self) -> bool {
+        match self {
+            &Surface::Instance(_) => true,
+            _ => false
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,6 +179,42 @@ impl Object {
         Object::Surface(s)
     }
 
+    /// Returns the object moved by `transform`, applied after its own, e.g.
+    /// to bring it from the frame of its group to the world.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> Object {
+        match *self {
+            Object::Light(ref l) => Object::Light(Light::new(l.face.transformed(transform), l.material.clone())),
+            Object::Surface(ref s) => Object::Surface(s.transformed(transform))
+        }
+    }
+
+    /// Makes the object use `new` wherever it used `old`, see
+    /// `Surface::replace_material`.
+    pub fn replace_material(&mut self, old: &Arc<Material>, new: &Arc<Material>,
+                            replaced: &mut ReplacedSurfaces) {
+        match *self {
+            Object::Light(ref mut l) => replace_handle(&mut l.face.material, old, new),
+            Object::Surface(ref mut s) => s.replace_material(old, new, replaced)
+        }
+    }
+
+    /// Returns the materials the object can be hit with, see
+    /// `Surface::materials`.
+    pub fn materials(&self) -> Vec<&Arc<Material>> {
+        match *self {
+            Object::Light(ref l) => vec!(&l.face.material),
+            Object::Surface(ref s) => s.materials()
+        }
+    }
+
+    /// Returns the material of the object where it was hit.
+    pub fn material_at(&self, face: Option<BoxFace>) -> &Arc<Material> {
+        match *self {
+            Object::Light(ref l) => &l.face.material,
+            Object::Surface(ref s) => s.material_at(face)
+        }
+    }
+
     pub fn is_light(&self) -> bool {
         match self {
             &Object::Light(_) => true,
@@ -107,24 +272,14 @@ impl Intersectable for Object {
         match self {
             &Object::Light(ref l) => {
                 match ray_face(&ray, &l.face) {
-                    Some(hit) => Some(Intersection::new(hit.0, hit.1, hit.2, ray, self)),
+                    Some(hit) => Some(Intersection::new(hit.0, hit.1, hit.2, hit.3, ray, self)),
                     None => None
                 }
             }
             &Object::Surface(ref s) => {
-                match s {
-                    &Surface::Face(ref f) => {
-                        match ray_face(&ray, f) {
-                            Some(hit) => Some(Intersection::new(hit.0, hit.1, hit.2, ray, self)),
-                            None => None
-                        }
-                    }
-                    &Surface::Box(ref b) => {
-                        match ray_box(&ray, b) {
-                            Some(hit) => Some(Intersection::new(hit.0, hit.1, hit.2, ray, self)),
-                            None => None
-                        }
-                    }
+                match ray_surface(&ray, s) {
+                    Some(hit) => Some(Intersection::new(hit.0, hit.1, hit.2, hit.3, ray, self)),
+                    None => None
                 }
             }
         }
@@ -132,15 +287,10 @@ impl Intersectable for Object {
 }
 
 impl Drawable for Object {
-    fn material(&self) -> &StdBox<Material> {
+    fn material(&self) -> &Arc<Material> {
         match self {
             &Object::Light(ref l) => &l.face.material,
-            &Object::Surface(ref s) => {
-                match s {
-                    &Surface::Face(ref f) => &f.material,
-                    &Surface::Box(ref b) => &b.material
-                }
-            }
+            &Object::Surface(ref s) => s.material()
         }
     }
 
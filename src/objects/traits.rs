@@ -1,15 +1,22 @@
 use ray::Ray;
 use material::Material;
 use intersection::Intersection;
+use std::sync::Arc;
 
 // TODO: rewrite the trait
 //
 pub trait Intersectable {
     fn intersects(&self, ray: Ray) -> Option<Intersection>;
+
+    /// Tells whether the ray hits anything within its extent, which may stop
+    /// at the first hit found rather than look for the closest one.
+    fn occluded(&self, ray: Ray) -> bool {
+        self.intersects(ray).is_some()
+    }
 }
 
 pub trait Drawable: Intersectable {
-    fn material(&self) -> &Box<Material>;
+    fn material(&self) -> &Arc<Material>;
 
     fn box_clone(&self) -> Box<Drawable>;
 }
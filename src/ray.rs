@@ -1,40 +1,87 @@
 use nalgebra::*;
+use algebra::Transform;
 use objects::Face;
+use std::f64;
+
+/// Distance by which rays leaving a surface are moved away from it, so that
+/// rounding errors do not make them hit the surface they leave.
+pub const RAY_EPSILON: f64 = 1e-6;
 
 #[derive(Debug, Clone)]
 pub struct Ray {
     pub origin: Point3<f64>,
-    pub direction: Vector3<f64>
+    pub direction: Vector3<f64>,
+    /// Instant at which the ray is cast, used to evaluate animated transforms.
+    pub time: f64,
+    /// Extent of the ray: only the points `origin + t * direction` with `t`
+    /// in ]t_min, t_max[ are hit.
+    pub t_min: f64,
+    pub t_max: f64
 }
 
 impl Ray {
     pub fn new(origin: Point3<f64>, direction: Vector3<f64>) -> Ray {
-        Ray { origin: origin, direction: direction }
+        Ray::with_time(origin, direction, 0.)
+    }
+
+    pub fn with_time(origin: Point3<f64>, direction: Vector3<f64>, time: f64) -> Ray {
+        Ray { origin: origin, direction: direction, time: time, t_min: 0., t_max: f64::INFINITY }
     }
 
     pub fn between(origin: Point3<f64>, destination: Point3<f64>) -> Ray {
         Ray::new(origin, (destination - origin).normalize())
     }
 
+    /// Returns a ray leaving a surface at `position` with normal `normal`,
+    /// moved off the surface on the side of `direction`.
+    pub fn from_surface(position: Point3<f64>, normal: Vector3<f64>, direction: Vector3<f64>, time: f64) -> Ray {
+        Ray::with_time(offset_origin(position, normal, direction), direction, time)
+    }
+
+    /// Returns the ray from a surface at `position` with normal `normal` to
+    /// `target`, stopping short of it so that the target is not hit.
+    pub fn shadow(position: Point3<f64>, normal: Vector3<f64>, target: Point3<f64>, time: f64) -> Ray {
+        let origin = offset_origin(position, normal, target - position);
+        let mut ray = Ray::with_time(origin, target - origin, time);
+        ray.t_max = 1. - RAY_EPSILON;
+        ray
+    }
+
+    /// Returns the ray moved by `transform`, keeping its time and extent.
+    pub fn transformed(&self, transform: &Transform) -> Ray {
+        Ray { origin: transform.transform_point(&self.origin),
+              direction: transform.transform_vector(&self.direction), time: self.time,
+              t_min: self.t_min, t_max: self.t_max }
+    }
+
+    /// Returns the ray in the frame in which `transform` is applied, keeping
+    /// its time and extent, which affine transforms do not change.
+    pub fn inverse_transformed(&self, transform: &Transform) -> Ray {
+        Ray { origin: transform.inverse_transform_point(&self.origin),
+              direction: transform.inverse_transform_vector(&self.direction), time: self.time,
+              t_min: self.t_min, t_max: self.t_max }
+    }
+
     /// Returns the point of intersection and distance between the ray and the
     /// given face, if any
     pub fn intersects_face(&self, f: &Face) -> Option<(Point3<f64>, f64)> {
         // Get the center of the face in woorld coordinates
-        let p = f.transform.transform(&Point3::new(0., 0., 0.));
-        let n = f.normal();
+        let transform = f.transform_at(self.time);
+        let p = transform.transform_point(&Point3::new(0., 0., 0.));
+        let n = transform.transform_normal(&Vector3::z());
         let d = dot(&self.direction, &n);
         match d.approx_eq(&0.) || d > 0. {
             true => None,
             false => {
                 // Ray is not parallel to plane
-                // Find if the intersection is in the positive direction
+                // Find if the intersection is within the extent of the ray
                 let t = dot(&(p - self.origin), &n) / d;
-                match t > 0. {
+                match t > self.t_min && t < self.t_max {
                     true => {
                         // Find the intersection point on the face's plane and
                         // make sure it's within the face
                         let i_world = self.origin + t * self.direction;
-                        let i_local = f.transform.inverse().unwrap().transform(&i_world);
+                        let i_local = transform.inverse_transform_point(&i_world);
                         match (abs(&i_local.x) <= (f.width / 2.)) && (abs(&i_local.y) <= (f.height / 2.)) {
                             true => Some((i_world, norm(&(i_world - self.origin)))),
                             false => None
@@ -47,9 +94,22 @@ impl Ray {
     }
 }
 
+fn offset_origin(position: Point3<f64>, normal: Vector3<f64>, direction: Vector3<f64>) -> Point3<f64> {
+    if dot(&direction, &normal) < 0. {
+        position - normal * RAY_EPSILON
+    } else {
+        position + normal * RAY_EPSILON
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use algebra::AnimatedTransform;
+    use material::Simple;
+    use image::Rgb;
+    use num_traits::Zero;
+    use std::sync::Arc;
 
     #[test]
     fn test_new_ray() {
@@ -58,7 +118,49 @@ mod tests {
         let ray = Ray::new(origin, direction);
         assert!(ray.direction == direction);
         assert!(ray.origin == origin);
+        assert!(ray.time == 0.);
+        assert!(ray.t_min == 0. && ray.t_max == f64::INFINITY);
+    }
+
+    #[test]
+    fn test_extent() {
+        let f = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zero()),
+                          Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+        let mut ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z() * 2.);
+        assert!(ray.intersects_face(&f).unwrap().1.approx_eq(&5.));
+        ray.t_max = 2.5;
+        assert!(ray.intersects_face(&f).is_none());
+        ray.t_max = 3.;
+        ray.t_min = 2.5;
+        assert!(ray.intersects_face(&f).is_none());
+        let shadow = Ray::shadow(Point3::new(0., 0., 0.), Vector3::z(), Point3::new(0., 0., -5.), 0.);
+        assert!(shadow.intersects_face(&f).is_none());
+        let through = Ray::shadow(Point3::new(0., 0., 0.), Vector3::z(), Point3::new(0., 0., -6.), 0.);
+        assert!(through.intersects_face(&f).is_some());
     }
 
+    #[test]
+    fn test_from_surface() {
+        // A ray starting on a face, which rounding errors put slightly in
+        // front of it, hits the face it starts on unless moved off it
+        let f = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zero()),
+                          Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+        let position = Point3::new(0.1, 0.2, -5. + 1e-12);
+        let direction = Vector3::new(0.3, 0., -1.);
+        assert!(Ray::new(position, direction).intersects_face(&f).is_some());
+        let ray = Ray::from_surface(position, Vector3::z(), direction, 0.);
+        assert!(ray.origin.z < -5. && ray.intersects_face(&f).is_none());
+    }
 
+    #[test]
+    fn test_intersects_moving_face() {
+        let mut f = Face::new(1., 1., Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zero()),
+                              Arc::new(Simple::new(Rgb { data: [0., 0., 0.] })));
+        f.motion = Some(AnimatedTransform::between(0., Isometry3::new(Vector3::new(0., 0., -5.), Vector3::zero()),
+                                                   1., Isometry3::new(Vector3::new(3., 0., -5.), Vector3::zero())));
+        let direction = -Vector3::z();
+        assert!(Ray::with_time(Point3::new(0., 0., 0.), direction, 0.).intersects_face(&f).is_some());
+        assert!(Ray::with_time(Point3::new(0., 0., 0.), direction, 1.).intersects_face(&f).is_none());
+        assert!(Ray::with_time(Point3::new(3., 0., 0.), direction, 1.).intersects_face(&f).is_some());
+    }
 }
@@ -0,0 +1,140 @@
+use image::*;
+use film::SampleCounts;
+
+/// Settings of adaptive sampling. Every pixel takes at least `min_samples`
+/// samples, then keeps sampling until the standard error of its mean
+/// luminance falls under `threshold` times the mean, or `max_samples` is
+/// reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveSettings {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64
+}
+
+/// Luminance under which the convergence threshold stops being relative, so
+/// that dark pixels do not soak up the whole sample budget.
+const MIN_LUMINANCE: f64 = 1. / 255.;
+
+/// Running mean and variance of the samples of a pixel (Welford's algorithm).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelStatistics {
+    count: u32,
+    mean: f64,
+    m2: f64
+}
+
+impl AdaptiveSettings {
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f64) -> AdaptiveSettings {
+        assert!(0 < min_samples && min_samples <= max_samples);
+        AdaptiveSettings { min_samples: min_samples, max_samples: max_samples, threshold: threshold }
+    }
+
+    /// Returns whether a pixel with the given statistics needs more samples.
+    pub fn needs_samples(&self, stats: &PixelStatistics) -> bool {
+        stats.count() < self.min_samples ||
+        (stats.count() < self.max_samples && !stats.is_converged(self.threshold))
+    }
+}
+
+impl PixelStatistics {
+    pub fn new() -> PixelStatistics {
+        PixelStatistics { count: 0, mean: 0., m2: 0. }
+    }
+
+    /// Rebuilds statistics from their count, mean and sum of squared
+    /// differences from the mean.
+    pub fn from_moments(count: u32, mean: f64, m2: f64) -> PixelStatistics {
+        PixelStatistics { count: count, mean: mean, m2: m2 }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u32 { self.count }
+    pub fn mean(&self) -> f64 { self.mean }
+    pub fn m2(&self) -> f64 { self.m2 }
+
+    /// Unbiased sample variance.
+    pub fn variance(&self) -> f64 {
+        match self.count < 2 {
+            true => 0.,
+            false => self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// Standard error of the mean.
+    pub fn standard_error(&self) -> f64 {
+        match self.count {
+            0 => 0.,
+            n => (self.variance() / n as f64).sqrt()
+        }
+    }
+
+    pub fn is_converged(&self, threshold: f64) -> bool {
+        self.count >= 2 && self.standard_error() <= threshold * self.mean.max(MIN_LUMINANCE)
+    }
+}
+
+/// Maps sample counts from 0 to `max_samples` to a blue-green-red heat map.
+pub fn sample_heatmap(counts: &SampleCounts, max_samples: u32) -> RgbImage {
+    let (width, height) = counts.dimensions();
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let t = (counts.get_pixel(x, y)[0] as f64 / max_samples.max(1) as f64).min(1.);
+        let (r, g, b) = match t < 0.5 {
+            true => (0., 2. * t, 1. - 2. * t),
+            false => (2. * t - 1., 2. - 2. * t, 0.)
+        };
+        Rgb { data: [(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8] }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let mut stats = PixelStatistics::new();
+        for v in [2., 4., 4., 4., 5., 5., 7., 9.].iter() {
+            stats.add(*v);
+        }
+        assert!(stats.count() == 8);
+        assert!((stats.mean() - 5.).abs() < 1e-12);
+        assert!((stats.variance() - 32. / 7.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_constant_pixel_converges() {
+        let settings = AdaptiveSettings::new(4, 64, 0.01);
+        let mut stats = PixelStatistics::new();
+        while settings.needs_samples(&stats) {
+            stats.add(0.5);
+        }
+        assert!(stats.count() == 4);
+    }
+
+    #[test]
+    fn test_noisy_pixel_hits_budget() {
+        let settings = AdaptiveSettings::new(4, 64, 0.001);
+        let mut stats = PixelStatistics::new();
+        let mut i = 0;
+        while settings.needs_samples(&stats) {
+            stats.add(if i % 2 == 0 { 0. } else { 1. });
+            i += 1;
+        }
+        assert!(stats.count() == 64);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let counts = SampleCounts::from_raw(2, 1, vec!(0, 10)).unwrap();
+        let heatmap = sample_heatmap(&counts, 10);
+        assert!(heatmap.get_pixel(0, 0).data == [0, 0, 255]);
+        assert!(heatmap.get_pixel(1, 0).data == [255, 0, 0]);
+    }
+}
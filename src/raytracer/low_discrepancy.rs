@@ -0,0 +1,350 @@
+use rand::Rng;
+use raytracer::sampler::*;
+use util::hash_u32s;
+
+/// Halton sequence, dimension `d` being the radical inverse in the `d`-th prime
+/// base. Each pixel gets its own Cranley-Patterson rotation so neighbouring
+/// pixels are decorrelated.
+///
+/// The low discrepancy samplers are deterministic: their scrambles are derived
+/// from the seed they are built with instead of the generator they are given.
+pub struct Halton {
+    primes: Vec<u32>,
+    seed: u32
+}
+
+/// Sobol sequence with a random digit scramble per pixel and dimension.
+/// Dimensions past the tabulated direction numbers are padded with
+/// independent random values.
+pub struct Sobol {
+    directions: Vec<[u32; 32]>,
+    seed: u32
+}
+
+/// Pairs of scrambled (0,2)-sequences. Every pair of dimensions is a (0,2)
+/// sequence whose samples are shuffled independently within the pixel, so any
+/// number of dimensions is well stratified two at a time.
+pub struct ZeroTwoSequence {
+    samples_per_pixel: u32,
+    seed: u32
+}
+
+/// Primitive polynomials and initial direction numbers of the first Sobol
+/// dimensions past the first one, as `(degree, coefficients, m)` (Joe & Kuo).
+const SOBOL_PARAMETERS: [(u32, u32, &'static [u32]); 12] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31])
+];
+
+/// Low discrepancy samples are placed by their first two dimensions regardless
+/// of the number of samples taken.
+fn indexed_sample<S: PixelSampler, R: Rng>(sampler: &S, pixel: (u32, u32), index: u32,
+                                           rng: &mut R) -> (f64, f64) {
+    (pixel.0 as f64 + sampler.sample_dimension(pixel, index, 0, rng),
+     pixel.1 as f64 + sampler.sample_dimension(pixel, index, 1, rng))
+}
+
+const ONE_OVER_2_32: f64 = 1. / 4294967296.;
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 * ONE_OVER_2_32
+}
+
+fn reverse_bits(n: u32) -> u32 {
+    let mut n = n;
+    n = (n << 16) | (n >> 16);
+    n = ((n & 0x00ff00ff) << 8) | ((n & 0xff00ff00) >> 8);
+    n = ((n & 0x0f0f0f0f) << 4) | ((n & 0xf0f0f0f0) >> 4);
+    n = ((n & 0x33333333) << 2) | ((n & 0xcccccccc) >> 2);
+    n = ((n & 0x55555555) << 1) | ((n & 0xaaaaaaaa) >> 1);
+    n
+}
+
+fn radical_inverse(base: u32, index: u32) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv = inv_base;
+    let mut i = index;
+    let mut r = 0.;
+    while i > 0 {
+        r += (i % base) as f64 * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+    r
+}
+
+fn is_prime(n: u32) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0)
+}
+
+fn nth_prime(n: u32) -> u32 {
+    (2..).filter(|p| is_prime(*p)).nth(n as usize).unwrap()
+}
+
+/// Second dimension of the Sobol sequence, XORed with `scramble`.
+fn sobol_2(index: u32, scramble: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut i = index;
+    let mut r = scramble;
+    while i != 0 {
+        if i & 1 == 1 {
+            r ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    r
+}
+
+/// Pseudo-random permutation of [0, l), selected by `p` (Kensler 2013).
+pub fn permute(index: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = index;
+    loop {
+        i ^= p; i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8; i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1; i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11; i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2; i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2; i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    ((i as u64 + p as u64) % l as u64) as u32
+}
+
+impl Halton {
+    pub fn new() -> Halton {
+        Halton::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Halton {
+        Halton { primes: (0..64).map(nth_prime).collect(), seed: seed }
+    }
+
+    fn base(&self, dimension: u32) -> u32 {
+        match self.primes.get(dimension as usize) {
+            Some(p) => *p,
+            None => nth_prime(dimension)
+        }
+    }
+}
+
+impl PixelSampler for Halton {
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, _: u32, rng: &mut R) -> (f64, f64) {
+        indexed_sample(self, pixel, index, rng)
+    }
+
+    fn sample_dimension<R: Rng>(&self, pixel: (u32, u32), index: u32, dimension: u32,
+                                _: &mut R) -> f64 {
+        let offset = to_unit(hash_u32s(&[self.seed, pixel.0, pixel.1, dimension]));
+        let u = radical_inverse(self.base(dimension), index) + offset;
+        if u >= 1. { u - 1. } else { u }
+    }
+}
+
+impl Sobol {
+    pub fn new() -> Sobol {
+        Sobol::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Sobol {
+        let mut directions = vec!();
+        let mut first = [0; 32];
+        for i in 0..32 {
+            first[i] = 1 << (31 - i);
+        }
+        directions.push(first);
+        for &(s, a, m) in SOBOL_PARAMETERS.iter() {
+            let s = s as usize;
+            let mut v = [0u32; 32];
+            for i in 0..s {
+                v[i] = m[i] << (31 - i);
+            }
+            for i in s..32 {
+                v[i] = v[i - s] ^ (v[i - s] >> s);
+                for k in 1..s {
+                    if (a >> (s - 1 - k)) & 1 == 1 {
+                        v[i] ^= v[i - k];
+                    }
+                }
+            }
+            directions.push(v);
+        }
+        Sobol { directions: directions, seed: seed }
+    }
+
+    /// Number of dimensions with proper Sobol direction numbers.
+    pub fn tabulated_dimensions(&self) -> u32 {
+        self.directions.len() as u32
+    }
+}
+
+impl PixelSampler for Sobol {
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, _: u32, rng: &mut R) -> (f64, f64) {
+        indexed_sample(self, pixel, index, rng)
+    }
+
+    fn sample_dimension<R: Rng>(&self, pixel: (u32, u32), index: u32, dimension: u32,
+                                _: &mut R) -> f64 {
+        let scramble = hash_u32s(&[self.seed, pixel.0, pixel.1, dimension]);
+        match self.directions.get(dimension as usize) {
+            Some(v) => {
+                let mut x = scramble;
+                let mut i = index;
+                let mut k = 0;
+                while i != 0 {
+                    if i & 1 == 1 {
+                        x ^= v[k];
+                    }
+                    i >>= 1;
+                    k += 1;
+                }
+                to_unit(x)
+            }
+            None => to_unit(hash_u32s(&[scramble, index]))
+        }
+    }
+}
+
+impl ZeroTwoSequence {
+    /// The samples of each pixel are shuffled in blocks of `samples_per_pixel`,
+    /// which should be the number of samples taken per pixel, ideally a power
+    /// of two.
+    pub fn new(samples_per_pixel: u32) -> ZeroTwoSequence {
+        ZeroTwoSequence::with_seed(samples_per_pixel, 0)
+    }
+
+    pub fn with_seed(samples_per_pixel: u32, seed: u32) -> ZeroTwoSequence {
+        assert!(samples_per_pixel > 0);
+        ZeroTwoSequence { samples_per_pixel: samples_per_pixel, seed: seed }
+    }
+}
+
+impl PixelSampler for ZeroTwoSequence {
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, _: u32, rng: &mut R) -> (f64, f64) {
+        indexed_sample(self, pixel, index, rng)
+    }
+
+    fn sample_dimension<R: Rng>(&self, pixel: (u32, u32), index: u32, dimension: u32,
+                                _: &mut R) -> f64 {
+        let pair = dimension / 2;
+        let l = self.samples_per_pixel;
+        let shuffle = hash_u32s(&[self.seed, pixel.0, pixel.1, pair, index / l]);
+        let i = (index / l) * l + permute(index % l, l, shuffle);
+        let scramble = hash_u32s(&[self.seed, pixel.0, pixel.1, dimension]);
+        match dimension % 2 {
+            0 => to_unit(reverse_bits(i) ^ scramble),
+            _ => to_unit(sobol_2(i, scramble))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::seeded_rng;
+
+    fn test_sampler_in_range<S: PixelSampler>(sampler: S) {
+        let mut rng = seeded_rng(&[0]);
+        for (x, y) in sampler.samples((10, 10), 3, &mut rng) {
+            assert!(10. <= x && x < 11. && 10. <= y && y < 11.);
+        }
+        for index in 0..16 {
+            let mut dims = sampler.dimensions((10, 10), index, &mut rng);
+            for _ in 0..40 {
+                let u = dims.next_1d();
+                assert!(0. <= u && u < 1.);
+            }
+        }
+    }
+
+    /// Checks that the first 4 samples of a pixel land in distinct quadrants.
+    fn test_stratified<S: PixelSampler>(sampler: S) {
+        let mut quadrants = [false; 4];
+        for (x, y) in sampler.samples((3, 7), 4, &mut seeded_rng(&[0])) {
+            let q = ((x - 3.) * 2.) as usize + 2 * ((y - 7.) * 2.) as usize;
+            assert!(!quadrants[q]);
+            quadrants[q] = true;
+        }
+    }
+
+    #[test]
+    fn test_radical_inverse() {
+        assert!(radical_inverse(2, 1) == 0.5);
+        assert!(radical_inverse(2, 3) == 0.75);
+        assert!((radical_inverse(3, 5) - 7. / 9.).abs() < 1e-12);
+        assert!(to_unit(reverse_bits(3)) == 0.75);
+    }
+
+    #[test]
+    fn test_nth_prime() {
+        assert!(nth_prime(0) == 2);
+        assert!(nth_prime(5) == 13);
+        assert!(Halton::new().base(100) == 547);
+    }
+
+    #[test]
+    fn test_permute() {
+        let mut seen = vec![false; 37];
+        for i in 0..37 {
+            let p = permute(i, 37, 1234) as usize;
+            assert!(!seen[p]);
+            seen[p] = true;
+        }
+    }
+
+    #[test]
+    fn test_halton_sampler() {
+        test_sampler_in_range(Halton::new());
+        let h = Halton::new();
+        let mut rng = seeded_rng(&[0]);
+        let mut xs: Vec<f64> = (0..4).map(|i| h.sample_dimension((3, 7), i, 0, &mut rng)).collect();
+        xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for w in xs.windows(2) {
+            assert!((w[1] - w[0] - 0.25).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_sobol_sampler() {
+        test_sampler_in_range(Sobol::new());
+        test_stratified(Sobol::new());
+    }
+
+    #[test]
+    fn test_zero_two_sampler() {
+        test_sampler_in_range(ZeroTwoSequence::new(4));
+        test_stratified(ZeroTwoSequence::new(4));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut rng = seeded_rng(&[0]);
+        let s = Sobol::new();
+        assert!(s.sample_dimension((1, 2), 5, 7, &mut rng) == s.sample_dimension((1, 2), 5, 7, &mut rng));
+        assert!(s.sample_dimension((1, 2), 5, 7, &mut rng) != Sobol::with_seed(1).sample_dimension((1, 2), 5, 7, &mut rng));
+    }
+}
@@ -1,5 +1,15 @@
 mod simple;
 mod sampler;
+mod low_discrepancy;
+mod adaptive;
+mod tiles;
+mod progressive;
+mod observer;
 
 pub use self::simple::*;
 pub use self::sampler::*;
+pub use self::low_discrepancy::*;
+pub use self::adaptive::*;
+pub use self::tiles::*;
+pub use self::progressive::*;
+pub use self::observer::*;
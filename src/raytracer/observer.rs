@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use raytracer::tiles::Tile;
+use util::{duration_as_secs_f64, duration_from_secs_f64};
+
+/// How far a render has gone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Fraction of the render done, in [0, 1].
+    pub fraction: f64,
+    /// Number of rays traced so far, camera, shadow and reflection rays alike.
+    pub rays: u64,
+    pub elapsed: Duration,
+    /// Estimated time left, extrapolated from the time taken so far.
+    pub eta: Option<Duration>
+}
+
+/// Events sent to a `RenderObserver` while rendering.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderEvent {
+    /// A tile was rendered and merged into the film.
+    TileDone { tile: Tile, progress: Progress },
+    /// A progressive pass was merged into the film.
+    PassDone { pass: u32, passes: u32, progress: Progress },
+    /// The render completed.
+    Finished { progress: Progress },
+    /// The render was cancelled, the film returned holds what was done.
+    Cancelled { progress: Progress }
+}
+
+/// Receives the events of renders, possibly from several threads at once.
+pub trait RenderObserver: Sync + Send {
+    fn notify(&self, event: &RenderEvent);
+}
+
+impl<F: Fn(&RenderEvent) + Sync + Send> RenderObserver for F {
+    fn notify(&self, event: &RenderEvent) {
+        self(event)
+    }
+}
+
+/// Shared flag stopping the renders it was given to. Renders check it before
+/// each row of pixels, so they stop shortly after it is cancelled.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl Progress {
+    pub fn new(fraction: f64, rays: u64, elapsed: Duration) -> Progress {
+        let eta = match fraction > 0. {
+            true => Some(scale_duration(elapsed, (1. - fraction).max(0.) / fraction)),
+            false => None
+        };
+        Progress { fraction: fraction, rays: rays, elapsed: elapsed, eta: eta }
+    }
+}
+
+impl RenderEvent {
+    pub fn progress(&self) -> &Progress {
+        match *self {
+            RenderEvent::TileDone { ref progress, .. } => progress,
+            RenderEvent::PassDone { ref progress, .. } => progress,
+            RenderEvent::Finished { ref progress } => progress,
+            RenderEvent::Cancelled { ref progress } => progress
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken { cancelled: Arc::new(AtomicBool::new(false)) }
+    }
+
+    /// Stops the renders holding this token or a clone of it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+fn scale_duration(duration: Duration, factor: f64) -> Duration {
+    duration_from_secs_f64(duration_as_secs_f64(duration) * factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_eta() {
+        let progress = Progress::new(0.25, 100, Duration::from_secs(10));
+        assert!(progress.eta == Some(Duration::from_secs(30)));
+        assert!(Progress::new(0., 0, Duration::from_secs(1)).eta.is_none());
+        assert!(Progress::new(1., 0, Duration::from_secs(1)).eta == Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_cancellation_token_shared() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use film::Film;
+use raytracer::PixelStatistics;
+
+/// Settings of progressive rendering, which renders successive passes of one
+/// sample per pixel until the number of passes, the time budget or the noise
+/// threshold is reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressiveSettings {
+    /// Maximum number of passes, over which the samples of each pixel are
+    /// stratified.
+    pub passes: u32,
+    /// Stops after the first pass ending past this budget.
+    pub time_budget: Option<Duration>,
+    /// Stops once the standard error of every pixel across passes falls under
+    /// this fraction of its luminance.
+    pub noise_threshold: Option<f64>,
+    /// Minimum time between snapshots. Without it, a snapshot is taken after
+    /// every pass.
+    pub snapshot_interval: Option<Duration>
+}
+
+impl ProgressiveSettings {
+    pub fn new(passes: u32) -> ProgressiveSettings {
+        ProgressiveSettings { passes: passes, time_budget: None, noise_threshold: None,
+                              snapshot_interval: None }
+    }
+}
+
+/// Everything accumulated by a progressive render, from which it can resume.
+/// Samples are drawn from generators seeded by their pass, so the number of
+/// passes done stands for the state of the generators.
+#[derive(Debug, Clone)]
+pub struct ProgressiveState {
+    pub film: Film,
+    /// Number of passes accumulated in the film.
+    pub pass: u32,
+    /// Luminance statistics of every pixel across passes, row by row.
+    pub statistics: Vec<PixelStatistics>
+}
+
+impl ProgressiveState {
+    /// Returns the state of a render that has not started yet.
+    pub fn new(film: Film) -> ProgressiveState {
+        let (width, height) = film.resolution();
+        ProgressiveState { film: film, pass: 0,
+                           statistics: vec![PixelStatistics::new(); width as usize * height as usize] }
+    }
+}
+
+pub trait ProgressiveRenderer {
+    /// Renders passes of one sample per pixel, accumulated into the returned
+    /// film. `snapshot` is called with the state of the render, at most once
+    /// per snapshot interval and after the last pass. With adaptive sampling,
+    /// pixels sit out the passes once they have enough samples, and the render
+    /// stops when they all have.
+    fn render_progressive<C>(&self, settings: &ProgressiveSettings, snapshot: C) -> Film
+        where C: FnMut(&ProgressiveState);
+
+    /// Carries on a progressive render from a previous state, e.g. read back
+    /// from a checkpoint. The result is the same as rendering without
+    /// interruption with the same settings.
+    fn resume_progressive<C>(&self, settings: &ProgressiveSettings, state: ProgressiveState,
+                             snapshot: C) -> Film
+        where C: FnMut(&ProgressiveState);
+}
@@ -1,92 +1,233 @@
-use rand::{thread_rng, Closed01, Rng};
+use rand::{Closed01, Rng};
+use raytracer::low_discrepancy::permute;
+use util::hash_u32s;
 
 pub struct Random;
-pub struct Jittered;
-pub struct Uniform;
+
+/// Jittered and uniform samplers stratify the pixel, visiting its strata in
+/// an order shuffled from the seed they are built with.
+pub struct Jittered {
+    seed: u32
+}
+
+pub struct Uniform {
+    seed: u32
+}
 
 pub trait PixelSampler {
-    fn samples(&self, pixel: (u32, u32), n: u32) -> Vec<(f64, f64)>;
+    /// Returns the position on the image plane of sample `index` out of the
+    /// `count` samples taken in `pixel`.
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, count: u32, rng: &mut R) -> (f64, f64);
+
+    /// Returns coordinate `dimension` of sample `index` of `pixel`, in [0, 1).
+    /// Dimensions 0 and 1 locate the sample within the pixel, the following
+    /// ones are used for lens, time, light and BSDF sampling.
+    fn sample_dimension<R: Rng>(&self, pixel: (u32, u32), index: u32, dimension: u32,
+                                rng: &mut R) -> f64;
+
+    /// Returns the positions on the image plane of the `count` samples of `pixel`.
+    fn samples<R: Rng>(&self, pixel: (u32, u32), count: u32, rng: &mut R) -> Vec<(f64, f64)> {
+        (0..count).into_iter().map(|i| self.sample(pixel, i, count, rng)).collect()
+    }
+
+    /// Returns the extra dimensions of sample `index` of `pixel`.
+    fn dimensions<'a, R: Rng>(&'a self, pixel: (u32, u32), index: u32,
+                              rng: &'a mut R) -> SampleDimensions<'a, Self, R>
+        where Self: Sized
+    {
+        SampleDimensions::new(self, pixel, index, rng)
+    }
+}
+
+/// Hands out the dimensions of a pixel sample past the image plane position,
+/// one or two at a time, in the order they are requested.
+pub struct SampleDimensions<'a, S: 'a + PixelSampler, R: 'a + Rng> {
+    sampler: &'a S,
+    rng: &'a mut R,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32
+}
+
+impl<'a, S: PixelSampler, R: Rng> SampleDimensions<'a, S, R> {
+    pub fn new(sampler: &'a S, pixel: (u32, u32), index: u32,
+               rng: &'a mut R) -> SampleDimensions<'a, S, R> {
+        SampleDimensions { sampler: sampler, rng: rng, pixel: pixel, index: index, dimension: 2 }
+    }
+
+    pub fn next_1d(&mut self) -> f64 {
+        let u = self.sampler.sample_dimension(self.pixel, self.index, self.dimension, &mut *self.rng);
+        self.dimension += 1;
+        u
+    }
+
+    pub fn next_2d(&mut self) -> (f64, f64) {
+        let u = self.next_1d();
+        let v = self.next_1d();
+        (u, v)
+    }
+}
+
+/// Returns the stratum of sample `index` out of `count`, as the origin and
+/// size of a cell of the unit square. The square is split in about `sqrt(count)`
+/// rows, the extra samples of non square counts going one per row to the first
+/// rows, so that every count is stratified.
+pub fn stratum(index: u32, count: u32) -> ((f64, f64), (f64, f64)) {
+    let rows = ((count as f64).sqrt().round() as u32).max(1);
+    let per_row = count / rows;
+    let extra = count % rows;
+    let mut first = 0;
+    let mut row = 0;
+    loop {
+        let in_row = per_row + if row < extra { 1 } else { 0 };
+        if index < first + in_row || row == rows - 1 {
+            let (w, h) = (1. / in_row as f64, 1. / rows as f64);
+            return (((index - first) as f64 * w, row as f64 * h), (w, h));
+        }
+        first += in_row;
+        row += 1;
+    }
+}
+
+/// Returns the stratum of sample `index` of `pixel`. Strata are visited in an
+/// order shuffled per pixel, so that the first samples of a pixel are spread
+/// over it even when fewer than `count` samples end up being taken. Samples
+/// past `count` start over with a new order.
+fn shuffled_stratum(seed: u32, pixel: (u32, u32), index: u32, count: u32) -> ((f64, f64), (f64, f64)) {
+    let shuffle = hash_u32s(&[seed, pixel.0, pixel.1, index / count]);
+    stratum(permute(index % count, count, shuffle), count)
+}
+
+impl Jittered {
+    pub fn new() -> Jittered {
+        Jittered::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Jittered {
+        Jittered { seed: seed }
+    }
+}
+
+impl Uniform {
+    pub fn new() -> Uniform {
+        Uniform::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Uniform {
+        Uniform { seed: seed }
+    }
 }
 
 impl PixelSampler for Uniform {
-    fn samples(&self, pixel: (u32, u32), n: u32) -> Vec<(f64, f64)> {
-        let step = 1. / n as f64;
-        let mut v = vec!();
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, count: u32, _: &mut R) -> (f64, f64) {
         let (px, py) = pixel;
-        let mut i = 0;
-        while i != n {
-            let mut j = 0;
-            while j != n {
-                v.push((px as f64 + (0.5 + i as f64) * step,
-                        py as f64 + (0.5 + j as f64) * step));
-                j += 1;
-            }
-            i += 1;
-        }
+        let ((x, y), (w, h)) = shuffled_stratum(self.seed, pixel, index, count);
+        (px as f64 + x + 0.5 * w, py as f64 + y + 0.5 * h)
+    }
 
-        v
+    /// Only the image plane is stratified, extra dimensions are independent
+    /// random numbers.
+    fn sample_dimension<R: Rng>(&self, _: (u32, u32), _: u32, _: u32, rng: &mut R) -> f64 {
+        rng.gen::<f64>()
     }
 }
 
 impl PixelSampler for Random {
-    fn samples(&self, pixel: (u32, u32), n: u32) -> Vec<(f64, f64)> {
+    fn sample<R: Rng>(&self, pixel: (u32, u32), _: u32, _: u32, rng: &mut R) -> (f64, f64) {
         let (px, py) = pixel;
-        let mut rng = thread_rng();
-        let n2 = n * n;
-        (0..n2).into_iter().map(|_| {
-            let (Closed01(x), Closed01(y)) = (rng.gen::<Closed01<f64>>(),
-                                              rng.gen::<Closed01<f64>>());
-            (px as f64 + x, py as f64 + y)
-        }).collect()
+        let (Closed01(x), Closed01(y)) = (rng.gen::<Closed01<f64>>(),
+                                          rng.gen::<Closed01<f64>>());
+        (px as f64 + x, py as f64 + y)
+    }
+
+    fn sample_dimension<R: Rng>(&self, _: (u32, u32), _: u32, _: u32, rng: &mut R) -> f64 {
+        rng.gen::<f64>()
     }
 }
 
 impl PixelSampler for Jittered {
-    fn samples(&self, pixel: (u32, u32), n: u32) -> Vec<(f64, f64)> {
-        let step = 1. / n as f64;
-        let mut v = vec!();
+    fn sample<R: Rng>(&self, pixel: (u32, u32), index: u32, count: u32, rng: &mut R) -> (f64, f64) {
         let (px, py) = pixel;
-        let mut rng = thread_rng();
-        let mut i = 0;
-        while i != n {
-            let mut j = 0;
-            while j != n {
-                let (Closed01(x), Closed01(y)) = (rng.gen::<Closed01<f64>>(),
-                                                  rng.gen::<Closed01<f64>>());
-                v.push((px as f64 + (x + i as f64) * step,
-                        py as f64 + (y + j as f64) * step));
-                j += 1;
-            }
-            i += 1;
-        }
+        let ((x, y), (w, h)) = shuffled_stratum(self.seed, pixel, index, count);
+        let (Closed01(jx), Closed01(jy)) = (rng.gen::<Closed01<f64>>(),
+                                            rng.gen::<Closed01<f64>>());
+        (px as f64 + x + jx * w, py as f64 + y + jy * h)
+    }
 
-        v
+    fn sample_dimension<R: Rng>(&self, _: (u32, u32), _: u32, _: u32, rng: &mut R) -> f64 {
+        rng.gen::<f64>()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use util::seeded_rng;
 
     fn test_sampler_in_range<S: PixelSampler>(mut sampler: S) {
-        let samples = sampler.samples((10, 10), 3);
+        let samples = sampler.samples((10, 10), 3, &mut seeded_rng(&[0]));
         for (x, y) in samples {
             assert!(10. <= x && x <= 11. && 10. <= y && y <= 11.);
         }
     }
 
+    fn test_dimensions_in_range<S: PixelSampler>(sampler: S) {
+        let mut rng = seeded_rng(&[0]);
+        let mut dims = sampler.dimensions((10, 10), 3, &mut rng);
+        for _ in 0..10 {
+            let (u, v) = dims.next_2d();
+            assert!(0. <= u && u < 1. && 0. <= v && v < 1.);
+        }
+    }
+
     #[test]
     fn test_random_sampler_in_range() {
         test_sampler_in_range(Random);
+        test_dimensions_in_range(Random);
     }
 
     #[test]
     fn test_jittered_sampler_in_range() {
-        test_sampler_in_range(Jittered);
+        test_sampler_in_range(Jittered::new());
+        test_dimensions_in_range(Jittered::new());
     }
 
     #[test]
     fn test_uniform_sampler_in_range() {
-        test_sampler_in_range(Uniform);
+        test_sampler_in_range(Uniform::new());
+        test_dimensions_in_range(Uniform::new());
+    }
+
+    #[test]
+    fn test_sample_counts() {
+        let mut rng = seeded_rng(&[0]);
+        for count in 1..40 {
+            assert!(Uniform::new().samples((0, 0), count, &mut rng).len() == count as usize);
+            assert!(Jittered::new().samples((0, 0), count, &mut rng).len() == count as usize);
+        }
+    }
+
+    #[test]
+    fn test_strata_cover_pixel() {
+        for &count in [1, 2, 4, 7, 37, 100].iter() {
+            let area: f64 = (0..count).map(|i| { let (_, (w, h)) = stratum(i, count); w * h }).sum();
+            assert!((area - 1.).abs() < 1e-9);
+            let mut centers: Vec<(f64, f64)> = Uniform::new().samples((0, 0), count, &mut seeded_rng(&[0]));
+            centers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            centers.dedup();
+            assert!(centers.len() == count as usize);
+        }
+    }
+
+    #[test]
+    fn test_seeded_samples_repeat() {
+        let s1 = Jittered::new().samples((4, 2), 4, &mut seeded_rng(&[42, 4, 2]));
+        let s2 = Jittered::new().samples((4, 2), 4, &mut seeded_rng(&[42, 4, 2]));
+        let s3 = Jittered::new().samples((4, 2), 4, &mut seeded_rng(&[43, 4, 2]));
+        assert!(s1 == s2);
+        assert!(s1 != s3);
+        let o1 = Uniform::with_seed(1).samples((4, 2), 37, &mut seeded_rng(&[0]));
+        let o2 = Uniform::with_seed(2).samples((4, 2), 37, &mut seeded_rng(&[0]));
+        assert!(o1 != o2);
     }
 }
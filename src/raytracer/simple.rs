@@ -1,159 +1,844 @@
 use image::*;
 use nalgebra::*;
+use rand::Rng;
 use rayon::prelude::*;
 
 use std::boxed::Box as StdBox;
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::time::Instant;
 
+use film::{Aov, Film, FilmTile, Filter};
 use intersection::Intersection;
 use light::Light;
 use objects::*;
 use ray::Ray;
+use raytracer::adaptive::*;
+use raytracer::observer::*;
+use raytracer::progressive::*;
 use raytracer::sampler::*;
+use raytracer::tiles::*;
 use scene::Scene;
 use util::*;
 
-pub struct SimpleSettings<F: Fn(f64)> {
+pub struct SimpleSettings {
+    /// Number of samples per pixel, which does not need to be a square.
     pub n_samples: u32,
-    pub progress_callback: Option<StdBox<F>>
+    /// Number of reflection bounces, 0 for direct illumination only.
+    pub bounces: u32,
+    /// Seed of the random numbers. Every sample draws from its own generator,
+    /// seeded from its pixel and index, so a given seed always produces the
+    /// same image.
+    pub seed: u32,
+    /// Adaptive sampling settings. When set, they replace `n_samples`.
+    pub adaptive: Option<AdaptiveSettings>,
+    /// Pixel reconstruction filter.
+    pub filter: StdBox<Filter>,
+    /// Extra buffers to render along with the radiance.
+    pub aovs: Vec<Aov>,
+    /// Side of the square tiles the image is rendered in. Samples near the
+    /// edges of a tile are also taken in the tiles around it, within the
+    /// radius of the filter, so small tiles take more samples.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Pixels to render, the whole image if not set. The film keeps the size
+    /// of the image. Pixels of the window come out as in a render of the
+    /// whole image, samples being also taken around the window within the
+    /// radius of the filter, and pixels out of it get nothing.
+    pub crop: Option<CropWindow>,
+    /// Receives the progress of renders.
+    pub observer: Option<StdBox<RenderObserver>>,
+    /// Stops renders when cancelled, the renderers then return what they
+    /// rendered so far.
+    pub cancellation: CancellationToken
 }
 
-/// Simple ray tracer. Only does simple illumination and no reflections.
-pub struct Simple<S: PixelSampler, F: Fn(f64)> {
+/// Half angle of the cone in which reflection rays are cast.
+const REFLECTION_CONE_ANGLE: f64 = 30. * ::std::f64::consts::PI / 180.;
+
+/// Radiance of a camera sample, split between the light coming straight from
+/// its first hit and the light reflected there.
+struct CameraSample<'a> {
+    position: (f64, f64),
+    direct: Rgb<f64>,
+    indirect: Rgb<f64>,
+    hit: Option<Intersection<'a>>
+}
+
+impl<'a> CameraSample<'a> {
+    fn energy(&self) -> Rgb<f64> {
+        rgb_add(&self.direct, &self.indirect)
+    }
+
+    fn alpha(&self) -> f64 {
+        if self.hit.is_some() { 1. } else { 0. }
+    }
+}
+
+/// Simple ray tracer. Only does simple illumination, and reflections when
+/// bouncing.
+pub struct Simple<S: PixelSampler> {
     scene: Scene,
-    settings: SimpleSettings<F>,
-    sampler: S
+    settings: SimpleSettings,
+    sampler: S,
+    /// Rays traced since the renderer was built, by all its renders.
+    rays: AtomicUsize
+}
+
+/// Reports the progress of a render to the observer of the settings.
+struct RenderTracker<'a> {
+    observer: Option<&'a RenderObserver>,
+    start: Instant,
+    rays: &'a AtomicUsize,
+    rays_at_start: usize
 }
 
+impl<'a> RenderTracker<'a> {
+    fn progress(&self, fraction: f64) -> Progress {
+        let rays = self.rays.load(AtomicOrdering::Relaxed).wrapping_sub(self.rays_at_start);
+        Progress::new(fraction, rays as u64, self.start.elapsed())
+    }
+
+    fn notify(&self, event: RenderEvent) {
+        if let Some(observer) = self.observer {
+            observer.notify(&event);
+        }
+    }
+}
+
+/// Renderers produce a floating point film holding the linear radiance of the
+/// scene, which can be converted to a display image afterwards.
 pub trait Renderer {
-    fn render(&self) -> RgbImage;
+    fn render(&self) -> Film;
 }
 
 pub trait ParallelRenderer {
-    fn render_parallel(&self) -> RgbImage;
+    fn render_parallel(&self) -> Film;
 }
 
-impl<S: PixelSampler, F: Fn(f64)> Simple<S, F> {
-    pub fn new(scene: Scene, settings: SimpleSettings<F>, sampler: S) -> Simple<S, F> {
-        Simple { scene: scene, settings: settings, sampler: sampler }
+impl<S: PixelSampler> Simple<S> {
+    pub fn new(scene: Scene, settings: SimpleSettings, sampler: S) -> Simple<S> {
+        Simple { scene: scene, settings: settings, sampler: sampler, rays: AtomicUsize::new(0) }
+    }
+
+    /// Intersects a ray with the scene, counting it in `rays`.
+    fn trace(&self, ray: Ray, rays: &mut u64) -> Option<Intersection> {
+        *rays += 1;
+        self.scene.intersects(ray)
     }
 
-    fn cast_shadow_ray(&self, i: &Intersection, light: &Light) -> Option<Intersection> {
+    /// Tells whether anything blocks a ray, counting it in `rays`.
+    fn occluded(&self, ray: Ray, rays: &mut u64) -> bool {
+        *rays += 1;
+        self.scene.occluded(ray)
+    }
+
+    fn tracker(&self) -> RenderTracker {
+        RenderTracker { observer: self.settings.observer.as_ref().map(|o| &**o), start: Instant::now(),
+                        rays: &self.rays, rays_at_start: self.rays.load(AtomicOrdering::Relaxed) }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.settings.cancellation.is_cancelled()
+    }
+
+    /// Notifies the end of a render, `fraction` of which was done.
+    fn finish(&self, tracker: &RenderTracker, fraction: f64) {
+        let progress = tracker.progress(fraction);
+        tracker.notify(match self.is_cancelled() {
+            true => RenderEvent::Cancelled { progress: progress },
+            false => RenderEvent::Finished { progress: progress }
+        });
+    }
+
+    /// Returns the shadow ray towards a point of the light, if the point is
+    /// visible: in front of both the surface and the light, with nothing in
+    /// between.
+    fn cast_shadow_ray(&self, i: &Intersection, light: &Light, uv: (f64, f64), rays: &mut u64) -> Option<Ray> {
         let surface_normal = i.normal;
-        let p = light.random_on_face();
+        let p = light.sample_on_face_at(uv, i.ray.time);
         let ray_direction = p - i.position;
-        let shadow_ray = Ray::new(i.position, ray_direction);
-        match self.scene.intersects(shadow_ray) {
-            None => None,
-            Some(inter) => { if surface_normal.dot(&ray_direction) > 0. &&
-                                inter.object == &Object::from_light(light.clone())
-                                { Some(inter) } else { None }}
+        if surface_normal.dot(&ray_direction) <= 0. || light.face.normal_at(i.ray.time).dot(&ray_direction) >= 0. {
+            return None;
         }
+        let shadow_ray = Ray::shadow(i.position, surface_normal, p, i.ray.time);
+        if self.occluded(shadow_ray.clone(), rays) { None } else { Some(shadow_ray) }
     }
 
-    fn ray_energy(&self, ray: Ray) -> Rgb<f64> {
-        // Find closest intersection
-        let intersect_opt = self.scene.intersects(ray);
-        let mut pixel;
-
-        if let Some(intersect) = intersect_opt {
-            match intersect.object {
-                &Object::Light(ref l) => {
-                    // Paint the light with its diffuse color
-                    pixel = l.light_material().diffuse_intensity;
-                },
-                &Object::Surface(_) => {
-                    // Cast light ray and compute Phong shading
-                    let surface_normal = intersect.normal;
-                    pixel = intersect.object.material().ambient_color();
-                    for light in self.scene.lights() {
-                        match self.cast_shadow_ray(&intersect, light) {
-                            None => (),
-                            Some(inter) => {
-                                let ray_diffuse_color = light.shade_diffuse(&intersect, &inter);
-                                let ray_specular_color = light.shade_specular(self.scene.camera().eye_position(), &intersect, &inter);
-                                pixel = rgb_add(&rgb_add(&ray_diffuse_color, &ray_specular_color), &pixel);
-                            }
+    /// Returns the direct and indirect light leaving the intersection of a
+    /// camera ray towards the camera.
+    fn shade<R: Rng>(&self, intersect: &Intersection, dims: &mut SampleDimensions<S, R>, rays: &mut u64)
+                     -> (Rgb<f64>, Rgb<f64>) {
+        let black = Rgb { data: [0., 0., 0.] };
+        let bounces = self.settings.bounces;
+        match intersect.object {
+            &Object::Light(ref l) => {
+                // Paint the light with its diffuse color
+                (l.light_material().diffuse_intensity, black)
+            },
+            &Object::Surface(_) if bounces == 0 => {
+                // Cast light ray and compute Phong shading
+                let mut direct = intersect.material().ambient_color();
+                for light in self.scene.lights() {
+                    match self.cast_shadow_ray(intersect, light, dims.next_2d(), rays) {
+                        None => (),
+                        Some(shadow_ray) => {
+                            let ray_diffuse_color = light.shade_diffuse(intersect, &shadow_ray);
+                            let ray_specular_color = light.shade_specular(self.scene.camera().eye_position(), intersect,
+                                                                          &shadow_ray);
+                            direct = rgb_add(&rgb_add(&ray_diffuse_color, &ray_specular_color), &direct);
                         }
                     }
                 }
+                (direct, black)
+            },
+            &Object::Surface(_) => {
+                // Average the surface color and the light reflected to it
+                match self.reflection(intersect, dims, rays) {
+                    None => (black, black),
+                    Some((color, refl_ray)) => {
+                        let energy = self.reflection_energy(refl_ray, bounces, dims, rays);
+                        let reflection = rgb_div(&energy, bounces as f64);
+                        (rgb_div(&color, 2.), rgb_div(&reflection, 2.))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the color of a surface lit by the last of the lights visible
+    /// from it, with the ray reflected in a cone around the mirror direction
+    /// of that light. Surfaces in the shadow of all the lights get nothing.
+    fn reflection<R: Rng>(&self, intersect: &Intersection, dims: &mut SampleDimensions<S, R>, rays: &mut u64)
+                          -> Option<(Rgb<f64>, Ray)> {
+        let surface_normal = intersect.normal;
+        let mut lit = None;
+        for light in self.scene.lights() {
+            if let Some(shadow_ray) = self.cast_shadow_ray(intersect, light, dims.next_2d(), rays) {
+                let ray_diffuse_color = light.shade_diffuse(intersect, &shadow_ray);
+                let ray_specular_color = light.shade_specular(self.scene.camera().eye_position(), intersect,
+                                                              &shadow_ray);
+                let color = rgb_add(&rgb_add(&ray_diffuse_color, &intersect.material().ambient_color()),
+                                    &ray_specular_color);
+                lit = Some((color, shadow_ray.direction.normalize()));
+            }
+        }
+        lit.map(|(color, l)| {
+            let r = 2. * l.dot(&surface_normal) * surface_normal - l;
+            let direction = sample_in_cone(r, REFLECTION_CONE_ANGLE, dims.next_2d());
+            (color, Ray::from_surface(intersect.position, surface_normal, direction, intersect.ray.time))
+        })
+    }
+
+    /// Returns the light coming along a reflection ray, which bounces
+    /// `bounces` more times. Surfaces reached after the last bounce are black.
+    fn reflection_energy<R: Rng>(&self, ray: Ray, bounces: u32, dims: &mut SampleDimensions<S, R>,
+                                 rays: &mut u64) -> Rgb<f64> {
+        let black = Rgb { data: [0., 0., 0.] };
+        match self.trace(ray, rays) {
+            None => self.scene.background(),
+            Some(intersect) => match intersect.object {
+                &Object::Light(ref l) => l.light_material().diffuse_intensity,
+                &Object::Surface(_) if bounces == 0 => black,
+                &Object::Surface(_) => match self.reflection(&intersect, dims, rays) {
+                    None => black,
+                    Some((color, refl_ray)) => {
+                        rgb_add(&color, &self.reflection_energy(refl_ray, bounces - 1, dims, rays))
+                    }
+                }
             }
         }
-        else {
-            pixel = self.scene.background();
+    }
+
+    /// Returns sample `index` out of `count` of a pixel. The time and light
+    /// dimensions of the sample come from the sampler.
+    fn camera_sample<R: Rng>(&self, pixel: (u32, u32), index: u32, count: u32, rng: &mut R, rays: &mut u64)
+                             -> CameraSample {
+        let camera = self.scene.camera();
+        let position = self.sampler.sample(pixel, index, count, rng);
+        let mut dims = self.sampler.dimensions(pixel, index, rng);
+        let time = camera.shutter_time(dims.next_1d());
+        let ray = camera.pixel_ray_at(position, time).unwrap();
+        match self.trace(ray, rays) {
+            Some(intersect) => {
+                let (direct, indirect) = self.shade(&intersect, &mut dims, rays);
+                CameraSample { position: position, direct: direct, indirect: indirect, hit: Some(intersect) }
+            }
+            None => CameraSample { position: position, direct: self.scene.background(),
+                                   indirect: Rgb { data: [0., 0., 0.] }, hit: None }
+        }
+    }
+
+    /// Adds a sample into the film, and its AOVs into the pixel at `(x, y)`.
+    fn add_sample(&self, film: &mut FilmTile, x: u32, y: u32, sample: &CameraSample) {
+        let height = film.resolution().1 as f64;
+        // The camera has y going up, the film has it going down
+        let (px, py) = sample.position;
+        film.add_sample((px, height - py), &sample.energy(), sample.alpha());
+        for aov in self.settings.aovs.iter() {
+            let value = match (*aov, &sample.hit) {
+                (Aov::Direct, _) => sample.direct.data.to_vec(),
+                (Aov::Indirect, _) => sample.indirect.data.to_vec(),
+                (_, &None) => continue,
+                (Aov::Depth, &Some(ref i)) => vec!(i.distance),
+                (Aov::Normal, &Some(ref i)) => vec!(i.normal.x, i.normal.y, i.normal.z),
+                (Aov::Albedo, &Some(ref i)) => i.material().diffuse_color().data.to_vec(),
+                (Aov::ObjectId, &Some(ref i)) => vec!(i.object_id as f64),
+                (Aov::MaterialId, &Some(ref i)) => vec!(self.scene.material_handle_id(i.material()) as f64)
+            };
+            film.add_aov_sample(x, y, *aov, &value);
+        }
+    }
+
+    /// Samples a pixel into the film, recording the number of samples taken.
+    /// The rays traced are counted in `rays`.
+    fn sample_pixel(&self, pixel: (u32, u32), film: &mut FilmTile, rays: &mut u64) {
+        let count = match self.settings.adaptive {
+            None => {
+                let n = self.settings.n_samples;
+                for index in 0..n {
+                    self.take_sample(pixel, index, n, film, rays);
+                }
+                n
+            }
+            Some(ref adaptive) => {
+                // Strata are laid out for the whole budget, the samplers spread
+                // the first ones over the pixel
+                let mut stats = PixelStatistics::new();
+                while adaptive.needs_samples(&stats) {
+                    let energy = self.take_sample(pixel, stats.count(), adaptive.max_samples, film, rays);
+                    stats.add(rgb_luminance(&energy));
+                }
+                stats.count()
+            }
+        };
+        let y = film.resolution().1 - 1 - pixel.1;
+        film.add_sample_count(pixel.0, y, count);
+    }
+
+    /// Takes sample `index` out of `count` of a pixel into the film and
+    /// returns its energy. The sample count of the pixel is left untouched.
+    fn take_sample(&self, pixel: (u32, u32), index: u32, count: u32, film: &mut FilmTile, rays: &mut u64)
+                   -> Rgb<f64> {
+        let mut rng = seeded_rng(&[self.settings.seed, pixel.0, pixel.1, index]);
+        let sample = self.camera_sample(pixel, index, count, &mut rng, rays);
+        let y = film.resolution().1 - 1 - pixel.1;
+        self.add_sample(film, pixel.0, y, &sample);
+        sample.energy()
+    }
+
+    /// Returns the window of the image to render.
+    fn window(&self) -> CropWindow {
+        let (width, height) = self.scene.camera().viewport();
+        self.settings.crop.map_or(CropWindow::full(width, height), |c| c.clamp(width, height))
+    }
+
+    /// Returns an empty film of the image to render.
+    pub fn new_film(&self) -> Film {
+        let (width, height) = self.scene.camera().viewport();
+        Film::with_aovs(width, height, self.settings.filter.box_clone(), &self.settings.aovs)
+    }
+
+    fn tile_template(&self) -> Film {
+        let (width, height) = self.scene.camera().viewport();
+        Film::without_pixels(width, height, self.settings.filter.box_clone(), &self.settings.aovs)
+    }
+
+    /// Returns the tiles of the image to render, in the order they are
+    /// rendered. Tiles do not share any pixel, so their films can be merged
+    /// in any order and give the same image as `render`.
+    pub fn tile_schedule(&self) -> Vec<Tile> {
+        tiles_in(&self.window(), self.settings.tile_size.max(1), self.settings.tile_order)
+    }
+
+    /// Returns the pixels whose samples contribute to a tile, those of the
+    /// tile and those around it within the filter radius, even out of the
+    /// window to render.
+    fn sampled_window(&self, tile: &Tile) -> CropWindow {
+        // Samples are taken up to half a pixel away from the center of their
+        // pixel, and the filter is 0 at its radius
+        let (rx, ry) = self.settings.filter.radius();
+        let (mx, my) = (((rx + 0.5).ceil() - 1.).max(0.) as u32, ((ry + 0.5).ceil() - 1.).max(0.) as u32);
+        let (width, height) = self.scene.camera().viewport();
+        CropWindow::new(tile.x0.saturating_sub(mx), tile.y0.saturating_sub(my),
+                        (tile.x1 + mx).min(width), (tile.y1 + my).min(height))
+    }
+
+    /// Renders a tile on its own, e.g. in another process, into a film
+    /// covering its pixels.
+    pub fn render_tile_film(&self, tile: &Tile) -> Film {
+        let mut film = self.tile_template().tile(tile.x0, tile.y0, tile.x1, tile.y1);
+        self.render_tile(&mut film.tiles_mut(&[*tile])[0], tile,
+                         &|pixel, film: &mut FilmTile, rays: &mut u64| self.sample_pixel(pixel, film, rays));
+        film
+    }
+
+    /// Renders a tile into its tile of the film, sampling with `sample_pixel`
+    /// the pixels whose samples contribute to it. Stops at the first row
+    /// after cancellation. The rays of the tile are counted locally and added
+    /// to the rays traced once the tile is done.
+    fn render_tile<G>(&self, film: &mut FilmTile, tile: &Tile, sample_pixel: &G)
+        where G: Fn((u32, u32), &mut FilmTile, &mut u64)
+    {
+        let height = film.resolution().1;
+        let sampled = self.sampled_window(tile);
+        let mut rays = 0;
+        for y in sampled.y0..sampled.y1 {
+            if self.is_cancelled() {
+                break;
+            }
+            for x in sampled.x0..sampled.x1 {
+                sample_pixel((x, height - 1 - y), film, &mut rays);
+            }
         }
-        pixel
+        self.rays.fetch_add(rays as usize, AtomicOrdering::Relaxed);
     }
 }
 
-impl<S, P> Renderer for Simple<S, P>
-    where S: PixelSampler, P: Fn(f64)
+impl<S> Simple<S>
+    where S: PixelSampler + Sync
 {
-    fn render(&self) -> RgbImage {
-        let (width, height) = self.scene.camera().viewport();
-        let mut img = RgbImage::new(width, height);
-        let n2 = (self.settings.n_samples * self.settings.n_samples) as f64;
-        let n_samples = (width * height) as f64;
-        let mut i = 0.;
-
-        for (x, y_inverted, pixel) in img.enumerate_pixels_mut() {
-            let y = height - 1 - y_inverted;
-            let mut energy = Rgb { data: [0., 0., 0.] };
-            for sample in self.sampler.samples((x, y), self.settings.n_samples) {
-                let (xf, yf) = sample;
-                let ray = self.scene.camera().pixel_ray((xf, yf)).unwrap();
-                energy = rgb_add(&energy, &self.ray_energy(ray));
-            }
-            energy = rgb_clamp_0_1(&rgb_div(&energy, n2));
-            *pixel = rgb_to_u8(&rgb_01_to_255(&energy));
-
-            if let Some(ref cb) = self.settings.progress_callback {
-                i += 1.;
-                cb(i / n_samples);
+    /// Renders the tiles in parallel straight into their tiles of the film,
+    /// each thread taking the next tile in order once done with the previous
+    /// one. `tile_done` is called with each tile rendered and the fraction of
+    /// tiles done, which is also returned.
+    fn render_tiles_parallel<G, Q>(&self, film: &mut Film, sample_pixel: G, tile_done: Q) -> f64
+        where G: Fn((u32, u32), &mut FilmTile, &mut u64) + Sync,
+              Q: Fn(&Tile, f64) + Sync
+    {
+        let tiles = self.tile_schedule();
+        let n_tiles = tiles.len().max(1);
+        let done = AtomicUsize::new(0);
+        // Only handing out the tiles goes through the lock
+        let next_tile = Mutex::new(tiles.iter().zip(film.tiles_mut(&tiles)));
+
+        (0..tiles.len()).into_par_iter().for_each(|_| {
+            let (tile, mut film_tile) = match next_tile.lock().unwrap().next() {
+                Some(next) => next,
+                None => return
+            };
+            if self.is_cancelled() {
+                return;
+            }
+            self.render_tile(&mut film_tile, tile, &sample_pixel);
+            if !self.is_cancelled() {
+                let done = done.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+                tile_done(tile, done as f64 / n_tiles as f64);
+            }
+        });
+        done.load(AtomicOrdering::SeqCst) as f64 / n_tiles as f64
+    }
+}
+
+impl<S> Renderer for Simple<S>
+    where S: PixelSampler
+{
+    fn render(&self) -> Film {
+        let tracker = self.tracker();
+        let mut film = self.new_film();
+        let tiles = self.tile_schedule();
+        let n_tiles = tiles.len().max(1);
+        let mut done = 0;
+        let sample_pixel = |pixel, film: &mut FilmTile, rays: &mut u64| self.sample_pixel(pixel, film, rays);
+
+        for (tile, mut film_tile) in tiles.iter().zip(film.tiles_mut(&tiles)) {
+            self.render_tile(&mut film_tile, tile, &sample_pixel);
+            if self.is_cancelled() {
+                break;
             }
+            done += 1;
+            let progress = tracker.progress(done as f64 / n_tiles as f64);
+            tracker.notify(RenderEvent::TileDone { tile: *tile, progress: progress });
         }
-        img
+        self.finish(&tracker, done as f64 / n_tiles as f64);
+        film
     }
 }
 
-impl<S, P> ParallelRenderer for Simple<S, P>
-    where S: PixelSampler + Sync,
-          P: Fn(f64) + Sync
+impl<S> ParallelRenderer for Simple<S>
+    where S: PixelSampler + Sync
 {
-    fn render_parallel(&self) -> RgbImage {
-        let (width, height) = self.scene.camera().viewport();
-        let n2 = (self.settings.n_samples * self.settings.n_samples) as f64;
-        let rows = (0..height).into_iter().map(|y| { (0..width).into_iter().map(|x| (x, y)).collect::<Vec<(u32, u32)>>() })
-                                          .collect::<Vec<Vec<(u32, u32)>>>();
-        let i = Arc::new(Mutex::new(0.));
-
-        let pixels : Vec<Vec<((u32, u32), Rgb<u8>)>> = rows.par_iter().map(|row| -> Vec<((u32, u32), Rgb<u8>)> {
-            let row_pixels = row.into_iter().map(|&(x, y)| {
-                let mut energy = Rgb { data: [0., 0., 0.] };
-                for sample in self.sampler.samples((x, y), self.settings.n_samples) {
-                    let (xf, yf) = sample;
-                    let ray = self.scene.camera().pixel_ray((xf, yf)).unwrap();
-                    energy = rgb_add(&energy, &self.ray_energy(ray));
+    fn render_parallel(&self) -> Film {
+        let tracker = self.tracker();
+        let mut film = self.new_film();
+        let fraction = self.render_tiles_parallel(
+            &mut film,
+            |pixel, film: &mut FilmTile, rays: &mut u64| self.sample_pixel(pixel, film, rays),
+            |tile, done| tracker.notify(RenderEvent::TileDone { tile: *tile, progress: tracker.progress(done) }));
+        self.finish(&tracker, fraction);
+        film
+    }
+}
+
+impl<S> ProgressiveRenderer for Simple<S>
+    where S: PixelSampler + Sync
+{
+    fn render_progressive<C>(&self, settings: &ProgressiveSettings, snapshot: C) -> Film
+        where C: FnMut(&ProgressiveState)
+    {
+        self.resume_progressive(settings, ProgressiveState::new(self.new_film()), snapshot)
+    }
+
+    fn resume_progressive<C>(&self, settings: &ProgressiveSettings, state: ProgressiveState,
+                             mut snapshot: C) -> Film
+        where C: FnMut(&ProgressiveState)
+    {
+        let tracker = self.tracker();
+        let mut last_snapshot = tracker.start;
+        let mut state = state;
+        let width = state.film.resolution().0;
+        assert!(state.film.resolution() == self.new_film().resolution());
+        let window = self.window();
+        let in_window: Vec<usize> = (window.y0..window.y1)
+            .flat_map(|y| (window.x0..window.x1).map(move |x| (y * width + x) as usize))
+            .collect();
+        let passes = settings.passes;
+        let adaptive = self.settings.adaptive.as_ref();
+
+        while state.pass < passes {
+            // Each pass goes in a film of its own to measure how much pixels vary
+            // between passes. With adaptive sampling, the pixels having enough
+            // samples sit the pass out.
+            let index = state.pass;
+            let sampled: Vec<bool> = state.statistics.iter()
+                .map(|stats| adaptive.map_or(true, |a| a.needs_samples(stats)))
+                .collect();
+            let take_sample = |pixel: (u32, u32), film: &mut FilmTile, rays: &mut u64| {
+                let y = film.resolution().1 - 1 - pixel.1;
+                if sampled[(y * width + pixel.0) as usize] {
+                    self.take_sample(pixel, index, passes, film, rays);
+                    film.add_sample_count(pixel.0, y, 1);
+                }
+            };
+            let tile_done = |tile: &Tile, done: f64| {
+                let progress = tracker.progress((index as f64 + done) / passes as f64);
+                tracker.notify(RenderEvent::TileDone { tile: *tile, progress: progress });
+            };
+            let mut pass_film = self.new_film();
+            self.render_tiles_parallel(&mut pass_film, take_sample, tile_done);
+            if self.is_cancelled() {
+                // The unfinished pass is dropped, leaving a state to resume from
+                snapshot(&state);
+                break;
+            }
+            state.film.merge(&pass_film);
+            state.pass += 1;
+
+            for &i in in_window.iter().filter(|&&i| sampled[i]) {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                state.statistics[i].add(rgb_luminance(&pass_film.pixel(x, y)));
+            }
+            let progress = tracker.progress(state.pass as f64 / passes as f64);
+            tracker.notify(RenderEvent::PassDone { pass: state.pass, passes: passes, progress: progress });
+
+            let converged = settings.noise_threshold.map_or(false, |t| {
+                in_window.iter().all(|&i| state.statistics[i].is_converged(t))
+            }) || adaptive.map_or(false, |a| in_window.iter().all(|&i| !a.needs_samples(&state.statistics[i])));
+            let out_of_time = settings.time_budget.map_or(false, |budget| tracker.start.elapsed() >= budget);
+            let done = converged || out_of_time || state.pass == passes;
+            let snapshot_due = settings.snapshot_interval.map_or(true, |i| last_snapshot.elapsed() >= i);
+            if done || snapshot_due {
+                snapshot(&state);
+                last_snapshot = Instant::now();
+            }
+            if done {
+                break;
+            }
+        }
+        self.finish(&tracker, state.pass as f64 / passes as f64);
+        state.film
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Perspective;
+    use film::{denoise, filter_from_name, same_image, DenoiseSettings, ToneMapping, ALL_AOVS};
+    use material::{Phong, LightMaterial};
+    use num_traits::{One, Zero};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn test_renderer(seed: u32) -> Simple<Jittered> {
+        test_renderer_sized(seed, 8)
+    }
+
+    fn test_renderer_sized(seed: u32, size: u32) -> Simple<Jittered> {
+        let grey = Rgb { data: [0.6, 0.6, 0.6] };
+        let material = Phong::new(Rgb { data: [0.1, 0.1, 0.1] }, grey, grey, 2.);
+        let wall = Face::new(4., 4., Isometry3::new(Vector3::new(0., 0., -3.), Vector3::zero()),
+                             Arc::new(material.clone()));
+        let light = Light::new(Face::new(1., 1., Isometry3::new(Vector3::new(0., 1.5, -2.), Vector3::x() * (PI / 2.)),
+                                         Arc::new(material.clone())),
+                               LightMaterial::new(grey, grey));
+        let cam = Perspective::fov((size, size), ((90.).to_radians(), (90.).to_radians()), Isometry3::one());
+        let scene = Scene::new(Rgb { data: [0.3, 0.3, 0.3] },
+                               vec!(Object::from_surface(Surface::from_face(wall)),
+                                    Object::from_light(light)),
+                               StdBox::new(cam));
+        let settings = SimpleSettings { n_samples: 5, bounces: 1, seed: seed, adaptive: None,
+                                        filter: filter_from_name("box").unwrap(), aovs: vec!(),
+                                        tile_size: 4, tile_order: TileOrder::Spiral, crop: None,
+                                        observer: None, cancellation: CancellationToken::new() };
+        Simple::new(scene, settings, Jittered::with_seed(seed))
+    }
+
+    #[test]
+    fn test_render_deterministic() {
+        let r = test_renderer(7);
+        let film = r.render();
+        assert!(same_image(&film, &r.render()));
+        assert!(same_image(&film, &r.render_parallel()));
+    }
+
+    #[test]
+    fn test_tile_orders_same_image() {
+        let mut r = test_renderer_sized(7, 16);
+        r.settings.filter = filter_from_name("gaussian").unwrap();
+        let film = r.render_parallel();
+        for &order in [TileOrder::Scanline, TileOrder::Hilbert].iter() {
+            r.settings.tile_order = order;
+            assert!(same_image(&film, &r.render_parallel()));
+            assert!(same_image(&film, &r.render()));
+        }
+    }
+
+    #[test]
+    fn test_progressive_matches_render() {
+        let r = test_renderer(4);
+        let mut passes = vec!();
+        let film = r.render_progressive(&ProgressiveSettings::new(5), |state| passes.push(state.pass));
+        assert!(passes == vec!(1, 2, 3, 4, 5));
+        // Pass k takes sample k of every pixel, as the whole render does
+        let reference = r.render();
+        for y in 0..8 {
+            for x in 0..8 {
+                assert!(film.sample_count(x, y) == 5);
+                for c in 0..3 {
+                    assert!((film.pixel(x, y)[c] - reference.pixel(x, y)[c]).abs() < 1e-9);
                 }
-                energy = rgb_clamp_0_1(&rgb_div(&energy, n2));
-                let pixel = rgb_to_u8(&rgb_01_to_255(&energy));
-                ((x, y), pixel)
+            }
+        }
+    }
+
+    #[test]
+    fn test_progressive_stops_early() {
+        let r = test_renderer(4);
+        let mut settings = ProgressiveSettings::new(16);
+        settings.noise_threshold = Some(100.);
+        settings.snapshot_interval = Some(Duration::from_secs(3600));
+        let mut snapshots = vec!();
+        let film = r.render_progressive(&settings, |state| snapshots.push(state.pass));
+        // Statistics need two passes, and only the last pass is snapshotted
+        assert!(snapshots == vec!(2));
+        assert!(film.sample_count(0, 0) == 2);
+
+        settings.noise_threshold = None;
+        settings.time_budget = Some(Duration::from_secs(0));
+        let film = r.render_progressive(&settings, |_| ());
+        assert!(film.sample_count(0, 0) == 1);
+    }
 
-            }).collect();
-            if let Some(ref cb) = self.settings.progress_callback {
-                let mut i_mut = i.lock().unwrap();
-                *i_mut += 1.;
-                cb(*i_mut / height as f64);
+    #[test]
+    fn test_progressive_adaptive() {
+        let mut r = test_renderer(4);
+        // Any two samples are close enough
+        r.settings.adaptive = Some(AdaptiveSettings::new(2, 16, 1e9));
+        let mut snapshots = vec!();
+        let film = r.render_progressive(&ProgressiveSettings::new(16), |state| snapshots.push(state.pass));
+        assert!(snapshots == vec!(1, 2));
+        for y in 0..8 {
+            for x in 0..8 {
+                assert!(film.sample_count(x, y) == 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_progressive_resume() {
+        let r = test_renderer(4);
+        let settings = ProgressiveSettings::new(6);
+        let mut saved = None;
+        let film = r.render_progressive(&settings, |state| if state.pass == 2 {
+            saved = Some(state.clone());
+        });
+        let resumed = r.resume_progressive(&settings, saved.unwrap(), |_| ());
+        assert!(same_image(&film, &resumed));
+        assert!(resumed.sample_count(3, 3) == 6);
+    }
+
+    #[test]
+    fn test_render_events() {
+        let mut r = test_renderer(4);
+        let events = Arc::new(Mutex::new(vec!()));
+        let observed = events.clone();
+        r.settings.observer = Some(StdBox::new(move |e: &RenderEvent| observed.lock().unwrap().push(e.clone())));
+        r.render_parallel();
+        let events = events.lock().unwrap();
+        // 4 tiles of 4x4 pixels, then the end of the render
+        assert!(events.len() == 5);
+        assert!(events[..4].iter().all(|e| match *e { RenderEvent::TileDone { .. } => true, _ => false }));
+        let progress = match events[4] {
+            RenderEvent::Finished { progress } => progress,
+            _ => panic!("render not finished")
+        };
+        assert!(progress.fraction == 1. && progress.eta == Some(Duration::from_secs(0)));
+        // At least a camera ray per sample
+        assert!(progress.rays >= 5 * 64);
+    }
+
+    #[test]
+    fn test_tiles_rendered_in_order() {
+        for &order in [TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let mut r = test_renderer_sized(7, 16);
+            r.settings.tile_order = order;
+            let done = Arc::new(Mutex::new(vec!()));
+            let observed = done.clone();
+            r.settings.observer = Some(StdBox::new(move |e: &RenderEvent| {
+                if let RenderEvent::TileDone { tile, .. } = *e {
+                    observed.lock().unwrap().push(tile);
+                }
+            }));
+            r.render();
+            let done = done.lock().unwrap();
+            assert!(*done == r.tile_schedule() && done.len() == 16);
+            let adjacent = |a: &Tile, b: &Tile| {
+                (a.column as i32 - b.column as i32).abs() + (a.row as i32 - b.row as i32).abs() == 1
+            };
+            match order {
+                // The 4 tiles around the center of the 4×4 grid come first
+                TileOrder::Spiral => assert!(done[..4].iter().all(|t| 1 <= t.column && t.column <= 2 &&
+                                                                      1 <= t.row && t.row <= 2)),
+                _ => assert!(done.windows(2).all(|w| adjacent(&w[0], &w[1])))
             }
-            row_pixels
-        }).collect();
+        }
+    }
+
+    #[test]
+    fn test_render_cancelled() {
+        let r = test_renderer(4);
+        r.settings.cancellation.cancel();
+        assert!(r.render().sample_count(0, 0) == 0);
+        assert!(r.render_parallel().sample_count(7, 7) == 0);
+
+        // Cancel a progressive render from its observer after two passes
+        let mut r = test_renderer(4);
+        let token = r.settings.cancellation.clone();
+        r.settings.observer = Some(StdBox::new(move |e: &RenderEvent| match *e {
+            RenderEvent::PassDone { pass: 2, .. } => token.cancel(),
+            _ => ()
+        }));
+        let mut snapshots = vec!();
+        let film = r.render_progressive(&ProgressiveSettings::new(6), |state| snapshots.push(state.pass));
+        assert!(snapshots == vec!(1, 2, 2));
+        assert!(film.sample_count(3, 3) == 2);
+    }
 
-        let mut img = RgbImage::new(width, height);
-        for row in pixels {
-            for ((x, y), pixel) in row {
-                img.put_pixel(x, height - 1 - y, pixel);
+    #[test]
+    fn test_crop_matches_full_render() {
+        let mut r = test_renderer_sized(7, 16);
+        // The filter spreads samples taken around the crop into it
+        r.settings.filter = filter_from_name("gaussian").unwrap();
+        let full = r.render_parallel();
+        let window = CropWindow::new(5, 3, 12, 9);
+        r.settings.crop = Some(window);
+        let crop = r.render_parallel();
+        for y in 0..16 {
+            for x in 0..16 {
+                match window.contains(x, y) {
+                    true => assert!(crop.film_pixel(x, y) == full.film_pixel(x, y)),
+                    false => assert!(crop.sample_count(x, y) == 0)
+                }
             }
         }
-        img
+        let mut settings = ProgressiveSettings::new(8);
+        settings.noise_threshold = Some(100.);
+        assert!(r.render_progressive(&settings, |_| ()).sample_count(5, 3) == 2);
+    }
+
+    #[test]
+    fn test_render_coverage() {
+        let film = test_renderer(7).render();
+        // The wall covers the center of the image, not its corners
+        assert!(film.alpha(4, 4) == 1.);
+        assert!(film.alpha(0, 0) == 0.);
+        assert!(film.sample_count(0, 0) == 5);
+    }
+
+    #[test]
+    fn test_render_aovs() {
+        let mut r = test_renderer(7);
+        r.settings.aovs = ALL_AOVS.to_vec();
+        let film = r.render();
+        assert!(same_image(&film, &test_renderer(7).render()));
+        let close = |x, y, aov, expected: &[f64]| {
+            let value = film.aov_pixel(x, y, aov).unwrap();
+            value.iter().zip(expected.iter()).all(|(v, e)| (v - e).abs() < 1e-9)
+        };
+        // The wall faces the camera 3 units away
+        assert!(close(4, 4, Aov::Normal, &[0., 0., 1.]));
+        assert!(film.aov_pixel(4, 4, Aov::Depth).unwrap()[0] >= 3.);
+        assert!(close(4, 4, Aov::Albedo, &[0.6, 0.6, 0.6]));
+        assert!(close(4, 4, Aov::ObjectId, &[1.]));
+        assert!(close(4, 4, Aov::MaterialId, &[1.]));
+        assert!(close(0, 0, Aov::ObjectId, &[0.]));
+        assert!(close(0, 0, Aov::Direct, &[0.3, 0.3, 0.3]));
+        assert!(close(0, 0, Aov::Indirect, &[0., 0., 0.]));
+        // Beauty is the sum of direct and indirect light
+        let direct = film.aov_pixel(4, 4, Aov::Direct).unwrap();
+        let indirect = film.aov_pixel(4, 4, Aov::Indirect).unwrap();
+        let beauty = film.pixel(4, 4);
+        assert!(close(4, 4, Aov::Direct, &[beauty[0] - indirect[0], beauty[1] - indirect[1],
+                                           beauty[2] - indirect[2]]));
+        assert!(direct[0] > 0.);
+    }
+
+    #[test]
+    fn test_denoise_against_reference() {
+        let mut r = test_renderer_sized(3, 24);
+        r.settings.aovs = vec!(Aov::Normal, Aov::Albedo, Aov::Depth);
+        r.settings.n_samples = 2;
+        let noisy = r.render();
+        r.settings.n_samples = 128;
+        let reference = r.render();
+        let denoised = denoise(&noisy, &DenoiseSettings::new());
+        let error = |film: &Film| -> f64 {
+            (0..24).flat_map(|y| (0..24).map(move |x| (x, y))).map(|(x, y)| {
+                let (a, b) = (film.pixel(x, y), reference.pixel(x, y));
+                (0..3).map(|c| (a[c] - b[c]).powi(2)).sum::<f64>()
+            }).sum()
+        };
+        assert!(error(&denoised) < error(&noisy));
+    }
+
+    #[test]
+    fn test_adaptive_render() {
+        let mut r = test_renderer(3);
+        r.settings.adaptive = Some(AdaptiveSettings::new(4, 32, 0.01));
+        let film = r.render();
+        let parallel = r.render_parallel();
+        assert!(same_image(&film, &parallel));
+        let counts = film.sample_counts();
+        assert!(counts.clone().into_raw() == parallel.sample_counts().into_raw());
+        assert!(counts.pixels().all(|c| 4 <= c[0] && c[0] <= 32));
+        // The flat background converges as early as possible
+        assert!(counts.pixels().any(|c| c[0] == 4));
+    }
+
+    #[test]
+    fn test_filtered_render_deterministic() {
+        let mut r = test_renderer(5);
+        r.settings.filter = filter_from_name("mitchell").unwrap();
+        assert!(same_image(&r.render(), &r.render_parallel()));
+    }
+
+    #[test]
+    fn test_render_seed_changes_noise() {
+        let tone = ToneMapping::default();
+        let img1 = test_renderer(1).render().to_rgb_image(&tone).into_raw();
+        let img2 = test_renderer(2).render().to_rgb_image(&tone).into_raw();
+        assert!(img1 != img2);
     }
 }
@@ -0,0 +1,223 @@
+use std::cmp::Ordering;
+
+/// Order in which the tiles of an image are rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    /// Row by row from the top left corner.
+    Scanline,
+    /// Outwards from the center of the image.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles close together.
+    Hilbert
+}
+
+/// Rectangle `[x0, x1) × [y0, y1)` of an image, `y` going down from the top.
+/// `column` and `row` locate the tile in the grid of tiles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+    pub column: u32,
+    pub row: u32
+}
+
+/// Rectangle `[x0, x1) × [y0, y1)` of the pixels of an image to render, `y`
+/// going down from the top.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropWindow {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None
+        }
+    }
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+/// Distance along the Hilbert curve filling a `n × n` grid, `n` being a power
+/// of two, of the cell at `(x, y)`.
+fn hilbert_index(n: u32, x: u32, y: u32) -> u64 {
+    let (mut x, mut y) = (x, y);
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as u32;
+        let ry = ((y & s) > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve continues from the previous one
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            ::std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+impl CropWindow {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> CropWindow {
+        assert!(x0 <= x1 && y0 <= y1);
+        CropWindow { x0: x0, y0: y0, x1: x1, y1: y1 }
+    }
+
+    /// Returns the window covering a whole `width × height` image.
+    pub fn full(width: u32, height: u32) -> CropWindow {
+        CropWindow::new(0, 0, width, height)
+    }
+
+    /// Returns horizontal band `part` out of `parts` of equal height, to
+    /// split an image between several renders.
+    pub fn band(width: u32, height: u32, part: u32, parts: u32) -> CropWindow {
+        assert!(part < parts);
+        let row = |i: u32| (i as u64 * height as u64 / parts as u64) as u32;
+        CropWindow::new(0, row(part), width, row(part + 1))
+    }
+
+    /// Parses a window written `x0,y0,x1,y1`.
+    pub fn parse(s: &str) -> Option<CropWindow> {
+        let values: Vec<u32> = s.split(',').filter_map(|v| v.trim().parse().ok()).collect();
+        match values.len() == 4 && values[0] <= values[2] && values[1] <= values[3] {
+            true => Some(CropWindow::new(values[0], values[1], values[2], values[3])),
+            false => None
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.x0 <= x && x < self.x1 && self.y0 <= y && y < self.y1
+    }
+
+    /// Returns the part of the window within a `width × height` image.
+    pub fn clamp(&self, width: u32, height: u32) -> CropWindow {
+        let (x1, y1) = (self.x1.min(width), self.y1.min(height));
+        CropWindow::new(self.x0.min(x1), self.y0.min(y1), x1, y1)
+    }
+}
+
+/// Splits a `width × height` image into tiles of `size × size` pixels, smaller
+/// along the right and bottom edges, in the given order.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    tiles_in(&CropWindow::full(width, height), size, order)
+}
+
+/// Splits a window of an image into tiles the same way as `tiles`, the grid
+/// starting at the corner of the window.
+pub fn tiles_in(window: &CropWindow, size: u32, order: TileOrder) -> Vec<Tile> {
+    assert!(size > 0);
+    let (width, height) = (window.width(), window.height());
+    let (columns, rows) = ((width + size - 1) / size, (height + size - 1) / size);
+    let mut tiles: Vec<Tile> = (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row)))
+                                        .map(|(column, row)| {
+        Tile { x0: window.x0 + column * size, y0: window.y0 + row * size,
+               x1: window.x0 + ((column + 1) * size).min(width),
+               y1: window.y0 + ((row + 1) * size).min(height),
+               column: column, row: row }
+    }).collect();
+    match order {
+        TileOrder::Scanline => (),
+        TileOrder::Spiral => {
+            let center = ((columns as f64 - 1.) / 2., (rows as f64 - 1.) / 2.);
+            let key = |t: &Tile| {
+                let (dx, dy) = (t.column as f64 - center.0, t.row as f64 - center.1);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            tiles.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal));
+        }
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            tiles.sort_by_key(|t| hilbert_index(n, t.column, t.row));
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers_image(tiles: &[Tile], width: u32, height: u32) -> bool {
+        let mut covered = vec![0; width as usize * height as usize];
+        for t in tiles {
+            for y in t.y0..t.y1 {
+                for x in t.x0..t.x1 {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+        covered.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn test_tiles_cover_image() {
+        for &order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let t = tiles(37, 21, 8, order);
+            assert!(t.len() == 5 * 3);
+            assert!(covers_image(&t, 37, 21));
+        }
+        assert!(tiles(37, 21, 8, TileOrder::Scanline)[4].width() == 5);
+    }
+
+    #[test]
+    fn test_tiles_in_window() {
+        let window = CropWindow::parse("10, 4, 30, 13").unwrap();
+        let t = tiles_in(&window, 8, TileOrder::Scanline);
+        assert!(t.len() == 3 * 2);
+        assert!((t[0].x0, t[0].y0) == (10, 4) && (t[5].x1, t[5].y1) == (30, 13));
+        assert!(t.iter().map(|t| t.width() * t.height()).sum::<u32>() == 20 * 9);
+        assert!(CropWindow::parse("3,4,2,5").is_none() && CropWindow::parse("1,2,3").is_none());
+    }
+
+    #[test]
+    fn test_bands_split_image() {
+        let bands: Vec<_> = (0..3).map(|i| CropWindow::band(10, 20, i, 3)).collect();
+        assert!(bands[0] == CropWindow::new(0, 0, 10, 6));
+        assert!(bands.windows(2).all(|b| b[0].y1 == b[1].y0));
+        assert!(bands[2].y1 == 20);
+    }
+
+    #[test]
+    fn test_spiral_starts_at_center() {
+        let t = tiles(50, 50, 10, TileOrder::Spiral);
+        assert!((t[0].column, t[0].row) == (2, 2));
+        assert!(t[1..9].iter().all(|t| (t.column as i32 - 2).abs() <= 1 && (t.row as i32 - 2).abs() <= 1));
+    }
+
+    #[test]
+    fn test_hilbert_is_continuous() {
+        let t = tiles(64, 64, 8, TileOrder::Hilbert);
+        for w in t.windows(2) {
+            let d = (w[0].column as i32 - w[1].column as i32).abs() + (w[0].row as i32 - w[1].row as i32).abs();
+            assert!(d == 1);
+        }
+    }
+}
@@ -1,22 +1,180 @@
 use image::Rgb;
+use algebra::{AnimatedTransform, Transform};
 use camera::Camera;
 use light::Light;
+use material::{Material, MaterialLibrary};
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::boxed::Box as StdBox;
 use util::filter_nones;
 use ray::Ray;
 use intersection::*;
 use objects::*;
+use nalgebra::*;
 
 pub struct Scene {
     bg: Rgb<f64>,
     objects: Vec<Object>,
+    materials: MaterialLibrary,
+    /// Identifiers of the materials, by `handle_key`.
+    material_ids: HashMap<usize, u32>,
     camera: StdBox<Camera>
 }
 
+/// Address of the material a handle refers to, which tells handles apart
+/// like `same_handle` does.
+fn handle_key(material: &Arc<Material>) -> usize {
+    &**material as *const Material as *const u8 as usize
+}
+
+/// Numbers the materials the objects can be hit with from 1, in the order
+/// the objects use them, by handle. Distinct handles get distinct numbers,
+/// even for materials which look the same.
+fn material_ids(objects: &[Object]) -> HashMap<usize, u32> {
+    let mut ids = HashMap::new();
+    for material in objects.iter().flat_map(|o| o.materials()) {
+        let id = ids.len() as u32 + 1;
+        ids.entry(handle_key(material)).or_insert(id);
+    }
+    ids
+}
+
+/// 64 bits FNV-1a hash of the values of a scene, written one by one so that
+/// the hash only depends on what is rendered and not on how it is printed.
+struct SceneHasher(u64);
+
+impl SceneHasher {
+    fn new() -> SceneHasher {
+        SceneHasher(0xcbf29ce484222325)
+    }
+
+    fn write_u64(&mut self, v: u64) {
+        for i in 0..8 {
+            self.0 = (self.0 ^ ((v >> (8 * i)) & 0xff)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_f64(&mut self, v: f64) {
+        self.write_u64(v.to_bits());
+    }
+
+    fn write_rgb(&mut self, c: &Rgb<f64>) {
+        for v in c.data.iter() {
+            self.write_f64(*v);
+        }
+    }
+
+    fn write_vector(&mut self, v: &Vector3<f64>) {
+        for &x in [v.x, v.y, v.z].iter() {
+            self.write_f64(x);
+        }
+    }
+
+    fn write_isometry(&mut self, isometry: &Isometry3<f64>) {
+        self.write_vector(&isometry.translation);
+        for axis in [Vector3::x(), Vector3::y(), Vector3::z()].iter() {
+            self.write_vector(&(*isometry * *axis));
+        }
+    }
+
+    fn write_transform(&mut self, transform: &Transform) {
+        self.write_vector(&transform.transform_point(&Point3::new(0., 0., 0.)).to_vector());
+        for axis in [Vector3::x(), Vector3::y(), Vector3::z()].iter() {
+            self.write_vector(&transform.transform_vector(axis));
+        }
+    }
+
+    fn write_motion(&mut self, motion: &Option<AnimatedTransform>) {
+        match *motion {
+            Some(ref motion) => {
+                self.write_u64(motion.keyframes().len() as u64);
+                for &(t, ref keyframe) in motion.keyframes() {
+                    self.write_f64(t);
+                    self.write_isometry(keyframe);
+                }
+            }
+            None => self.write_u64(0)
+        }
+    }
+
+    fn write_material(&mut self, material: &Material) {
+        self.write_rgb(&material.ambient_color());
+        self.write_rgb(&material.diffuse_color());
+        self.write_rgb(&material.specular_color());
+        self.write_f64(material.shininess());
+    }
+
+    fn write_face(&mut self, face: &Face) {
+        self.write_f64(face.width);
+        self.write_f64(face.height);
+        self.write_transform(&face.transform);
+        self.write_motion(&face.motion);
+        self.write_material(&*face.material);
+    }
+
+    fn write_surface(&mut self, surface: &Surface) {
+        match *surface {
+            Surface::Face(ref f) => {
+                self.write_u64(0);
+                self.write_face(f);
+            }
+            Surface::Box(ref b) => {
+                self.write_u64(1);
+                self.write_vector(&b.size);
+                self.write_transform(&b.transform);
+                self.write_motion(&b.motion);
+                for face in BoxFace::all().iter() {
+                    self.write_material(&*b.face(*face).material);
+                }
+            }
+            Surface::Instance(ref i) => {
+                self.write_u64(2);
+                self.write_transform(&i.transform);
+                self.write_material(&**i.material());
+                self.write_surface(&i.surface);
+            }
+        }
+    }
+
+    /// Writes a camera through the rays of the corners and the center of its
+    /// image when the shutter opens and closes, cameras being only known by
+    /// their rays.
+    fn write_camera(&mut self, camera: &Camera) {
+        let (width, height) = camera.viewport();
+        let (open, close) = camera.shutter();
+        self.write_u64(width as u64);
+        self.write_u64(height as u64);
+        self.write_f64(open);
+        self.write_f64(close);
+        self.write_vector(&camera.eye_position().to_vector());
+        let (w, h) = (width as f64, height as f64);
+        for &time in [open, close].iter() {
+            for &coords in [(0., 0.), (w - 1., 0.), (0., h - 1.), (w - 1., h - 1.), (w / 2., h / 2.)].iter() {
+                match camera.pixel_ray_at(coords, time) {
+                    Some(ray) => {
+                        self.write_vector(&ray.origin.to_vector());
+                        self.write_vector(&ray.direction);
+                    }
+                    None => self.write_u64(0)
+                }
+            }
+        }
+    }
+}
+
 impl Scene {
     pub fn new(background: Rgb<f64>, objects: Vec<Object>,
                camera: StdBox<Camera>) -> Scene {
-        Scene { bg: background, objects: objects, camera: camera }
+        Scene::with_materials(background, MaterialLibrary::new(), objects, camera)
+    }
+
+    /// Builds a scene whose objects use the materials of a library, which can
+    /// then be changed by name.
+    pub fn with_materials(background: Rgb<f64>, materials: MaterialLibrary, objects: Vec<Object>,
+                          camera: StdBox<Camera>) -> Scene {
+        let material_ids = material_ids(&objects);
+        Scene { bg: background, objects: objects, materials: materials, material_ids: material_ids,
+                camera: camera }
     }
 
     pub fn background(&self) -> Rgb<f64> {
@@ -31,6 +189,23 @@ impl Scene {
         &self.camera
     }
 
+    pub fn materials(&self) -> &MaterialLibrary {
+        &self.materials
+    }
+
+    /// Replaces the material with the given name in the library and in every
+    /// object using it, or adds it to the library if there is none.
+    pub fn set_material(&mut self, name: &str, material: Arc<Material>) {
+        if let Some(old) = self.materials.get(name) {
+            let mut replaced = vec!();
+            for object in self.objects.iter_mut() {
+                object.replace_material(&old, &material, &mut replaced);
+            }
+        }
+        self.materials.add(name, material);
+        self.material_ids = material_ids(&self.objects);
+    }
+
     pub fn objects(&self) -> Vec<&Object> {
         self.objects.iter().collect()
     }
@@ -42,21 +217,94 @@ impl Scene {
     pub fn lights(&self) -> Vec<&Light> {
         self.objects.iter().filter_map(|o| o.as_light()).collect()
     }
+
+    /// Returns the lights with the identifiers of their objects, against
+    /// which the `object_id` of intersections can be checked.
+    pub fn lights_with_ids(&self) -> Vec<(u32, &Light)> {
+        self.objects.iter().enumerate().filter_map(|(i, o)| o.as_light().map(|l| (i as u32 + 1, l))).collect()
+    }
+
+    /// Returns the object with the given identifier.
+    pub fn object(&self, id: u32) -> Option<&Object> {
+        if id == 0 { None } else { self.objects.get(id as usize - 1) }
+    }
+
+    /// Returns a hash of everything in the scene, stable across runs and
+    /// builds, to tell whether saved renders come from the same scene.
+    pub fn hash(&self) -> u64 {
+        let mut hasher = SceneHasher::new();
+        hasher.write_rgb(&self.bg);
+        hasher.write_u64(self.objects.len() as u64);
+        for object in self.objects.iter() {
+            match *object {
+                Object::Surface(ref s) => {
+                    hasher.write_u64(0);
+                    hasher.write_surface(s);
+                }
+                Object::Light(ref l) => {
+                    hasher.write_u64(1);
+                    hasher.write_face(&l.face);
+                    hasher.write_rgb(&l.material.diffuse_intensity);
+                    hasher.write_rgb(&l.material.specular_intensity);
+                }
+            }
+        }
+        hasher.write_camera(&**self.camera);
+        hasher.0
+    }
+
+    fn object_index(&self, object: &Object) -> Option<usize> {
+        self.objects.iter().position(|o| o as *const Object == object as *const Object)
+    }
+
+    /// Returns the identifier of an object of the scene, which is its index
+    /// plus one, or 0 if the object is not part of the scene. Identifiers
+    /// never change as objects cannot be added or removed once the scene is
+    /// built. Intersections with the scene carry the identifier of the
+    /// object hit, which is cheaper than looking it up.
+    pub fn object_id(&self, object: &Object) -> u32 {
+        self.object_index(object).map_or(0, |i| i as u32 + 1)
+    }
+
+    /// Returns the identifier of the material of an object of the scene,
+    /// shared by the objects using the same material handle, or 0 if the
+    /// object is not part of the scene.
+    pub fn material_id(&self, object: &Object) -> u32 {
+        self.object_index(object).map_or(0, |_| self.material_handle_id(object.material()))
+    }
+
+    /// Returns the identifier of a material handle, which is the one of the
+    /// objects using it, or 0 if no object of the scene uses it. Box faces
+    /// with their own material get its identifier, so the material of an
+    /// intersection tells the material hit.
+    pub fn material_handle_id(&self, material: &Arc<Material>) -> u32 {
+        self.material_ids.get(&handle_key(material)).cloned().unwrap_or(0)
+    }
 }
 
 impl Intersectable for Scene {
     fn intersects(&self, ray: Ray) -> Option<Intersection> {
-        let intersections = filter_nones(self.objects.iter().map(|o| o.intersects(ray.clone())).collect());
+        let intersections = filter_nones(self.objects.iter().enumerate().map(|(i, o)| {
+            o.intersects(ray.clone()).map(|mut intersection| {
+                intersection.object_id = i as u32 + 1;
+                intersection
+            })
+        }).collect());
         closest_intersection(intersections)
     }
+
+    fn occluded(&self, ray: Ray) -> bool {
+        self.objects.iter().any(|o| o.occluded(ray.clone()))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use camera::Orthographic;
+    use material::{LightMaterial, Phong};
     use nalgebra::*;
-    use num_traits::Zero;
+    use num_traits::{One, Zero};
 
     #[test]
     fn test_new_scene() {
@@ -66,4 +314,124 @@ mod tests {
         let scene = Scene::new(c, vec!(), StdBox::new(cam));
         assert!(scene.background() == c);
     }
+
+    #[test]
+    fn test_object_and_material_ids() {
+        let grey = Rgb { data: [0.5, 0.5, 0.5] };
+        let red = Rgb { data: [1., 0., 0.] };
+        let material = |color| -> Arc<Material> { Arc::new(Phong::new(color, color, color, 1.)) };
+        let face = |material: &Arc<Material>| Object::from_surface(Surface::from_face(
+            Face::new(1., 1., Isometry3::one(), material.clone())));
+        let (grey_material, red_material, top_material) = (material(grey), material(red), material(red));
+        let cam = Orthographic::new((8, 8), (1., 1.), Isometry3::one());
+        let b = Box::with_face_materials(Vector3::new(1., 1., 1.), Isometry3::one(), grey_material.clone(),
+                                         &[(BoxFace::Top, top_material.clone())]);
+        // The fourth face looks the same as the first one but has its own material
+        let objects = vec!(face(&grey_material), face(&red_material), face(&grey_material), face(&material(grey)),
+                           Object::from_surface(Surface::from_box(b)));
+        let scene = Scene::new(grey, objects, StdBox::new(cam));
+        let objects = scene.objects();
+        assert!(objects.iter().map(|o| scene.object_id(o)).collect::<Vec<_>>() == vec!(1, 2, 3, 4, 5));
+        assert!(objects.iter().map(|o| scene.material_id(o)).collect::<Vec<_>>() == vec!(1, 2, 1, 3, 1));
+        assert!(scene.material_handle_id(&top_material) == 4 && scene.material_handle_id(&material(red)) == 0);
+        assert!(scene.object_id(&objects[0].clone()) == 0);
+        assert!(scene.object(2).map(|o| o as *const Object) == Some(objects[1] as *const Object));
+        assert!(scene.object(0).is_none() && scene.object(6).is_none());
+    }
+
+    #[test]
+    fn test_intersection_ids() {
+        let grey = Rgb { data: [0.5, 0.5, 0.5] };
+        let material = Arc::new(Phong::new(grey, grey, grey, 1.));
+        let face = |z: f64| Face::new(1., 1., Isometry3::new(Vector3::z() * z, Vector3::zero()), material.clone());
+        let light = Light::new(face(-2.), LightMaterial::new(grey, grey));
+        let objects = vec!(Object::from_surface(Surface::from_face(face(-3.))), Object::from_light(light.clone()),
+                           Object::from_light(light));
+        let cam = Orthographic::new((8, 8), (1., 1.), Isometry3::one());
+        let scene = Scene::new(grey, objects, StdBox::new(cam));
+        let ids: Vec<_> = scene.lights_with_ids().iter().map(|&(id, _)| id).collect();
+        assert!(ids == vec!(2, 3));
+        // Both lights are equal, only the first one in the scene is hit
+        let hit = scene.intersects(Ray::new(Point3::new(0., 0., 0.), -Vector3::z())).unwrap();
+        assert!(hit.object_id == 2 && scene.object_id(hit.object) == 2);
+        let ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
+        assert!(scene.object(1).unwrap().intersects(ray).unwrap().object_id == 0);
+    }
+
+    #[test]
+    fn test_occluded() {
+        let grey = Rgb { data: [0.5, 0.5, 0.5] };
+        let material = Arc::new(Phong::new(grey, grey, grey, 1.));
+        let face = |z: f64| Face::new(1., 1., Isometry3::new(Vector3::z() * z, Vector3::zero()), material.clone());
+        let objects = vec!(Object::from_surface(Surface::from_face(face(-3.))),
+                           Object::from_surface(Surface::from_face(face(-5.))));
+        let cam = Orthographic::new((8, 8), (1., 1.), Isometry3::one());
+        let scene = Scene::new(grey, objects, StdBox::new(cam));
+        let mut ray = Ray::new(Point3::new(0., 0., 0.), -Vector3::z());
+        assert!(scene.occluded(ray.clone()));
+        ray.t_max = 2.;
+        assert!(!scene.occluded(ray.clone()));
+        ray.t_min = 4.;
+        ray.t_max = 6.;
+        assert!(scene.occluded(ray.clone()));
+        assert!(scene.intersects(ray).unwrap().position.approx_eq(&Point3::new(0., 0., -5.)));
+        // Shadow rays stop short of their target
+        let blocked = Ray::shadow(Point3::new(0., 0., 0.), Vector3::z(), Point3::new(0., 0., -5.), 0.);
+        let to_target = Ray::shadow(Point3::new(0., 0., 0.), Vector3::z(), Point3::new(0., 0., -3.), 0.);
+        assert!(scene.occluded(blocked) && !scene.occluded(to_target));
+    }
+
+    #[test]
+    fn test_set_material() {
+        let grey = Rgb { data: [0.5, 0.5, 0.5] };
+        let red = Rgb { data: [1., 0., 0.] };
+        let mut materials = MaterialLibrary::new();
+        let wall = materials.add("wall", Arc::new(Phong::new(grey, grey, grey, 1.)));
+        let floor = materials.add("floor", Arc::new(Phong::new(grey, grey, grey, 1.)));
+        let panel = Arc::new(Surface::from_face(Face::new(1., 1., Isometry3::one(), wall.clone())));
+        let instance = |x: f64| Object::from_surface(Surface::from_instance(
+            Instance::new(panel.clone(), Isometry3::new(Vector3::x() * x, Vector3::zero()))));
+        let objects = vec!(Object::from_surface(Surface::from_face(Face::new(1., 1., Isometry3::one(), wall))),
+                           Object::from_surface(Surface::from_box(Box::new(Vector3::new(1., 1., 1.), Isometry3::one(),
+                                                                           floor))),
+                           instance(2.), instance(4.));
+        let cam = Orthographic::new((8, 8), (1., 1.), Isometry3::one());
+        let mut scene = Scene::with_materials(grey, materials, objects, StdBox::new(cam));
+        let ids = |scene: &Scene| scene.objects().iter().map(|o| scene.material_id(o)).collect::<Vec<_>>();
+        assert!(ids(&scene) == vec!(1, 2, 1, 1));
+        scene.set_material("wall", Arc::new(Phong::new(red, red, red, 1.)));
+        let colors: Vec<_> = scene.objects().iter().map(|o| o.material().diffuse_color()).collect();
+        assert!(colors == vec!(red, grey, red, red));
+        assert!(ids(&scene) == vec!(1, 2, 1, 1));
+        match *scene.objects()[1] {
+            Object::Surface(Surface::Box(ref b)) => assert!(b.front.material.diffuse_color() == grey),
+            _ => panic!("not a box")
+        }
+        // The instances still share one surface, which is not the old one
+        let shared: Vec<_> = scene.objects()[2..].iter().map(|o| match **o {
+            Object::Surface(Surface::Instance(ref i)) => &*i.surface as *const Surface,
+            _ => panic!("not an instance")
+        }).collect();
+        assert!(shared[0] == shared[1] && shared[0] != &*panel as *const Surface);
+        assert!(panel.material().diffuse_color() == grey);
+        assert!(scene.materials().name(scene.objects()[0].material()) == Some("wall"));
+    }
+
+    #[test]
+    fn test_scene_hash() {
+        let scene = |background: f64, width: u32, z: f64, shininess: f64| {
+            let grey = Rgb { data: [0.5, 0.5, 0.5] };
+            let material = Arc::new(Phong::new(grey, grey, grey, shininess));
+            let face = Face::new(1., 1., Isometry3::new(Vector3::z() * z, Vector3::zero()), material);
+            let cam = Orthographic::new((width, 8), (1., 1.), Isometry3::one());
+            Scene::new(Rgb { data: [background; 3] }, vec!(Object::from_surface(Surface::from_face(face))),
+                       StdBox::new(cam))
+        };
+        let hash = scene(0.3, 8, -3., 1.).hash();
+        assert!(hash == scene(0.3, 8, -3., 1.).hash());
+        assert!(hash != scene(0.4, 8, -3., 1.).hash());
+        assert!(hash != scene(0.3, 16, -3., 1.).hash());
+        assert!(hash != scene(0.3, 8, -4., 1.).hash());
+        assert!(hash != scene(0.3, 8, -3., 2.).hash());
+    }
 }
@@ -0,0 +1,198 @@
+//! Hierarchy of groups placing objects relative to each other, so that an
+//! assembly of objects can be moved or repeated as a whole. The hierarchy is
+//! flattened into objects placed in the world when building a `Scene`.
+
+use image::Rgb;
+use nalgebra::*;
+use num_traits::One;
+use std::boxed::Box as StdBox;
+use std::sync::Arc;
+
+use camera::Camera;
+use objects::{self, Object, Surface};
+use scene::Scene;
+
+/// Node of a scene graph, placed in the frame of the group holding it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Object placed by its own transform.
+    Object(Object),
+    Group(Group),
+    /// Group shared by several nodes, each placing a copy of it. The surfaces
+    /// of the copies are shared, see `Group::flatten`.
+    Instance(Instance)
+}
+
+/// Nodes placed together by `transform`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub transform: Isometry3<f64>,
+    pub children: Vec<Node>
+}
+
+/// Copy of a shared group, placed by `transform` applied after the transform
+/// of the group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub group: Arc<Group>,
+    pub transform: Isometry3<f64>
+}
+
+impl Node {
+    pub fn from_object(object: Object) -> Node {
+        Node::Object(object)
+    }
+
+    pub fn from_group(group: Group) -> Node {
+        Node::Group(group)
+    }
+
+    pub fn instance(group: Arc<Group>, transform: Isometry3<f64>) -> Node {
+        Node::Instance(Instance { group: group, transform: transform })
+    }
+}
+
+impl Group {
+    pub fn new(transform: Isometry3<f64>) -> Group {
+        Group { transform: transform, children: vec!() }
+    }
+
+    pub fn with_children(transform: Isometry3<f64>, children: Vec<Node>) -> Group {
+        Group { transform: transform, children: children }
+    }
+
+    pub fn add(&mut self, node: Node) {
+        self.children.push(node);
+    }
+
+    pub fn add_object(&mut self, object: Object) {
+        self.children.push(Node::Object(object));
+    }
+
+    /// Returns the objects of the group and of its descendants placed in the
+    /// frame of its parent. The surfaces of a group placed by instances are
+    /// shared by all of them through `objects::Instance`, each instance only
+    /// holding its transform. Lights cannot be shared and get their own
+    /// copies.
+    pub fn flatten(&self) -> Vec<Object> {
+        let mut objects = vec!();
+        self.flatten_into(&Isometry3::one(), &mut objects, &mut vec!());
+        objects
+    }
+
+    /// Flattens the group, `shared` holding the objects of the groups placed
+    /// by instances, flattened once and with their surfaces shared.
+    fn flatten_into(&self, parent: &Isometry3<f64>, objects: &mut Vec<Object>,
+                    shared: &mut Vec<(Arc<Group>, Vec<Object>)>) {
+        let transform = *parent * self.transform;
+        for child in self.children.iter() {
+            match *child {
+                Node::Object(ref object) => objects.push(object.transformed(&transform)),
+                Node::Group(ref group) => group.flatten_into(&transform, objects, shared),
+                Node::Instance(ref instance) => {
+                    let group = &*instance.group as *const Group;
+                    let known = shared.iter().position(|&(ref g, _)| &**g as *const Group == group);
+                    let k = match known {
+                        Some(k) => k,
+                        None => {
+                            let shared_objects = instance.group.shared_objects(shared);
+                            shared.push((instance.group.clone(), shared_objects));
+                            shared.len() - 1
+                        }
+                    };
+                    let placement = transform * instance.transform;
+                    objects.extend(shared[k].1.iter().map(|o| o.transformed(&placement)));
+                }
+            }
+        }
+    }
+
+    /// Returns the objects of the group placed in the frame of its parent,
+    /// boxes and faces being wrapped in instances so that placing them again
+    /// shares them.
+    fn shared_objects(&self, shared: &mut Vec<(Arc<Group>, Vec<Object>)>) -> Vec<Object> {
+        let mut objects = vec!();
+        self.flatten_into(&Isometry3::one(), &mut objects, shared);
+        objects.into_iter().map(|object| match object {
+            Object::Surface(Surface::Instance(instance)) => Object::Surface(Surface::Instance(instance)),
+            Object::Surface(surface) => {
+                let instance = objects::Instance::new(Arc::new(surface), Isometry3::one());
+                Object::Surface(Surface::Instance(instance))
+            }
+            light => light
+        }).collect()
+    }
+}
+
+impl Scene {
+    /// Builds a scene from the objects of a scene graph.
+    pub fn from_graph(background: Rgb<f64>, root: &Group, camera: StdBox<Camera>) -> Scene {
+        Scene::new(background, root.flatten(), camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use camera::Orthographic;
+    use material::Simple;
+    use num_traits::Zero;
+    use objects::{self, Face};
+    use std::f64::consts::PI;
+
+    fn material() -> Arc<Simple> {
+        Arc::new(Simple::new(Rgb { data: [1., 0., 0.] }))
+    }
+
+    fn face(transform: Isometry3<f64>) -> Object {
+        Object::from_surface(Surface::from_face(Face::new(1., 1., transform, material())))
+    }
+
+    fn cube(transform: Isometry3<f64>) -> Object {
+        Object::from_surface(Surface::from_box(objects::Box::new(Vector3::new(1., 2., 1.), transform, material())))
+    }
+
+    #[test]
+    fn test_nested_transforms() {
+        let inner_transform = Isometry3::new(Vector3::new(0., 1., 0.), Vector3::y() * (PI / 2.));
+        let inner = Group::with_children(inner_transform, vec!(Node::from_object(cube(Isometry3::one()))));
+        let outer_transform = Isometry3::new(Vector3::new(3., 0., -2.), Vector3::x() * 0.3);
+        let mut outer = Group::new(outer_transform);
+        outer.add(Node::from_group(inner));
+        outer.add_object(face(Isometry3::new(Vector3::z(), Vector3::zero())));
+        let objects = outer.flatten();
+        assert!(objects.len() == 2);
+        assert!(objects[0] == cube(outer_transform * inner_transform));
+        assert!(objects[1] == face(outer_transform * Isometry3::new(Vector3::z(), Vector3::zero())));
+    }
+
+    #[test]
+    fn test_instances() {
+        let chair = Arc::new(Group::with_children(Isometry3::new(Vector3::y(), Vector3::zero()),
+                                                  vec!(Node::from_object(face(Isometry3::one())))));
+        let mut room = Group::new(Isometry3::one());
+        for x in 0..3 {
+            room.add(Node::instance(chair.clone(), Isometry3::new(Vector3::x() * x as f64, Vector3::zero())));
+        }
+        let objects = room.flatten();
+        assert!(objects.len() == 3);
+        let seat = face(Isometry3::new(Vector3::y(), Vector3::zero()));
+        let mut surfaces = vec!();
+        for (x, object) in objects.iter().enumerate() {
+            match *object {
+                Object::Surface(Surface::Instance(ref i)) => {
+                    assert!(Some(&*i.surface) == seat.as_surface());
+                    let placement = Isometry3::new(Vector3::x() * x as f64, Vector3::zero());
+                    assert!(i.transform.approx_eq(&placement.into()));
+                    surfaces.push(&*i.surface as *const Surface);
+                }
+                _ => panic!("not an instance")
+            }
+        }
+        // The chair was flattened once and its face is shared
+        assert!(surfaces.iter().all(|s| *s == surfaces[0]));
+        let camera = Orthographic::new((8, 8), (1., 1.), Isometry3::one());
+        let scene = Scene::from_graph(Rgb { data: [0.; 3] }, &room, StdBox::new(camera));
+        assert!(scene.objects().len() == 3);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{self, BufRead, Write};
+
+use io::bytes::invalid_data;
+
+/// Largest request body accepted, scenes being far smaller.
+const MAX_BODY_SIZE: usize = 64 << 20;
+
+/// Longest request line or header accepted.
+const MAX_LINE_LENGTH: usize = 8 << 10;
+
+/// Most headers accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// Why a request could not be read.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection failed or the request is malformed.
+    Io(io::Error),
+    /// The body is larger than accepted.
+    TooLarge
+}
+
+/// HTTP request, without its headers. `path` has no query string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> RequestError {
+        RequestError::Io(e)
+    }
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str, body: &[u8]) -> Request {
+        Request { method: method.to_string(), path: path.to_string(), body: body.to_vec() }
+    }
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Response {
+        Response { status: status, content_type: content_type, body: body }
+    }
+
+    pub fn json(status: u16, body: String) -> Response {
+        Response::new(status, "application/json", body.into_bytes())
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error"
+    }
+}
+
+/// Reads a line of the request head, failing on lines too long rather than
+/// buffering them whole.
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    r.by_ref().take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line)?;
+    match line.len() > MAX_LINE_LENGTH {
+        true => Err(invalid_data("request line or header too long")),
+        false => Ok(line)
+    }
+}
+
+/// Reads a HTTP/1.x request, whose body is sized by its `Content-Length`.
+pub fn read_request<R: BufRead>(r: &mut R) -> Result<Request, RequestError> {
+    let line = read_line(r)?;
+    let (method, path) = {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.split('?').next().unwrap().to_string()),
+            _ => return Err(invalid_data("malformed request line").into())
+        }
+    };
+    let mut length = 0;
+    for n_headers in 0.. {
+        let header = read_line(r)?;
+        if header.is_empty() {
+            return Err(invalid_data("truncated request headers").into());
+        }
+        let header = header.trim_right();
+        if header.is_empty() {
+            break;
+        }
+        if n_headers == MAX_HEADERS {
+            return Err(invalid_data("too many request headers").into());
+        }
+        if let Some(colon) = header.find(':') {
+            if header[..colon].trim().to_lowercase() == "content-length" {
+                length = header[colon + 1..].trim().parse()
+                                            .map_err(|_| invalid_data("invalid content length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY_SIZE {
+        return Err(RequestError::TooLarge);
+    }
+    let mut body = vec![0; length];
+    r.read_exact(&mut body)?;
+    Ok(Request { method: method, path: path, body: body })
+}
+
+/// Writes a response, closing the connection after it.
+pub fn write_response<W: Write>(w: &mut W, response: &Response) -> io::Result<()> {
+    write!(w, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+           response.status, reason_phrase(response.status), response.content_type, response.body.len())?;
+    w.write_all(&response.body)?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /jobs?verbose=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 4\r\n\r\n{}\r\nextra";
+        let request = read_request(&mut Cursor::new(&raw[..])).unwrap();
+        assert!(request == Request::new("POST", "/jobs", b"{}\r\n"));
+        assert!(read_request(&mut Cursor::new(&b"GET\r\n\r\n"[..])).is_err());
+        assert!(read_request(&mut Cursor::new(&b"GET / HTTP/1.1\r\nHost: x\r\n"[..])).is_err());
+        let long = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", "x".repeat(MAX_LINE_LENGTH));
+        assert!(read_request(&mut Cursor::new(long.as_bytes())).is_err());
+        let large = format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        match read_request(&mut Cursor::new(large.as_bytes())) {
+            Err(RequestError::TooLarge) => (),
+            r => panic!("unexpected {:?}", r)
+        }
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = vec!();
+        write_response(&mut out, &Response::json(404, "{}".to_string())).unwrap();
+        let expected = "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\
+                        Connection: close\r\n\r\n{}";
+        assert!(out == expected.as_bytes());
+    }
+}
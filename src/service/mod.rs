@@ -0,0 +1,10 @@
+//! Rendering service: an HTTP API through which other programs submit scenes
+//! to a queue of renders, follow their progress and fetch the images.
+
+mod http;
+mod queue;
+mod server;
+
+pub use self::http::*;
+pub use self::queue::*;
+pub use self::server::*;
@@ -0,0 +1,313 @@
+use std::boxed::Box as StdBox;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use distributed::Job;
+use film::Film;
+use raytracer::{CancellationToken, Jittered, ParallelRenderer, Progress, RenderEvent, Simple};
+
+/// Stage of a job in a `RenderQueue`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    /// Cancelled while queued, or while rendering in which case the film
+    /// holds what was rendered.
+    Cancelled,
+    Failed(String)
+}
+
+/// What a `RenderQueue` knows of a job.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobState {
+    pub status: JobStatus,
+    /// Progress of the render, from the events of the renderer.
+    pub progress: Option<Progress>
+}
+
+struct QueuedJob {
+    job: Job,
+    state: JobState,
+    cancellation: CancellationToken,
+    film: Option<Arc<Film>>
+}
+
+struct QueueState {
+    /// Jobs submitted and not removed, by id.
+    jobs: BTreeMap<usize, QueuedJob>,
+    /// Id of the next job submitted, ids never being reused.
+    next_id: usize,
+    pending: VecDeque<usize>
+}
+
+struct Shared {
+    state: Mutex<QueueState>,
+    job_available: Condvar
+}
+
+/// Largest jobs a `RenderQueue` accepts, so that a single job cannot make it
+/// allocate or render without bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JobLimits {
+    /// Pixels of the image.
+    pub max_pixels: u64,
+    /// Samples over all the pixels, counting the maximum of adaptive jobs.
+    pub max_samples: u64
+}
+
+/// Renders the jobs submitted to it in order, a given number of them at a
+/// time. Jobs and their films are kept until removed once finished.
+#[derive(Clone)]
+pub struct RenderQueue {
+    shared: Arc<Shared>,
+    limits: JobLimits
+}
+
+impl JobLimits {
+    pub fn new(max_pixels: u64, max_samples: u64) -> JobLimits {
+        JobLimits { max_pixels: max_pixels, max_samples: max_samples }
+    }
+
+    /// Returns why a job is too large, if it is.
+    fn check(&self, job: &Job) -> Result<(), String> {
+        let (width, height) = job.scene.camera.resolution();
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(format!("{}x{} image above the limit of {} pixels", width, height, self.max_pixels));
+        }
+        let per_pixel = job.render.adaptive.map_or(job.render.n_samples, |(_, max, _)| max);
+        match pixels.checked_mul(per_pixel as u64) {
+            Some(samples) if samples <= self.max_samples => Ok(()),
+            _ => Err(format!("{} samples per pixel above the limit of {} samples", per_pixel, self.max_samples))
+        }
+    }
+}
+
+impl Default for JobLimits {
+    /// A 4096x4096 image, with 256 samples per pixel.
+    fn default() -> JobLimits {
+        JobLimits::new(1 << 24, 1 << 32)
+    }
+}
+
+impl JobStatus {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Cancelled => "cancelled",
+            JobStatus::Failed(_) => "failed"
+        }
+    }
+
+    /// Returns whether the job will not change anymore.
+    pub fn is_finished(&self) -> bool {
+        match *self {
+            JobStatus::Queued | JobStatus::Running => false,
+            _ => true
+        }
+    }
+}
+
+/// Renders a job, updating its progress from the events of the renderer.
+fn render_job(shared: &Arc<Shared>, id: usize, job: &Job, cancellation: CancellationToken) -> Result<Film, String> {
+    let mut settings = job.render.build().map_err(|e| format!("invalid render settings: {}", e))?;
+    let observed = shared.clone();
+    settings.observer = Some(StdBox::new(move |event: &RenderEvent| {
+        if let Some(queued) = observed.state.lock().unwrap().jobs.get_mut(&id) {
+            queued.state.progress = Some(*event.progress());
+        }
+    }));
+    settings.cancellation = cancellation;
+    let scene = job.scene.build().map_err(|e| format!("unknown material or invalid scene: {}", e))?;
+    let sampler = Jittered::with_seed(job.render.seed);
+    panic::catch_unwind(AssertUnwindSafe(|| Simple::new(scene, settings, sampler).render_parallel()))
+        .map_err(|_| "the renderer panicked".to_string())
+}
+
+fn run_jobs(shared: Arc<Shared>) {
+    loop {
+        let (id, job, cancellation) = {
+            let mut state = shared.state.lock().unwrap();
+            while state.pending.is_empty() {
+                state = shared.job_available.wait(state).unwrap();
+            }
+            let id = state.pending.pop_front().unwrap();
+            let queued = state.jobs.get_mut(&id).unwrap();
+            queued.state.status = JobStatus::Running;
+            (id, queued.job.clone(), queued.cancellation.clone())
+        };
+        let film = render_job(&shared, id, &job, cancellation.clone());
+        let mut state = shared.state.lock().unwrap();
+        let queued = state.jobs.get_mut(&id).unwrap();
+        match film {
+            Ok(film) => {
+                queued.state.status = if cancellation.is_cancelled() { JobStatus::Cancelled } else { JobStatus::Done };
+                queued.film = Some(Arc::new(film));
+            }
+            Err(e) => queued.state.status = JobStatus::Failed(e)
+        }
+    }
+}
+
+impl RenderQueue {
+    /// Starts a queue rendering `concurrency` jobs at a time, which share the
+    /// threads of the renderer, and rejecting jobs above `limits`.
+    pub fn new(concurrency: u32, limits: JobLimits) -> RenderQueue {
+        let shared = Arc::new(Shared { state: Mutex::new(QueueState { jobs: BTreeMap::new(), next_id: 0,
+                                                                       pending: VecDeque::new() }),
+                                       job_available: Condvar::new() });
+        for _ in 0..concurrency.max(1) {
+            let shared = shared.clone();
+            thread::spawn(move || run_jobs(shared));
+        }
+        RenderQueue { shared: shared, limits: limits }
+    }
+
+    /// Queues a job and returns its id. Jobs with unknown render settings or
+    /// materials are rejected with the unknown name, jobs with invalid ones
+    /// or above the limits of the queue with what is wrong.
+    pub fn submit(&self, job: Job) -> Result<u32, String> {
+        job.render.build()?;
+        self.limits.check(&job)?;
+        job.scene.build()?;
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.jobs.insert(id, QueuedJob { job: job, state: JobState { status: JobStatus::Queued, progress: None },
+                                          cancellation: CancellationToken::new(), film: None });
+        state.pending.push_back(id);
+        self.shared.job_available.notify_one();
+        Ok(id as u32)
+    }
+
+    pub fn state(&self, id: u32) -> Option<JobState> {
+        self.shared.state.lock().unwrap().jobs.get(&(id as usize)).map(|j| j.state.clone())
+    }
+
+    /// Returns the ids and states of all the jobs.
+    pub fn jobs(&self) -> Vec<(u32, JobState)> {
+        let state = self.shared.state.lock().unwrap();
+        state.jobs.iter().map(|(&id, j)| (id as u32, j.state.clone())).collect()
+    }
+
+    /// Returns the film of a job once rendered or cancelled while rendering.
+    pub fn film(&self, id: u32) -> Option<Arc<Film>> {
+        self.shared.state.lock().unwrap().jobs.get(&(id as usize)).and_then(|j| j.film.clone())
+    }
+
+    /// Cancels a job, dropping it from the queue if it was not started.
+    /// Returns false for unknown jobs.
+    pub fn cancel(&self, id: u32) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = id as usize;
+        let queued = match state.jobs.get(&id).map(|j| j.state.status == JobStatus::Queued) {
+            Some(queued) => queued,
+            None => return false
+        };
+        if queued {
+            state.pending.retain(|&j| j != id);
+        }
+        let job = state.jobs.get_mut(&id).unwrap();
+        if queued {
+            job.state.status = JobStatus::Cancelled;
+        }
+        job.cancellation.cancel();
+        true
+    }
+
+    /// Removes a finished job along with its film. Returns false for unknown
+    /// jobs and jobs still queued or rendering.
+    pub fn remove(&self, id: u32) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = id as usize;
+        let finished = state.jobs.get(&id).map_or(false, |j| j.state.status.is_finished());
+        if finished {
+            state.jobs.remove(&id);
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use description::*;
+    use std::time::Duration;
+
+    fn test_job(size: u32, n_samples: u32) -> Job {
+        Job { scene: test_scene([size, size]), render: RenderDescription::new(n_samples, 1) }
+    }
+
+    fn wait_until_finished(queue: &RenderQueue, id: u32) -> JobState {
+        loop {
+            let state = queue.state(id).unwrap();
+            if state.status != JobStatus::Queued && state.status != JobStatus::Running {
+                return state;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_queue_renders_jobs() {
+        let queue = RenderQueue::new(2, JobLimits::default());
+        let ids: Vec<u32> = (0..3).map(|_| queue.submit(test_job(8, 2)).unwrap()).collect();
+        assert!(ids == vec!(0, 1, 2));
+        for &id in ids.iter() {
+            let state = wait_until_finished(&queue, id);
+            assert!(state.status == JobStatus::Done);
+            assert!(state.progress.unwrap().fraction == 1.);
+            assert!(queue.film(id).unwrap().resolution() == (8, 8));
+        }
+        let mut job = test_job(8, 2);
+        job.render.filter = "sinc".to_string();
+        assert!(queue.submit(job) == Err("sinc".to_string()));
+    }
+
+    #[test]
+    fn test_job_limits() {
+        let queue = RenderQueue::new(1, JobLimits::new(100, 1000));
+        assert!(queue.submit(test_job(8, 2)).is_ok());
+        assert!(queue.submit(test_job(16, 2)).is_err());
+        assert!(queue.submit(test_job(8, 100)).is_err());
+        let mut job = test_job(8, 2);
+        job.render.adaptive = Some((2, 100, 0.1));
+        assert!(queue.submit(job).is_err());
+        let huge = test_job(u32::max_value(), 2);
+        assert!(JobLimits::new(u64::max_value(), u64::max_value()).check(&huge).is_err());
+    }
+
+    #[test]
+    fn test_cancel_jobs() {
+        let queue = RenderQueue::new(1, JobLimits::default());
+        let running = queue.submit(test_job(64, 10000)).unwrap();
+        let queued = queue.submit(test_job(8, 2)).unwrap();
+        while queue.state(running).unwrap().status == JobStatus::Queued {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(queue.cancel(queued) && queue.cancel(running));
+        assert!(!queue.cancel(5));
+        assert!(wait_until_finished(&queue, running).status == JobStatus::Cancelled);
+        assert!(queue.film(running).is_some());
+        assert!(queue.state(queued).unwrap().status == JobStatus::Cancelled);
+        assert!(queue.film(queued).is_none());
+    }
+
+    #[test]
+    fn test_remove_jobs() {
+        let queue = RenderQueue::new(1, JobLimits::default());
+        let running = queue.submit(test_job(64, 10000)).unwrap();
+        assert!(!queue.remove(running));
+        queue.cancel(running);
+        wait_until_finished(&queue, running);
+        assert!(queue.remove(running) && !queue.remove(running));
+        assert!(queue.state(running).is_none() && queue.film(running).is_none());
+        assert!(queue.submit(test_job(8, 2)).unwrap() == running + 1);
+        assert!(queue.jobs().len() == 1);
+    }
+}
@@ -0,0 +1,269 @@
+use image::{self, png};
+use rustc_serialize::json;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str;
+use std::thread;
+use std::time::Duration;
+
+use distributed::Job;
+use film::{Film, ToneMapOperator, ToneMapping};
+use io::{write_exr, FloatImage, PixelType};
+use service::http::*;
+use service::queue::*;
+use util::duration_as_secs_f64;
+
+/// Job as reported by the API.
+#[derive(Debug, RustcEncodable)]
+struct JobReport {
+    id: u32,
+    status: String,
+    error: Option<String>,
+    /// Fraction of the render done, in [0, 1].
+    progress: f64,
+    rays: u64,
+    elapsed: f64,
+    eta: Option<f64>
+}
+
+#[derive(Debug, RustcEncodable)]
+struct ErrorReport {
+    error: String
+}
+
+/// HTTP API submitting jobs to a render queue:
+///
+/// - `POST /jobs` queues the job given as JSON, a scene and its render
+///   settings, and answers its id;
+/// - `GET /jobs` lists the jobs and `GET /jobs/<id>` reports one, with its
+///   status and progress;
+/// - `GET /jobs/<id>/image.png` and `GET /jobs/<id>/image.exr` fetch the
+///   render once done, tone mapped or linear;
+/// - `DELETE /jobs/<id>` cancels a job, or once finished removes it along
+///   with its image.
+pub struct RenderServer {
+    listener: TcpListener,
+    queue: RenderQueue
+}
+
+impl JobReport {
+    fn new(id: u32, state: &JobState) -> JobReport {
+        let error = match state.status {
+            JobStatus::Failed(ref e) => Some(e.clone()),
+            _ => None
+        };
+        let progress = state.progress.as_ref();
+        JobReport { id: id, status: state.status.name().to_string(), error: error,
+                    progress: progress.map_or(0., |p| p.fraction), rays: progress.map_or(0, |p| p.rays),
+                    elapsed: progress.map_or(0., |p| duration_as_secs_f64(p.elapsed)),
+                    eta: progress.and_then(|p| p.eta).map(duration_as_secs_f64) }
+    }
+}
+
+/// Time a client may stall sending its request or reading the response.
+const CLIENT_TIMEOUT_SECS: u64 = 30;
+
+/// Time to wait after failing to accept a client, e.g. out of descriptors.
+const ACCEPT_RETRY_MS: u64 = 100;
+
+fn error(status: u16, message: &str) -> Response {
+    Response::json(status, json::encode(&ErrorReport { error: message.to_string() }).unwrap())
+}
+
+fn submit(queue: &RenderQueue, body: &[u8]) -> Response {
+    let job = match str::from_utf8(body).ok().map(json::decode::<Job>) {
+        Some(Ok(job)) => job,
+        Some(Err(e)) => return error(400, &format!("invalid job: {}", e)),
+        None => return error(400, "invalid job: not UTF-8")
+    };
+    match queue.submit(job) {
+        Ok(id) => {
+            let state = queue.state(id).unwrap();
+            Response::json(201, json::encode(&JobReport::new(id, &state)).unwrap())
+        }
+        Err(name) => error(400, &format!("unknown or invalid render settings or scene: {}", name))
+    }
+}
+
+fn encode_png(film: &Film) -> io::Result<Vec<u8>> {
+    let image = film.to_rgb_image(&ToneMapping::new(ToneMapOperator::Clamp, 0.));
+    let mut png = vec!();
+    png::PNGEncoder::new(&mut png).encode(&image, image.width(), image.height(), image::RGB(8))?;
+    Ok(png)
+}
+
+fn encode_exr(film: &Film) -> io::Result<Vec<u8>> {
+    let mut exr = vec!();
+    write_exr(&mut exr, &FloatImage::from_film(film), PixelType::Half)?;
+    Ok(exr)
+}
+
+fn image(queue: &RenderQueue, id: u32, name: &str) -> Response {
+    let encode: fn(&Film) -> io::Result<Vec<u8>> = match name {
+        "image.png" => encode_png,
+        "image.exr" => encode_exr,
+        _ => return error(404, "unknown image")
+    };
+    let content_type = if name == "image.png" { "image/png" } else { "image/x-exr" };
+    match queue.film(id) {
+        Some(film) => match encode(&film) {
+            Ok(data) => Response::new(200, content_type, data),
+            Err(e) => error(500, &format!("cannot encode the image: {}", e))
+        },
+        None => error(409, "the job is not rendered")
+    }
+}
+
+/// Answers a request of the API.
+pub fn handle_request(queue: &RenderQueue, request: &Request) -> Response {
+    let path: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    if path.first() != Some(&"jobs") || path.len() > 3 {
+        return error(404, "unknown resource");
+    }
+    let method = &request.method[..];
+    let id = match path.get(1).map(|id| id.parse::<u32>()) {
+        None => return match method {
+            "POST" => submit(queue, &request.body),
+            "GET" => {
+                let reports: Vec<_> = queue.jobs().iter().map(|&(id, ref state)| JobReport::new(id, state)).collect();
+                Response::json(200, json::encode(&reports).unwrap())
+            }
+            _ => error(405, "method not allowed")
+        },
+        Some(Ok(id)) if queue.state(id).is_some() => id,
+        Some(_) => return error(404, "unknown job")
+    };
+    match (method, path.get(2)) {
+        ("GET", None) => Response::json(200, json::encode(&JobReport::new(id, &queue.state(id).unwrap())).unwrap()),
+        ("DELETE", None) => {
+            let state = queue.state(id).unwrap();
+            if queue.remove(id) {
+                return Response::json(200, json::encode(&JobReport::new(id, &state)).unwrap());
+            }
+            queue.cancel(id);
+            Response::json(202, json::encode(&JobReport::new(id, &queue.state(id).unwrap())).unwrap())
+        }
+        ("GET", Some(name)) => image(queue, id, name),
+        (_, None) => error(405, "method not allowed"),
+        _ => error(404, "unknown resource")
+    }
+}
+
+fn serve_client(stream: TcpStream, queue: RenderQueue) {
+    let timeout = Some(Duration::from_secs(CLIENT_TIMEOUT_SECS));
+    if stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout)).is_err() {
+        return;
+    }
+    let response = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => handle_request(&queue, &request),
+        Err(RequestError::TooLarge) => error(413, "request body too large"),
+        Err(RequestError::Io(e)) => error(400, &e.to_string())
+    };
+    let _ = write_response(&mut &stream, &response);
+}
+
+impl RenderServer {
+    /// Listens on `address`, rendering `concurrency` jobs at a time and
+    /// rejecting jobs above `limits`.
+    pub fn bind<A: ToSocketAddrs>(address: A, concurrency: u32, limits: JobLimits) -> io::Result<RenderServer> {
+        Ok(RenderServer { listener: TcpListener::bind(address)?, queue: RenderQueue::new(concurrency, limits) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn queue(&self) -> &RenderQueue {
+        &self.queue
+    }
+
+    /// Serves the clients connecting, each on its own thread. Failing to
+    /// accept a client is logged and does not stop the server.
+    pub fn run(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let queue = self.queue.clone();
+                    thread::spawn(move || serve_client(stream, queue));
+                }
+                Err(e) => {
+                    println!("Cannot accept a client: {}", e);
+                    thread::sleep(Duration::from_millis(ACCEPT_RETRY_MS));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use description::*;
+    use std::collections::BTreeMap;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    fn job_json(filter: &str, n_samples: u32) -> String {
+        let camera = CameraDescription::Orthographic { resolution: [6, 4], plane_size: [2., 2.],
+                                                       transform: TransformDescription::new([0.; 3], [0.; 3]) };
+        let mut render = RenderDescription::new(n_samples, 0);
+        render.filter = filter.to_string();
+        json::encode(&Job { scene: SceneDescription { background: [0.5; 3], materials: BTreeMap::new(),
+                                                          objects: vec!(), camera: camera },
+                            render: render }).unwrap()
+    }
+
+    fn request(address: SocketAddr, raw: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_api() {
+        let queue = RenderQueue::new(1, JobLimits::default());
+        let get = |path: &str| handle_request(&queue, &Request::new("GET", path, b""));
+        assert!(handle_request(&queue, &Request::new("POST", "/jobs", job_json("sinc", 1).as_bytes())).status == 400);
+        assert!(handle_request(&queue, &Request::new("POST", "/jobs", b"{")).status == 400);
+        let created = handle_request(&queue, &Request::new("POST", "/jobs", job_json("box", 1).as_bytes()));
+        assert!(created.status == 201);
+        assert!(str::from_utf8(&created.body).unwrap().starts_with("{\"id\":0,"));
+        while queue.film(0).is_none() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let report = String::from_utf8(get("/jobs/0").body).unwrap();
+        assert!(report.contains("\"status\":\"done\"") && report.contains("\"progress\":1.0"));
+        let exr = get("/jobs/0/image.exr");
+        assert!(exr.status == 200 && exr.content_type == "image/x-exr");
+        assert!(::io::read_exr(&mut &exr.body[..]).unwrap().width == 6);
+        assert!(&get("/jobs/0/image.png").body[1..4] == b"PNG");
+        assert!(get("/jobs").status == 200);
+        assert!(get("/jobs/1").status == 404 && get("/jobs/0/image.jpg").status == 404);
+        assert!(handle_request(&queue, &Request::new("PUT", "/jobs/0", b"")).status == 405);
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let server = Arc::new(RenderServer::bind("127.0.0.1:0", 1, JobLimits::default()).unwrap());
+        let address = server.local_addr().unwrap();
+        let serving = server.clone();
+        thread::spawn(move || serving.run());
+        let job = job_json("box", 100000);
+        let response = request(address, &format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                                                 job.len(), job));
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        let response = request(address, "DELETE /jobs/0 HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 202 Accepted\r\n") && response.contains("\"id\":0"));
+        assert!(server.queue().state(0).unwrap().status != JobStatus::Queued);
+        while !server.queue().state(0).unwrap().status.is_finished() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let response = request(address, "DELETE /jobs/0 HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && server.queue().state(0).is_none());
+        let response = request(address, &format!("POST /jobs HTTP/1.1\r\nContent-Length: {}\r\n\r\n", 1 << 30));
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+}
@@ -1,6 +1,11 @@
 use image::*;
 use nalgebra::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
 use algebra::OrthoNormalBase;
+use std::time::Duration;
+
+/// Random number generator used for rendering.
+pub type RenderRng = XorShiftRng;
 
 pub fn rgb_01_to_255(pixel: &Rgb<f64>) -> Rgb<f64> {
     Rgb { data: [pixel[0] * 255., pixel[1] * 255., pixel[2] * 255.] }
@@ -35,13 +40,22 @@ pub fn rgb_clamp_0_1(r: &Rgb<f64>) -> Rgb<f64> {
     r.map(|c| clamp(c, 0., 1.))
 }
 
-pub fn random_in_cone(direction: Vector3<f64>, angle: f64) -> Vector3<f64> {
-    use rand::distributions::*;
-    use rand::*;
-    let range = Range::new(-angle, angle);
-    let mut rng = thread_rng();
-    let theta = range.ind_sample(&mut rng);
-    let phi = range.ind_sample(&mut rng);
+/// Relative luminance of a linear Rec. 709 color.
+pub fn rgb_luminance(r: &Rgb<f64>) -> f64 {
+    0.2126 * r[0] + 0.7152 * r[1] + 0.0722 * r[2]
+}
+
+pub fn random_in_cone<R: Rng>(direction: Vector3<f64>, angle: f64, rng: &mut R) -> Vector3<f64> {
+    let uv = (rng.gen::<f64>(), rng.gen::<f64>());
+    sample_in_cone(direction, angle, uv)
+}
+
+/// Maps `(u, v)` in [0, 1]² to a direction deviating at most `angle` from
+/// `direction` around each of its orthogonal axes.
+pub fn sample_in_cone(direction: Vector3<f64>, angle: f64, uv: (f64, f64)) -> Vector3<f64> {
+    let (u, v) = uv;
+    let theta = (2. * u - 1.) * angle;
+    let phi = (2. * v - 1.) * angle;
     let base = OrthoNormalBase::from_w(direction);
     let v = Rotation3::new(base.u() * theta).transform(&Rotation3::new(base.v() * phi).transform(&direction));
     v.normalize()
@@ -50,3 +64,41 @@ pub fn random_in_cone(direction: Vector3<f64>, angle: f64) -> Vector3<f64> {
 pub fn filter_nones<T>(v: Vec<Option<T>>) -> Vec<T> {
     v.into_iter().filter_map(|i| i).collect::<Vec<T>>()
 }
+
+/// Integer hash with good avalanche, used to derive decorrelated scrambles and
+/// seeds from pixel coordinates and indices.
+pub fn hash_u32(x: u32) -> u32 {
+    let mut h = x;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^= h >> 16;
+    h
+}
+
+/// Hashes a sequence of integers into one.
+pub fn hash_u32s(values: &[u32]) -> u32 {
+    values.iter().fold(0x9e3779b9, |h, v| hash_u32(h ^ hash_u32(*v)))
+}
+
+/// Returns a generator seeded from the given values, e.g. the render seed and a
+/// pixel position, so that results do not depend on scheduling.
+pub fn seeded_rng(values: &[u32]) -> RenderRng {
+    let h = hash_u32s(values);
+    let mut seed = [hash_u32(h), hash_u32(h ^ 1), hash_u32(h ^ 2), hash_u32(h ^ 3)];
+    if seed == [0; 4] {
+        seed[0] = 1;
+    }
+    XorShiftRng::from_seed(seed)
+}
+
+/// Converts a number of seconds to a duration, negative values giving 0.
+pub fn duration_from_secs_f64(seconds: f64) -> Duration {
+    let seconds = seconds.max(0.);
+    Duration::new(seconds.trunc() as u64, (seconds.fract() * 1e9) as u32)
+}
+
+pub fn duration_as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}